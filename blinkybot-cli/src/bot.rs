@@ -0,0 +1,152 @@
+//! Finding and connecting to bots.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use blinkybot_replay::RecordingClient;
+use blinkybot_rpc::{
+    serial_number, DeviceInfo, GetDeviceInfoEndpoint, SetTimeEndpoint, WallTime, USB_PRODUCT_ID,
+    USB_VENDOR_ID,
+};
+use chrono::Local;
+use postcard_rpc::{
+    host_client::HostClient,
+    standard_icd::{WireError, ERROR_PATH},
+};
+
+/// Which bot to use.
+pub enum Selector {
+    Serial(String),
+    Name(String),
+    /// The only bot connected.
+    Any,
+}
+
+/// Which bot to use and what to do with it besides running the command.
+pub struct Target {
+    pub selector: Selector,
+    /// File to save the session with the bot to, for `replay`.
+    pub record: Option<PathBuf>,
+}
+
+pub struct Bot {
+    pub client: RecordingClient,
+    pub info: DeviceInfo,
+    /// File to save the recorded session to when closing.
+    record: Option<PathBuf>,
+}
+
+impl Bot {
+    pub fn serial(&self) -> String {
+        serial_string(self.info.serial)
+    }
+
+    /// Disconnects, saving the recorded session if recording.
+    pub fn close(&self) -> Result<()> {
+        self.client.close();
+        if let (Some(file), Some(session)) = (&self.record, self.client.stop_recording()) {
+            fs::write(file, session.to_text())
+                .map_err(|e| anyhow!("failed to write {}: {e}", file.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `serial` as reported in the bot's USB serial number.
+pub fn serial_string(serial: u64) -> String {
+    String::from_utf8_lossy(&serial_number(serial)).into_owned()
+}
+
+/// Returns the USB serial numbers of the connected bots.
+pub fn serials() -> Result<Vec<String>> {
+    Ok(nusb::list_devices()?
+        .filter(|d| d.vendor_id() == USB_VENDOR_ID && d.product_id() == USB_PRODUCT_ID)
+        .filter_map(|d| d.serial_number().map(str::to_string))
+        .collect())
+}
+
+/// Returns the current time of the host.
+fn host_time() -> WallTime {
+    let unix_millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64);
+    let offset_secs = Local::now().offset().local_minus_utc();
+    WallTime::new(unix_millis, (offset_secs / 60) as i16)
+}
+
+/// Connects to the bot with USB serial number `serial`.
+pub async fn connect(serial: &str) -> Result<Bot> {
+    let client = HostClient::try_new_raw_nusb(
+        |d| {
+            d.vendor_id() == USB_VENDOR_ID
+                && d.product_id() == USB_PRODUCT_ID
+                && d.serial_number() == Some(serial)
+        },
+        ERROR_PATH,
+        8,
+    )
+    .map_err(|e| anyhow!("failed to connect to {serial}: {e}"))?;
+    let info = client
+        .send_resp::<GetDeviceInfoEndpoint>(&())
+        .await
+        .map_err(|e| anyhow!("failed to get device info from {serial}: {e:?}"))?;
+    // The bot has no clock of its own, so share ours on every connect.
+    client
+        .send_resp::<SetTimeEndpoint>(&host_time())
+        .await
+        .map_err(|e| anyhow!("failed to set the time on {serial}: {e:?}"))?;
+    Ok(Bot {
+        client: RecordingClient::new(client),
+        info,
+        record: None,
+    })
+}
+
+/// Connects to the bot picked by `target`, recording the session if asked
+/// to.
+pub async fn open(target: &Target) -> Result<Bot> {
+    let mut bot = select(&target.selector).await?;
+    if let Some(file) = &target.record {
+        bot.client.start_recording();
+        bot.record = Some(file.clone());
+    }
+    Ok(bot)
+}
+
+/// Connects to the bot picked by `selector`.
+async fn select(selector: &Selector) -> Result<Bot> {
+    let serials = serials()?;
+    match selector {
+        Selector::Serial(serial) => match serials.iter().find(|s| s.eq_ignore_ascii_case(serial)) {
+            Some(serial) => connect(serial).await,
+            None => bail!("no bot with serial number {serial}"),
+        },
+        // Names are only known to the bots themselves so ask each in turn.
+        Selector::Name(name) => {
+            for serial in &serials {
+                let bot = match connect(serial).await {
+                    Ok(bot) => bot,
+                    Err(e) => {
+                        eprintln!("skipping {serial}: {e}");
+                        continue;
+                    }
+                };
+                if bot.info.name.as_str() == name {
+                    return Ok(bot);
+                }
+                bot.close()?;
+            }
+            bail!("no bot named {name}")
+        }
+        Selector::Any => match serials.as_slice() {
+            [serial] => connect(serial).await,
+            [] => bail!("no bots connected"),
+            _ => bail!(
+                "{} bots connected, pick one with --serial or --name",
+                serials.len()
+            ),
+        },
+    }
+}
@@ -0,0 +1,653 @@
+//! Command line client for BlinkyBots.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use blinkybot_replay::Session;
+use blinkybot_rpc::{
+    ApplyPreset, ApplyPresetEndpoint, BehaviorEventKind, BehaviorTopic, BlinkSync, ButtonTopic,
+    DeviceName, ExpressionIndex, FaceMode, FriendId, GetBlinkSyncEndpoint, GetClockConfigEndpoint,
+    GetMoodEndpoint, GetPeerEndpoint, GetScriptEndpoint, GetTransitionEndpoint,
+    ListFriendsEndpoint, ListPresetsEndpoint, Mood, PingEndpoint, Preset, RemoveFriendEndpoint,
+    SetBlinkSyncEndpoint, SetClockConfigEndpoint, SetDeviceNameEndpoint, SetFaceModeEndpoint,
+    SetMoodEndpoint, SetScriptEndpoint, SetTransition, SetTransitionEndpoint, TransitionEffect,
+    TransitionKind, MAX_NAME_LEN,
+};
+use clap::{Parser, Subcommand};
+
+mod bot;
+mod pack;
+mod script;
+
+use bot::{Selector, Target};
+
+#[derive(Parser)]
+#[command(about = "Control BlinkyBots over USB")]
+struct Args {
+    /// USB serial number of the bot to use.
+    #[arg(long, global = true, conflicts_with = "name")]
+    serial: Option<String>,
+    /// Name of the bot to use.
+    #[arg(long, global = true)]
+    name: Option<String>,
+    /// Save the requests sent to the bot, its responses and the events it
+    /// sent to a file, to check later with `replay`.
+    #[arg(long, global = true)]
+    record: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List connected bots.
+    List,
+    /// Show the bot's serial number and name.
+    Info,
+    /// Set the bot's name.
+    SetName { name: String },
+    /// Check that the bot responds.
+    Ping,
+    /// Show the bot's mood, or force it to one of idle, happy, sleepy,
+    /// surprised or bored.  `auto` lets the bot decide again.
+    Mood {
+        #[arg(value_parser = parse_mood)]
+        mood: Option<MoodArg>,
+    },
+    /// Print button gestures as they happen.
+    Buttons,
+    /// Print what the bot does as it happens: blinks, expressions, moods,
+    /// friends, brightness and sleep.
+    Watch,
+    /// Show the friend the bot is facing.
+    Peer,
+    /// Show or set whether the bot blinks in time with friends: `off`,
+    /// `unison` or a delay after the leading friend in milliseconds.
+    BlinkSync {
+        #[arg(value_parser = parse_blink_sync)]
+        sync: Option<BlinkSync>,
+    },
+    /// List the friend table.
+    Friends,
+    /// Remove a friend, given as a serial number or an ADC band like
+    /// `adc:0-ff`.
+    ForgetFriend {
+        #[arg(value_parser = parse_friend_id)]
+        id: FriendId,
+    },
+    /// Show the transitions between expressions, or set the one used for
+    /// blink, friend, mood or sleep to cut, crossfade, wipe, dissolve or
+    /// eyelid.
+    Transition {
+        #[arg(value_parser = parse_transition_kind)]
+        kind: Option<TransitionKind>,
+        #[arg(value_parser = parse_transition_effect, requires = "kind")]
+        effect: Option<TransitionEffect>,
+        /// Duration in milliseconds.  Keeps the current one if not given.
+        #[arg(requires = "effect")]
+        duration_ms: Option<u16>,
+    },
+    /// Assemble, upload and simulate behavior scripts.
+    Script {
+        #[command(subcommand)]
+        command: ScriptCommand,
+    },
+    /// Send the requests of a session saved with `--record` again and
+    /// report the responses that differ.
+    Replay { file: PathBuf },
+    /// Export, import and inspect expression packs.
+    Pack {
+        #[command(subcommand)]
+        command: PackCommand,
+    },
+    /// List the bot's preset expressions, or set the default, blink,
+    /// friend, friend-blink or sleep expression to one of them.
+    Preset {
+        #[arg(value_parser = parse_expression_index, requires = "preset")]
+        slot: Option<ExpressionIndex>,
+        #[arg(value_parser = parse_preset)]
+        preset: Option<Preset>,
+    },
+    /// Show or change how the bot shows the time.
+    Clock {
+        /// Show 12 or 24 hour time.
+        #[arg(long, value_parser = parse_hours)]
+        hours: Option<bool>,
+        /// Scroll the date past after the time: on or off.
+        #[arg(long, value_parser = parse_on_off)]
+        date: Option<bool>,
+        /// Seconds to show the time for.
+        #[arg(long)]
+        clock_secs: Option<u16>,
+        /// Seconds to show the face for in between, 0 to only show the
+        /// time.
+        #[arg(long)]
+        face_secs: Option<u16>,
+        /// Also switch the bot to showing the clock.
+        #[arg(long)]
+        show: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScriptCommand {
+    /// Check that a script assembles.
+    Check { file: PathBuf },
+    /// Upload a script to the bot.
+    Upload {
+        file: PathBuf,
+        /// Also switch the bot to running it.
+        #[arg(long)]
+        run: bool,
+    },
+    /// Print the bot's script.
+    Show,
+    /// Run a script on a pretend bot, printing what it shows.
+    Simulate {
+        file: PathBuf,
+        /// Pretend a friend is seen.
+        #[arg(long)]
+        friend: bool,
+        /// Sense input reading, in hex.
+        #[arg(long, value_parser = parse_hex, default_value = "fff")]
+        adc: u16,
+        /// How long to run for.
+        #[arg(long, default_value_t = 30)]
+        seconds: u64,
+        /// Seed for the script's random choices.
+        #[arg(long, default_value_t = 1)]
+        seed: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum PackCommand {
+    /// Save the bot's expressions, script, behavior and brightness as a
+    /// pack.
+    Export {
+        /// What to call the pack.
+        #[arg(long = "pack-name", default_value = "")]
+        pack_name: String,
+        #[arg(long, default_value = "")]
+        author: String,
+        /// File to write.  Prints the text for a share link if not given.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Set everything in a pack on the bot.  Takes a pack file, its share
+    /// text or a whole share link.
+    Import { pack: String },
+    /// Print what a pack contains.
+    Show { pack: String },
+}
+
+/// A mood to force, `None` for `auto`.
+#[derive(Clone, Copy)]
+struct MoodArg(Option<Mood>);
+
+fn parse_mood(s: &str) -> Result<MoodArg, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(MoodArg(None));
+    }
+    Mood::ALL
+        .into_iter()
+        .find(|mood| format!("{mood:?}").eq_ignore_ascii_case(s))
+        .map(|mood| MoodArg(Some(mood)))
+        .ok_or_else(|| format!("unknown mood {s}"))
+}
+
+fn parse_expression_index(s: &str) -> Result<ExpressionIndex, String> {
+    let name = s.replace(['-', '_'], "");
+    ExpressionIndex::ALL
+        .into_iter()
+        .find(|index| format!("{index:?}").eq_ignore_ascii_case(&name))
+        .ok_or_else(|| format!("unknown expression {s}"))
+}
+
+fn parse_preset(s: &str) -> Result<Preset, String> {
+    Preset::ALL
+        .into_iter()
+        .find(|preset| preset.name() == s)
+        .ok_or_else(|| format!("unknown preset {s}"))
+}
+
+fn parse_transition_kind(s: &str) -> Result<TransitionKind, String> {
+    TransitionKind::ALL
+        .into_iter()
+        .find(|kind| format!("{kind:?}").eq_ignore_ascii_case(s))
+        .ok_or_else(|| format!("unknown transition {s}"))
+}
+
+fn parse_transition_effect(s: &str) -> Result<TransitionEffect, String> {
+    [
+        TransitionEffect::Cut,
+        TransitionEffect::CrossFade,
+        TransitionEffect::Wipe,
+        TransitionEffect::Dissolve,
+        TransitionEffect::Eyelid,
+    ]
+    .into_iter()
+    .find(|effect| format!("{effect:?}").eq_ignore_ascii_case(s))
+    .ok_or_else(|| format!("unknown effect {s}"))
+}
+
+fn parse_blink_sync(s: &str) -> Result<BlinkSync, String> {
+    match s {
+        "off" => Ok(BlinkSync::new(false, 0)),
+        "unison" => Ok(BlinkSync::new(true, 0)),
+        _ => s
+            .parse()
+            .map(|cascade_ms| BlinkSync::new(true, cascade_ms))
+            .map_err(|_| format!("expected off, unison or a delay in ms, got {s}")),
+    }
+}
+
+/// Parses 12 or 24 hour time, returning whether it's 24 hour.
+fn parse_hours(s: &str) -> Result<bool, String> {
+    match s {
+        "12" => Ok(false),
+        "24" => Ok(true),
+        _ => Err(format!("expected 12 or 24, got {s}")),
+    }
+}
+
+fn parse_on_off(s: &str) -> Result<bool, String> {
+    match s {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, got {s}")),
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s, 16).map_err(|e| format!("{s}: {e}"))
+}
+
+fn parse_friend_id(s: &str) -> Result<FriendId, String> {
+    let parse = |hex| u16::from_str_radix(hex, 16).map_err(|e| format!("{hex}: {e}"));
+    match s.strip_prefix("adc:") {
+        Some(band) => {
+            let (min, max) = band
+                .split_once('-')
+                .ok_or_else(|| format!("expected adc:MIN-MAX, got {s}"))?;
+            Ok(FriendId::AdcBand {
+                min: parse(min)?,
+                max: parse(max)?,
+            })
+        }
+        None => u64::from_str_radix(s, 16)
+            .map(FriendId::Serial)
+            .map_err(|e| format!("{s}: {e}")),
+    }
+}
+
+fn format_friend_id(id: FriendId) -> String {
+    match id {
+        FriendId::Serial(serial) => bot::serial_string(serial),
+        FriendId::AdcBand { min, max } => format!("adc:{min:x}-{max:x}"),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let selector = match (args.serial, args.name) {
+        (Some(serial), _) => Selector::Serial(serial),
+        (None, Some(name)) => Selector::Name(name),
+        (None, None) => Selector::Any,
+    };
+    let target = Target {
+        selector,
+        record: args.record,
+    };
+
+    match args.command {
+        Command::List => {
+            for serial in bot::serials()? {
+                match bot::connect(&serial).await {
+                    Ok(bot) => {
+                        println!("{}\t{}", bot.serial(), bot.info.name.as_str());
+                        bot.close()?;
+                    }
+                    Err(e) => eprintln!("{serial}\tnot responding: {e}"),
+                }
+            }
+        }
+        Command::Info => {
+            let bot = bot::open(&target).await?;
+            println!("serial: {}", bot.serial());
+            println!("name: {}", bot.info.name.as_str());
+            bot.close()?;
+        }
+        Command::SetName { name } => {
+            let name = DeviceName::new(&name).ok_or_else(|| {
+                anyhow!(
+                    "name is {} bytes, the maximum is {MAX_NAME_LEN}",
+                    name.len()
+                )
+            })?;
+            let bot = bot::open(&target).await?;
+            bot.client
+                .send_resp::<SetDeviceNameEndpoint>(&name)
+                .await
+                .map_err(|e| anyhow!("failed to set name: {e:?}"))?;
+            bot.close()?;
+        }
+        Command::Ping => {
+            let bot = bot::open(&target).await?;
+            let val = bot
+                .client
+                .send_resp::<PingEndpoint>(&42)
+                .await
+                .map_err(|e| anyhow!("ping failed: {e:?}"))?;
+            println!("ping: {val}");
+            bot.close()?;
+        }
+        Command::Mood { mood } => {
+            let bot = bot::open(&target).await?;
+            match mood {
+                Some(MoodArg(mood)) => bot
+                    .client
+                    .send_resp::<SetMoodEndpoint>(&mood)
+                    .await
+                    .map_err(|e| anyhow!("failed to set mood: {e:?}"))?,
+                None => {
+                    let mood = bot
+                        .client
+                        .send_resp::<GetMoodEndpoint>(&())
+                        .await
+                        .map_err(|e| anyhow!("failed to get mood: {e:?}"))?;
+                    println!("mood: {mood:?}");
+                }
+            }
+            bot.close()?;
+        }
+        Command::Buttons => {
+            let bot = bot::open(&target).await?;
+            let mut events = bot
+                .client
+                .subscribe::<ButtonTopic>(8)
+                .await
+                .map_err(|_| anyhow!("failed to subscribe to button events"))?;
+            while let Some(event) = events.recv().await {
+                println!("button {}: {:?}", event.button, event.gesture);
+            }
+        }
+        Command::Watch => {
+            let bot = bot::open(&target).await?;
+            let mut events = bot
+                .client
+                .subscribe::<BehaviorTopic>(16)
+                .await
+                .map_err(|_| anyhow!("failed to subscribe to behavior events"))?;
+            while let Some(event) = events.recv().await {
+                let what = match event.kind {
+                    BehaviorEventKind::Blink => "blink".to_string(),
+                    BehaviorEventKind::Expression(index) => format!("expression {index:?}"),
+                    BehaviorEventKind::FaceMode(mode) => format!("face mode {mode:?}"),
+                    BehaviorEventKind::Mood(mood) => format!("mood {mood:?}"),
+                    BehaviorEventKind::Friend(true) => "friend seen".to_string(),
+                    BehaviorEventKind::Friend(false) => "friend gone".to_string(),
+                    BehaviorEventKind::Brightness(level) => format!("brightness {level}%"),
+                    BehaviorEventKind::Sleep(true) => "asleep".to_string(),
+                    BehaviorEventKind::Sleep(false) => "awake".to_string(),
+                };
+                let ms = event.uptime_ms;
+                println!("{:>6}.{:03}s  {what}", ms / 1000, ms % 1000);
+            }
+        }
+        Command::Peer => {
+            let bot = bot::open(&target).await?;
+            let peer = bot
+                .client
+                .send_resp::<GetPeerEndpoint>(&())
+                .await
+                .map_err(|e| anyhow!("failed to get peer: {e:?}"))?;
+            match peer {
+                Some(peer) => {
+                    println!("serial: {}", bot::serial_string(peer.serial));
+                    println!("name: {}", peer.name.as_str());
+                    println!("mood: {:?}", peer.mood);
+                }
+                None => println!("no friend"),
+            }
+            bot.close()?;
+        }
+        Command::BlinkSync { sync } => {
+            let bot = bot::open(&target).await?;
+            match sync {
+                Some(sync) => bot
+                    .client
+                    .send_resp::<SetBlinkSyncEndpoint>(&sync)
+                    .await
+                    .map_err(|e| anyhow!("failed to set blink sync: {e:?}"))?,
+                None => {
+                    let sync = bot
+                        .client
+                        .send_resp::<GetBlinkSyncEndpoint>(&())
+                        .await
+                        .map_err(|e| anyhow!("failed to get blink sync: {e:?}"))?;
+                    match (sync.enabled, sync.cascade_ms) {
+                        (false, _) => println!("blink sync: off"),
+                        (true, 0) => println!("blink sync: unison"),
+                        (true, ms) => println!("blink sync: {ms}ms after leader"),
+                    }
+                }
+            }
+            bot.close()?;
+        }
+        Command::Friends => {
+            let bot = bot::open(&target).await?;
+            let ids = bot
+                .client
+                .send_resp::<ListFriendsEndpoint>(&())
+                .await
+                .map_err(|e| anyhow!("failed to list friends: {e:?}"))?;
+            for id in ids.into_iter().flatten() {
+                println!("{}", format_friend_id(id));
+            }
+            bot.close()?;
+        }
+        Command::ForgetFriend { id } => {
+            let bot = bot::open(&target).await?;
+            let removed = bot
+                .client
+                .send_resp::<RemoveFriendEndpoint>(&id)
+                .await
+                .map_err(|e| anyhow!("failed to remove friend: {e:?}"))?;
+            bot.close()?;
+            if !removed {
+                return Err(anyhow!("no friend {}", format_friend_id(id)));
+            }
+        }
+        Command::Transition {
+            kind,
+            effect,
+            duration_ms,
+        } => {
+            let bot = bot::open(&target).await?;
+            let kinds = kind.map_or(TransitionKind::ALL.to_vec(), |kind| vec![kind]);
+            for kind in kinds {
+                let mut style = bot
+                    .client
+                    .send_resp::<GetTransitionEndpoint>(&kind)
+                    .await
+                    .map_err(|e| anyhow!("failed to get transition: {e:?}"))?;
+                let Some(effect) = effect else {
+                    println!("{kind:?}: {:?} {}ms", style.effect, style.duration_ms);
+                    continue;
+                };
+                style.effect = effect;
+                style.duration_ms = duration_ms.unwrap_or(style.duration_ms);
+                bot.client
+                    .send_resp::<SetTransitionEndpoint>(&SetTransition { kind, style })
+                    .await
+                    .map_err(|e| anyhow!("failed to set transition: {e:?}"))?;
+            }
+            bot.close()?;
+        }
+        Command::Script { command } => run_script_command(command, &target).await?,
+        Command::Replay { file } => {
+            let text = fs::read_to_string(&file)
+                .map_err(|e| anyhow!("failed to read {}: {e}", file.display()))?;
+            let session = Session::parse(&text).map_err(|e| anyhow!("{}: {e}", file.display()))?;
+            let bot = bot::open(&target).await?;
+            let mismatches = blinkybot_replay::replay(&bot.client, &session).await;
+            bot.close()?;
+            for mismatch in &mismatches {
+                println!(
+                    "{} {}: {}",
+                    mismatch.seq_no, mismatch.path, mismatch.difference
+                );
+            }
+            if !mismatches.is_empty() {
+                bail!(
+                    "{} of {} responses differ",
+                    mismatches.len(),
+                    session.exchanges.len()
+                );
+            }
+            println!("{} responses match", session.exchanges.len());
+        }
+        Command::Pack { command } => run_pack_command(command, &target).await?,
+        Command::Preset { slot, preset } => {
+            let bot = bot::open(&target).await?;
+            if let (Some(index), Some(preset)) = (slot, preset) {
+                bot.client
+                    .send_resp::<ApplyPresetEndpoint>(&ApplyPreset { preset, index })
+                    .await
+                    .map_err(|e| anyhow!("failed to apply preset: {e:?}"))?;
+            } else {
+                let presets = bot
+                    .client
+                    .send_resp::<ListPresetsEndpoint>(&())
+                    .await
+                    .map_err(|e| anyhow!("failed to list presets: {e:?}"))?;
+                for preset in presets {
+                    println!("{}", preset.name());
+                }
+            }
+            bot.close()?;
+        }
+        Command::Clock {
+            hours,
+            date,
+            clock_secs,
+            face_secs,
+            show,
+        } => {
+            let bot = bot::open(&target).await?;
+            let mut config = bot
+                .client
+                .send_resp::<GetClockConfigEndpoint>(&())
+                .await
+                .map_err(|e| anyhow!("failed to get clock config: {e:?}"))?;
+            let current = config;
+            config.hours_24 = hours.unwrap_or(config.hours_24);
+            config.show_date = date.unwrap_or(config.show_date);
+            config.clock_secs = clock_secs.unwrap_or(config.clock_secs);
+            config.face_secs = face_secs.unwrap_or(config.face_secs);
+            if config != current {
+                bot.client
+                    .send_resp::<SetClockConfigEndpoint>(&config)
+                    .await
+                    .map_err(|e| anyhow!("failed to set clock config: {e:?}"))?;
+            }
+            if show {
+                bot.client
+                    .send_resp::<SetFaceModeEndpoint>(&FaceMode::Clock)
+                    .await
+                    .map_err(|e| anyhow!("failed to set face mode: {e:?}"))?;
+            }
+            bot.close()?;
+            println!(
+                "clock: {} hour, date {}, {}s clock, {}s face",
+                if config.hours_24 { 24 } else { 12 },
+                if config.show_date { "on" } else { "off" },
+                config.clock_secs,
+                config.face_secs
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn run_script_command(command: ScriptCommand, target: &Target) -> Result<()> {
+    let read = |file: &PathBuf| {
+        let source = fs::read_to_string(file)
+            .map_err(|e| anyhow!("failed to read {}: {e}", file.display()))?;
+        script::assemble(&source).map_err(|e| anyhow!("{}: {e}", file.display()))
+    };
+    match command {
+        ScriptCommand::Check { file } => {
+            let script = read(&file)?;
+            println!("{}: {} bytes", file.display(), script.code().len());
+        }
+        ScriptCommand::Upload { file, run } => {
+            let script = read(&file)?;
+            let bot = bot::open(target).await?;
+            bot.client
+                .send_resp::<SetScriptEndpoint>(&script)
+                .await
+                .map_err(|e| anyhow!("failed to upload script: {e:?}"))?
+                .map_err(|e| anyhow!("bot rejected script: {e:?}"))?;
+            if run {
+                bot.client
+                    .send_resp::<SetFaceModeEndpoint>(&FaceMode::Script)
+                    .await
+                    .map_err(|e| anyhow!("failed to set face mode: {e:?}"))?;
+            }
+            bot.close()?;
+        }
+        ScriptCommand::Show => {
+            let bot = bot::open(target).await?;
+            let script = bot
+                .client
+                .send_resp::<GetScriptEndpoint>(&())
+                .await
+                .map_err(|e| anyhow!("failed to get script: {e:?}"))?;
+            bot.close()?;
+            print!("{}", script::disassemble(&script)?);
+        }
+        ScriptCommand::Simulate {
+            file,
+            friend,
+            adc,
+            seconds,
+            seed,
+        } => {
+            let script = read(&file)?;
+            script::simulate(script, friend, adc, seconds * 1000, seed)?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_pack_command(command: PackCommand, target: &Target) -> Result<()> {
+    match command {
+        PackCommand::Export {
+            pack_name,
+            author,
+            output,
+        } => {
+            let manifest = pack::manifest(&pack_name, &author)?;
+            let bot = bot::open(target).await?;
+            let pack = pack::read(&bot, manifest).await?;
+            bot.close()?;
+            match output {
+                Some(file) => fs::write(&file, pack::to_bytes(&pack)?)
+                    .map_err(|e| anyhow!("failed to write {}: {e}", file.display()))?,
+                None => println!("{}", pack::to_text(&pack)?),
+            }
+        }
+        PackCommand::Import { pack } => {
+            let pack = pack::load(&pack)?;
+            let bot = bot::open(target).await?;
+            pack::apply(&bot, &pack).await?;
+            bot.close()?;
+        }
+        PackCommand::Show { pack } => print!("{}", pack::describe(&pack::load(&pack)?)),
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! Expression packs on the command line.
+//!
+//! Packs are read from and written to files, or given as the base64url text
+//! of a share link.  Anything up to the last `pack=` is skipped, so whole
+//! links can be pasted.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use blinkybot_core::pack::{
+    self, Manifest, Pack, Requests, TransferError, MAX_PACK_LEN, MAX_TEXT_LEN,
+};
+use blinkybot_rpc::{DeviceName, ExpressionIndex, MAX_NAME_LEN};
+use postcard::experimental::schema::Schema;
+use postcard_rpc::{host_client::HostErr, standard_icd::WireError, Endpoint};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::bot::Bot;
+
+/// Reads a pack from the file `source`, or from share text if there is no
+/// such file.
+pub fn load(source: &str) -> Result<Pack> {
+    let mut buffer = [0; MAX_PACK_LEN];
+    let bytes = if Path::new(source).is_file() {
+        fs::read(source).map_err(|e| anyhow!("failed to read {source}: {e}"))?
+    } else {
+        let text = source.rsplit("pack=").next().unwrap_or(source);
+        pack::decode_text(text, &mut buffer)
+            .map_err(|e| anyhow!("{source} is neither a file nor a pack: {e:?}"))?
+            .to_vec()
+    };
+    pack::decode(&bytes).map_err(|e| anyhow!("bad pack: {e:?}"))
+}
+
+/// Returns `pack` encoded for a file.
+pub fn to_bytes(pack: &Pack) -> Result<Vec<u8>> {
+    let mut buffer = [0; MAX_PACK_LEN];
+    let bytes = pack::encode(pack, &mut buffer).map_err(|e| anyhow!("{e:?}"))?;
+    Ok(bytes.to_vec())
+}
+
+/// Returns `pack` as the text of a share link.
+pub fn to_text(pack: &Pack) -> Result<String> {
+    let mut buffer = [0; MAX_TEXT_LEN];
+    let text = pack::encode_text(&to_bytes(pack)?, &mut buffer).map_err(|e| anyhow!("{e:?}"))?;
+    Ok(text.to_string())
+}
+
+/// Returns what `pack` contains, one part per line.
+pub fn describe(pack: &Pack) -> String {
+    let mut description = format!("name: {}\n", pack.manifest.name.as_str());
+    if !pack.manifest.author.as_str().is_empty() {
+        description += &format!("author: {}\n", pack.manifest.author.as_str());
+    }
+    for (index, expression) in ExpressionIndex::ALL.iter().zip(&pack.expressions) {
+        if expression.is_some() {
+            description += &format!("expression: {index:?}\n");
+        }
+    }
+    if let Some(script) = &pack.script {
+        description += &format!("script: {} bytes\n", script.code().len());
+    }
+    if let Some(behavior) = &pack.behavior {
+        description += &format!("behavior: {:?} face\n", behavior.face_mode);
+    }
+    if let Some(brightness) = &pack.brightness {
+        description += &format!(
+            "brightness: {}% {:?}\n",
+            brightness.brightness, brightness.mode
+        );
+    }
+    description
+}
+
+impl Requests for Bot {
+    type Error = HostErr<WireError>;
+
+    async fn send<E: Endpoint>(&self, request: &E::Request) -> Result<E::Response, Self::Error>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: Serialize + DeserializeOwned + Schema,
+    {
+        self.client.send_resp::<E>(request).await
+    }
+}
+
+fn transfer_error(e: TransferError<HostErr<WireError>>) -> anyhow::Error {
+    match e {
+        TransferError::Request { path, error } => anyhow!("request to {path} failed: {error:?}"),
+        TransferError::ScriptRejected(e) => anyhow!("bot rejected script: {e:?}"),
+    }
+}
+
+/// Returns a pack described by `manifest` of everything `bot` has.
+pub async fn read(bot: &Bot, manifest: Manifest) -> Result<Pack> {
+    pack::read(bot, manifest).await.map_err(transfer_error)
+}
+
+/// Sets everything in `pack` on `bot`.
+pub async fn apply(bot: &Bot, pack: &Pack) -> Result<()> {
+    pack::apply(bot, pack).await.map_err(transfer_error)
+}
+
+/// Returns the manifest of a pack called `name` made by `author`.
+pub fn manifest(name: &str, author: &str) -> Result<Manifest> {
+    let text = |text: &str| {
+        DeviceName::new(text).ok_or_else(|| {
+            anyhow!(
+                "{text} is {} bytes, the maximum is {MAX_NAME_LEN}",
+                text.len()
+            )
+        })
+    };
+    Ok(Manifest {
+        name: text(name)?,
+        author: text(author)?,
+    })
+}
@@ -0,0 +1,355 @@
+//! Behavior script assembly.
+//!
+//! Scripts are written one instruction per line as a mnemonic followed by
+//! its operands, separated by spaces:
+//!
+//! ```text
+//! # Look around, and blink now and then.
+//! start:
+//!     show default
+//!     waitr 2000 6000
+//!     iffriend greet
+//!     chance 30 start
+//!     show blink
+//!     wait 100
+//!     jump start
+//! greet:
+//!     show friend
+//!     wait 1000
+//!     jump start
+//! ```
+//!
+//! Expressions are given by name, jump targets by label, and the brightness
+//! of `bright` as a percentage or `default`.  Numbers are decimal or hex
+//! with a `0x` prefix.  Everything after a `#` is a comment.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use blinkybot_core::clock::{MockClock, MonotonicClock};
+use blinkybot_core::script::{
+    self, Body, Instruction, Op, Operand, Status, Vm, CONFIGURED_BRIGHTNESS, MAX_INSTRUCTION_LEN,
+};
+use blinkybot_rpc::{ExpressionIndex, Script, ScriptError, MAX_SCRIPT_LEN};
+
+/// Returns the name of `expression` in assembly, e.g. `friend_blink`.
+fn expression_name(expression: ExpressionIndex) -> String {
+    let mut name = String::new();
+    for c in format!("{expression:?}").chars() {
+        if c.is_ascii_uppercase() && !name.is_empty() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+fn parse_number(s: &str) -> Result<u16> {
+    let number = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    number.map_err(|e| anyhow!("bad number {s}: {e}"))
+}
+
+fn parse_operand(operand: Operand, s: &str, labels: &HashMap<&str, usize>) -> Result<u16> {
+    match operand {
+        Operand::Expression => ExpressionIndex::ALL
+            .into_iter()
+            .find(|&expression| expression_name(expression) == s)
+            .map(|expression| expression as u16)
+            .ok_or_else(|| anyhow!("unknown expression {s}")),
+        Operand::Target => match labels.get(s) {
+            Some(&offset) => Ok(offset as u16),
+            None => parse_number(s).map_err(|_| anyhow!("unknown label {s}")),
+        },
+        Operand::Brightness if s == "default" => Ok(CONFIGURED_BRIGHTNESS),
+        _ => parse_number(s),
+    }
+}
+
+/// One instruction of the source.
+struct Line<'a> {
+    number: usize,
+    op: Op,
+    operands: Vec<&'a str>,
+}
+
+/// Assembles `source` into a script.
+pub fn assemble(source: &str) -> Result<Script> {
+    // First find where every label points, then encode the instructions.
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let (label, line) = match line.split_once(':') {
+            Some((label, rest)) => (Some(label.trim()), rest.trim()),
+            None => (None, line),
+        };
+        if let Some(label) = label {
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                bail!("line {number}: bad label {label:?}");
+            }
+            if labels.insert(label, offset).is_some() {
+                bail!("line {number}: label {label} is already defined");
+            }
+        }
+        let mut words = line.split_whitespace();
+        let Some(mnemonic) = words.next() else {
+            continue;
+        };
+        let op = Op::ALL
+            .into_iter()
+            .find(|op| op.mnemonic() == mnemonic)
+            .ok_or_else(|| anyhow!("line {number}: unknown instruction {mnemonic}"))?;
+        let operands: Vec<_> = words.collect();
+        if operands.len() != op.operands().len() {
+            bail!(
+                "line {number}: {mnemonic} takes {} operands, got {}",
+                op.operands().len(),
+                operands.len()
+            );
+        }
+        offset += Instruction {
+            op,
+            operands: [0; 2],
+        }
+        .encoded_len();
+        lines.push(Line {
+            number,
+            op,
+            operands,
+        });
+    }
+
+    let mut code = Vec::new();
+    // Source line of each instruction, for reporting validation errors.
+    let mut line_at = HashMap::new();
+    for line in &lines {
+        let mut instruction = Instruction {
+            op: line.op,
+            operands: [0; 2],
+        };
+        for ((operand, s), value) in line
+            .op
+            .operands()
+            .iter()
+            .zip(&line.operands)
+            .zip(instruction.operands.iter_mut())
+        {
+            let parsed = parse_operand(*operand, s, &labels)
+                .map_err(|e| anyhow!("line {}: {e}", line.number))?;
+            if operand.encoded_len() == 1 && parsed > u8::MAX.into() {
+                bail!("line {}: {s} doesn't fit in a byte", line.number);
+            }
+            *value = parsed;
+        }
+        line_at.insert(code.len(), line.number);
+        let mut buffer = [0; MAX_INSTRUCTION_LEN];
+        let len = instruction.encode(&mut buffer);
+        code.extend_from_slice(&buffer[..len]);
+    }
+
+    if let Err(ScriptError { offset, kind }) = script::validate(&code) {
+        return Err(match line_at.get(&usize::from(offset)) {
+            Some(number) => anyhow!("line {number}: {kind:?}"),
+            None => anyhow!("{kind:?} at offset {offset}"),
+        });
+    }
+    Script::new(&code).ok_or_else(|| {
+        anyhow!(
+            "script is {} bytes, the maximum is {MAX_SCRIPT_LEN}",
+            code.len()
+        )
+    })
+}
+
+/// Returns the assembly of `script`, with jump targets given as labels.
+pub fn disassemble(script: &Script) -> Result<String> {
+    let code = script.code();
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let instruction = Instruction::decode(code, offset)
+            .map_err(|kind| anyhow!("{kind:?} at offset {offset}"))?;
+        instructions.push((offset, instruction));
+        offset += instruction.encoded_len();
+    }
+
+    let label = |target: u16| format!("at_{target}");
+    let mut targets: Vec<u16> = instructions
+        .iter()
+        .flat_map(|(_, instruction)| {
+            instruction
+                .op
+                .operands()
+                .iter()
+                .zip(instruction.operands)
+                .filter(|(operand, _)| **operand == Operand::Target)
+                .map(|(_, value)| value)
+        })
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+
+    let mut source = String::new();
+    for (offset, instruction) in instructions {
+        if targets.binary_search(&(offset as u16)).is_ok() {
+            source += &format!("{}:\n", label(offset as u16));
+        }
+        source += "    ";
+        source += instruction.op.mnemonic();
+        for (operand, value) in instruction.op.operands().iter().zip(instruction.operands) {
+            let value = match operand {
+                Operand::Expression => ExpressionIndex::ALL
+                    .get(value as usize)
+                    .map_or(value.to_string(), |&expression| expression_name(expression)),
+                Operand::Target => label(value),
+                Operand::Brightness if value == CONFIGURED_BRIGHTNESS => "default".to_string(),
+                Operand::Adc => format!("0x{value:x}"),
+                _ => value.to_string(),
+            };
+            source += " ";
+            source += &value;
+        }
+        source += "\n";
+    }
+    // Jumps may also go to the end of the script.
+    if targets.binary_search(&(code.len() as u16)).is_ok() {
+        source += &format!("{}:\n", label(code.len() as u16));
+    }
+    Ok(source)
+}
+
+/// A pretend bot that prints what the script does to it.
+struct Simulated<'a> {
+    clock: &'a MockClock,
+    sees_friend: bool,
+    adc: u16,
+}
+
+impl Simulated<'_> {
+    fn log(&self, what: &str) {
+        let ms = self.clock.now_ms();
+        println!("{:>4}.{:03}s  {what}", ms / 1000, ms % 1000);
+    }
+}
+
+impl Body for Simulated<'_> {
+    fn show(&mut self, expression: ExpressionIndex) {
+        self.log(&format!("show {}", expression_name(expression)));
+    }
+
+    fn set_brightness(&mut self, brightness: Option<u8>) {
+        match brightness {
+            Some(brightness) => self.log(&format!("brightness {brightness}%")),
+            None => self.log("brightness default"),
+        }
+    }
+
+    fn sees_friend(&self) -> bool {
+        self.sees_friend
+    }
+
+    fn adc(&self) -> u16 {
+        self.adc
+    }
+}
+
+/// Runs `script` for `duration_ms` as if on a bot that reads `adc` from its
+/// sense input, printing what it shows.
+pub fn simulate(
+    script: Script,
+    sees_friend: bool,
+    adc: u16,
+    duration_ms: u64,
+    seed: u32,
+) -> Result<()> {
+    let clock = MockClock::new(0);
+    let mut vm = Vm::new(&clock, script, seed).map_err(|e| anyhow!("{e:?}"))?;
+    let mut body = Simulated {
+        clock: &clock,
+        sees_friend,
+        adc,
+    };
+    loop {
+        match vm.run(&mut body) {
+            Status::Waiting { until_ms } if until_ms <= duration_ms => clock.set(until_ms),
+            Status::Waiting { .. } => break,
+            Status::Ended => {
+                body.log("end");
+                break;
+            }
+            Status::Busy => bail!(
+                "script stopped at {}ms for running {} instructions without waiting",
+                clock.now_ms(),
+                script::MAX_BUSY_STEPS
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "
+        # Look around, and blink now and then.
+        start:
+            show default
+            waitr 2000 6000
+            iffriend greet
+            chance 30 start
+            show blink
+            wait 100
+            jump start
+        greet:
+            show friend_blink
+            bright default
+            ifadcbelow 0x80 done
+            bright 50
+            count 1 3
+        again: loop 1 again
+            wait 1000
+            jump start
+        done:
+    ";
+
+    #[test]
+    fn assembly_round_trips() {
+        let script = assemble(EXAMPLE).unwrap();
+        let source = disassemble(&script).unwrap();
+        assert_eq!(assemble(&source).unwrap(), script);
+        assert_eq!(disassemble(&assemble(&source).unwrap()).unwrap(), source);
+    }
+
+    #[test]
+    fn disassembly_labels_targets() {
+        let script =
+            assemble("top: show sleep\n wait 0x10\n iffriend top\n jump end\nend:").unwrap();
+        assert_eq!(
+            disassemble(&script).unwrap(),
+            "at_0:\n    show sleep\n    wait 16\n    iffriend at_0\n    jump at_11\nat_11:\n"
+        );
+    }
+
+    #[test]
+    fn mistakes_are_reported_by_line() {
+        for (source, error) in [
+            ("show nobody", "line 1: unknown expression nobody"),
+            ("\njump nowhere", "line 2: unknown label nowhere"),
+            ("a: end\na: end", "line 2: label a is already defined"),
+            ("wait", "line 1: wait takes 1 operands, got 0"),
+            ("dance", "line 1: unknown instruction dance"),
+            ("count 0 256", "line 1: 256 doesn't fit in a byte"),
+            ("show blink\nchance 101 0", "line 2: BadPercent"),
+            ("bright 101", "line 1: BadBrightness"),
+            ("jump 1\nend", "line 1: BadTarget"),
+        ] {
+            assert_eq!(assemble(source).unwrap_err().to_string(), error);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Smoothing ambient light readings into a steady automatic brightness.
+//!
+//! Readings are averaged by `Smoother` and turned into a brightness level by
+//! `AutoLevel`, which holds the level until the light moves clearly past it.
+//! Every new level redraws the display, so sensor noise mustn't reach it.
+
+use blinkybot_rpc::AutoBrightness;
+
+/// Readings the light has to move by, past where the level would change,
+/// before it does.
+pub const HYSTERESIS: u16 = 32;
+
+/// Exponential moving average over raw ADC readings.
+///
+/// The average is kept with 4 extra bits of precision so small changes in
+/// light still move the output.
+pub struct Smoother {
+    value: Option<u32>,
+}
+
+impl Smoother {
+    const SHIFT: u32 = 3;
+    const FRACTION_BITS: u32 = 4;
+
+    pub const fn new() -> Self {
+        Self { value: None }
+    }
+
+    /// Adds `sample` to the average and returns the new smoothed value.
+    pub fn update(&mut self, sample: u16) -> u16 {
+        let sample = (sample as u32) << Self::FRACTION_BITS;
+        let value = match self.value {
+            None => sample,
+            Some(value) => value - (value >> Self::SHIFT) + (sample >> Self::SHIFT),
+        };
+        self.value = Some(value);
+        (value >> Self::FRACTION_BITS) as u16
+    }
+}
+
+impl Default for Smoother {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The automatic brightness level, with hysteresis.
+#[derive(Default)]
+pub struct AutoLevel {
+    level: Option<u8>,
+}
+
+impl AutoLevel {
+    pub const fn new() -> Self {
+        Self { level: None }
+    }
+
+    /// Returns the level for the `ambient` reading with `auto`.  The last
+    /// level is kept while it is within `HYSTERESIS` of the reading.
+    pub fn update(&mut self, auto: &AutoBrightness, ambient: u16) -> u8 {
+        let lower = auto.level(ambient.saturating_sub(HYSTERESIS));
+        let upper = auto.level(ambient.saturating_add(HYSTERESIS));
+        let range = lower.min(upper)..=lower.max(upper);
+        let level = match self.level {
+            Some(level) if range.contains(&level) => level,
+            _ => auto.level(ambient),
+        };
+        self.level = Some(level);
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Darkness reads high, as on the bot.
+    const AUTO: AutoBrightness = AutoBrightness {
+        dark: 0xe00,
+        bright: 0x200,
+        min: 5,
+        max: 80,
+    };
+
+    #[test]
+    fn smoother_starts_at_first_sample() {
+        let mut smoother = Smoother::new();
+        assert_eq!(smoother.update(1000), 1000);
+        assert_eq!(smoother.update(1000), 1000);
+    }
+
+    #[test]
+    fn smoother_follows_step() {
+        let mut smoother = Smoother::new();
+        smoother.update(0);
+        let first = smoother.update(800);
+        assert_eq!(first, 100);
+        let mut last = first;
+        for _ in 0..100 {
+            let value = smoother.update(800);
+            assert!(value >= last && value <= 800);
+            last = value;
+        }
+        assert!(last >= 795, "{last}");
+    }
+
+    #[test]
+    fn smoother_keeps_small_changes() {
+        // One count a sample still moves the average, thanks to the extra
+        // precision.
+        let mut smoother = Smoother::new();
+        smoother.update(100);
+        let value = (0..50).map(|_| smoother.update(101)).last().unwrap();
+        assert_eq!(value, 101);
+    }
+
+    #[test]
+    fn smoother_full_range() {
+        let mut smoother = Smoother::new();
+        smoother.update(u16::MAX);
+        assert_eq!(smoother.update(u16::MAX), u16::MAX);
+        assert!(smoother.update(0) < u16::MAX);
+    }
+
+    #[test]
+    fn level_clamps() {
+        assert_eq!(AUTO.level(0xfff), 5);
+        assert_eq!(AUTO.level(0xe00), 5);
+        assert_eq!(AUTO.level(0x200), 80);
+        assert_eq!(AUTO.level(0), 80);
+        assert_eq!(AUTO.level(0x800), 42);
+        let too_bright = AutoBrightness { max: 200, ..AUTO };
+        assert_eq!(too_bright.level(0), 100);
+    }
+
+    #[test]
+    fn level_with_dark_equal_to_bright() {
+        let auto = AutoBrightness::new(0x800, 0x800, 5, 80);
+        assert_eq!(auto.level(0), 80);
+        assert_eq!(auto.level(0x800), 80);
+        assert_eq!(auto.level(0xfff), 80);
+    }
+
+    #[test]
+    fn level_with_min_above_max() {
+        // Dimmer in brighter light.
+        let auto = AutoBrightness {
+            min: 80,
+            max: 5,
+            ..AUTO
+        };
+        assert_eq!(auto.level(0xe00), 80);
+        assert_eq!(auto.level(0x200), 5);
+        assert!(auto.level(0x600) < auto.level(0xa00));
+    }
+
+    #[test]
+    fn auto_level_ignores_noise() {
+        let mut auto_level = AutoLevel::new();
+        let level = auto_level.update(&AUTO, 0x800);
+        for noise in [0, 20, -20, 31, -31, 5] {
+            let ambient = (0x800 + noise) as u16;
+            assert_eq!(auto_level.update(&AUTO, ambient), level, "{noise}");
+        }
+    }
+
+    #[test]
+    fn auto_level_follows_light() {
+        let mut auto_level = AutoLevel::new();
+        let mut last = auto_level.update(&AUTO, 0xe00);
+        assert_eq!(last, 5);
+        for ambient in (0x200..0xe00).rev().step_by(8) {
+            let level = auto_level.update(&AUTO, ambient);
+            assert!(level >= last);
+            assert!(level.abs_diff(AUTO.level(ambient)) <= 2, "{ambient}");
+            last = level;
+        }
+        assert_eq!(auto_level.update(&AUTO, 0x100), 80);
+        assert_eq!(auto_level.update(&AUTO, 0xfff), 5);
+    }
+
+    #[test]
+    fn auto_level_follows_settings() {
+        let mut auto_level = AutoLevel::new();
+        assert_eq!(auto_level.update(&AUTO, 0x200), 80);
+        let dimmer = AutoBrightness { max: 50, ..AUTO };
+        assert_eq!(auto_level.update(&dimmer, 0x200), 50);
+    }
+}
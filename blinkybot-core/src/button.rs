@@ -0,0 +1,257 @@
+//! Button gesture detection.
+//!
+//! `Button` debounces the raw level of a button and turns presses into
+//! `Gesture`s.  A press is only reported once the double press window has
+//! passed, so a double press never also reports a press.
+
+use blinkybot_rpc::Gesture;
+
+/// Timings of button gestures in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ButtonTiming {
+    /// How long the level has to be stable to count.
+    pub debounce_ms: u64,
+    /// How long a button has to be held for a long press.
+    pub long_press_ms: u64,
+    /// Longest time from releasing the button to pressing it again for a
+    /// double press.
+    pub double_press_ms: u64,
+}
+
+impl Default for ButtonTiming {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 20,
+            long_press_ms: 600,
+            double_press_ms: 300,
+        }
+    }
+}
+
+pub struct Button {
+    timing: ButtonTiming,
+    /// Last raw level and when it was seen.
+    raw: bool,
+    raw_since_ms: u64,
+    /// Debounced level.
+    pressed: bool,
+    /// When the debounced press started.
+    pressed_at_ms: u64,
+    /// The current press has already been reported, as a long press or the
+    /// second half of a double press.
+    handled: bool,
+    /// Release of a press that may still become a double press.
+    released_at_ms: Option<u64>,
+}
+
+impl Button {
+    pub fn new(timing: ButtonTiming) -> Self {
+        Self {
+            timing,
+            raw: false,
+            raw_since_ms: 0,
+            pressed: false,
+            pressed_at_ms: 0,
+            handled: false,
+            released_at_ms: None,
+        }
+    }
+
+    /// Records the raw level of the button at `now_ms`, true if pressed.
+    /// Only the latest level is kept so call `update()` after each change.
+    pub fn set_level(&mut self, now_ms: u64, pressed: bool) {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since_ms = now_ms;
+        }
+    }
+
+    /// Returns the next gesture completed by `now_ms`.  Call until it
+    /// returns `None`.
+    pub fn update(&mut self, now_ms: u64) -> Option<Gesture> {
+        // Gestures that completed before the level last changed come first.
+        let edge_ms = self.edge_ms().filter(|&at_ms| at_ms <= now_ms);
+        let before_edge = |at_ms: u64| at_ms <= now_ms && edge_ms.is_none_or(|edge| at_ms <= edge);
+
+        if self.long_press_ms().is_some_and(before_edge) {
+            self.handled = true;
+            return Some(Gesture::LongPress);
+        }
+        if self.double_press_expiry_ms().is_some_and(before_edge) {
+            self.released_at_ms = None;
+            return Some(Gesture::Press);
+        }
+
+        let at_ms = edge_ms?;
+        self.pressed = self.raw;
+        if self.pressed {
+            self.pressed_at_ms = at_ms;
+            self.handled = self.released_at_ms.take().is_some();
+            if self.handled {
+                return Some(Gesture::DoublePress);
+            }
+        } else if !self.handled {
+            self.released_at_ms = Some(at_ms);
+        }
+        self.update(now_ms)
+    }
+
+    /// Returns when `update()` next needs calling if the level doesn't
+    /// change, or `None` if nothing is pending.
+    pub fn deadline_ms(&self) -> Option<u64> {
+        [
+            self.edge_ms(),
+            self.long_press_ms(),
+            self.double_press_expiry_ms(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// When the raw level becomes the debounced level.
+    fn edge_ms(&self) -> Option<u64> {
+        (self.raw != self.pressed).then_some(self.raw_since_ms + self.timing.debounce_ms)
+    }
+
+    /// When the current press becomes a long press.
+    fn long_press_ms(&self) -> Option<u64> {
+        (self.pressed && !self.handled).then_some(self.pressed_at_ms + self.timing.long_press_ms)
+    }
+
+    /// When the last press can no longer become a double press.
+    fn double_press_expiry_ms(&self) -> Option<u64> {
+        self.released_at_ms
+            .map(|released_at_ms| released_at_ms + self.timing.double_press_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MockClock, MonotonicClock};
+
+    /// Drives a `Button` from a mock clock like the firmware does.
+    struct Harness {
+        clock: MockClock,
+        button: Button,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            Self {
+                clock: MockClock::new(0),
+                button: Button::new(ButtonTiming::default()),
+            }
+        }
+
+        /// Sets the raw level at `at_ms` and returns the gestures completed.
+        fn level(&mut self, at_ms: u64, pressed: bool) -> Vec<Gesture> {
+            self.clock.set(at_ms);
+            self.button.set_level(at_ms, pressed);
+            self.update()
+        }
+
+        /// Returns the gestures completed by `at_ms`.
+        fn at(&mut self, at_ms: u64) -> Vec<Gesture> {
+            self.clock.set(at_ms);
+            self.update()
+        }
+
+        fn update(&mut self) -> Vec<Gesture> {
+            let now_ms = self.clock.now_ms();
+            core::iter::from_fn(|| self.button.update(now_ms)).collect()
+        }
+    }
+
+    #[test]
+    fn press_waits_out_the_double_press_window() {
+        let mut h = Harness::new();
+        assert_eq!(h.level(0, true), []);
+        assert_eq!(h.button.deadline_ms(), Some(20));
+        assert_eq!(h.at(20), []);
+        assert_eq!(h.button.deadline_ms(), Some(620));
+        assert_eq!(h.level(100, false), []);
+        assert_eq!(h.at(120), []);
+        assert_eq!(h.button.deadline_ms(), Some(420));
+        assert_eq!(h.at(419), []);
+        assert_eq!(h.at(420), [Gesture::Press]);
+        assert_eq!(h.button.deadline_ms(), None);
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut h = Harness::new();
+        h.level(0, true);
+        h.level(10, false);
+        assert_eq!(h.button.deadline_ms(), None);
+        assert_eq!(h.at(1000), []);
+
+        // Bouncing restarts the debounce.
+        h.level(1000, true);
+        h.level(1005, false);
+        h.level(1010, true);
+        assert_eq!(h.at(1020), []);
+        assert_eq!(h.button.deadline_ms(), Some(1030));
+        h.at(1030);
+        assert_eq!(h.button.deadline_ms(), Some(1630));
+    }
+
+    #[test]
+    fn long_press_is_reported_while_held() {
+        let mut h = Harness::new();
+        h.level(0, true);
+        h.at(20);
+        assert_eq!(h.at(619), []);
+        assert_eq!(h.at(620), [Gesture::LongPress]);
+        assert_eq!(h.at(2000), []);
+        h.level(2000, false);
+        assert_eq!(h.at(2020), []);
+        assert_eq!(h.button.deadline_ms(), None);
+    }
+
+    #[test]
+    fn double_press_replaces_the_press() {
+        let mut h = Harness::new();
+        h.level(0, true);
+        h.at(20);
+        h.level(100, false);
+        h.at(120);
+        h.level(200, true);
+        assert_eq!(h.at(220), [Gesture::DoublePress]);
+
+        // Holding the second press is not a long press.
+        assert_eq!(h.at(1000), []);
+        h.level(1000, false);
+        assert_eq!(h.at(1020), []);
+        assert_eq!(h.at(5000), []);
+    }
+
+    #[test]
+    fn slow_second_press_is_two_presses() {
+        let mut h = Harness::new();
+        h.level(0, true);
+        h.at(20);
+        h.level(100, false);
+        h.at(120);
+        assert_eq!(h.at(420), [Gesture::Press]);
+        h.level(500, true);
+        assert_eq!(h.at(520), []);
+        h.level(600, false);
+        h.at(620);
+        assert_eq!(h.at(920), [Gesture::Press]);
+    }
+
+    #[test]
+    fn late_updates_keep_gestures_in_order() {
+        let mut h = Harness::new();
+        h.level(0, true);
+        h.at(20);
+        h.level(100, false);
+        // Released long before it would have been a long press.
+        assert_eq!(h.at(2000), [Gesture::Press]);
+
+        h.level(3000, true);
+        assert_eq!(h.at(5000), [Gesture::LongPress]);
+    }
+}
@@ -0,0 +1,43 @@
+//! Time sources.
+
+use core::cell::Cell;
+
+/// Monotonic time in milliseconds since an arbitrary start.
+pub trait MonotonicClock {
+    fn now_ms(&self) -> u64;
+}
+
+impl<C: MonotonicClock> MonotonicClock for &C {
+    fn now_ms(&self) -> u64 {
+        (*self).now_ms()
+    }
+}
+
+/// A clock that only moves when told to, for running state machines on the
+/// host.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now_ms: Cell<u64>,
+}
+
+impl MockClock {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            now_ms: Cell::new(now_ms),
+        }
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms.set(now_ms);
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now_ms.set(self.now_ms.get() + ms);
+    }
+}
+
+impl MonotonicClock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.get()
+    }
+}
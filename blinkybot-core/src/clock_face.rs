@@ -0,0 +1,381 @@
+//! Drawing the time for `FaceMode::Clock`.
+//!
+//! The time is drawn as HH:MM in a 3x5 font, which just fits the 15 pixel
+//! wide display with the colon squeezed in between the digits.  The date
+//! doesn't fit so it is scrolled past as YYYY-MM-DD.  `ClockFace` cycles
+//! between the time, the date and the face as set in `ClockConfig`.
+
+use blinkybot_rpc::{ClockConfig, Expression, WallTime, MILLIS_PER_DAY};
+
+use crate::clock::MonotonicClock;
+
+/// Time between steps of the scrolling date.
+pub const SCROLL_STEP_MS: u64 = 80;
+
+const GLYPH_HEIGHT: u8 = 5;
+const MILLIS_PER_MINUTE: u32 = 60 * 1000;
+
+/// Returns the columns of the glyph for `c`, bit `y` of each being row `y`.
+/// Anything without a glyph is a space.
+fn glyph(c: u8) -> &'static [u8] {
+    match c {
+        b'0' => &[0x1f, 0x11, 0x1f],
+        b'1' => &[0x12, 0x1f, 0x10],
+        b'2' => &[0x1d, 0x15, 0x17],
+        b'3' => &[0x15, 0x15, 0x1f],
+        b'4' => &[0x07, 0x04, 0x1f],
+        b'5' => &[0x17, 0x15, 0x1d],
+        b'6' => &[0x1f, 0x15, 0x1d],
+        b'7' => &[0x01, 0x01, 0x1f],
+        b'8' => &[0x1f, 0x15, 0x1f],
+        b'9' => &[0x17, 0x15, 0x1f],
+        b':' => &[0x0a],
+        b'-' => &[0x04, 0x04],
+        _ => &[0, 0, 0],
+    }
+}
+
+/// Returns the columns of `text`, with a blank column between glyphs.
+fn columns(text: &[u8]) -> impl Iterator<Item = u8> + Clone + '_ {
+    text.iter().enumerate().flat_map(|(i, &c)| {
+        let space = (i > 0).then_some(0);
+        space.into_iter().chain(glyph(c).iter().copied())
+    })
+}
+
+/// Draws `columns` with the first at `x`, centered vertically.
+fn draw(columns: impl Iterator<Item = u8>, x: i32, width: u8, height: u8) -> Expression {
+    let top = height.saturating_sub(GLYPH_HEIGHT) / 2;
+    let mut expression = Expression::default();
+    for (column, bits) in (x..).zip(columns) {
+        if !(0..i32::from(width)).contains(&column) {
+            continue;
+        }
+        for row in 0..GLYPH_HEIGHT.min(height) {
+            if bits & (1 << row) != 0 {
+                expression.set_pixel(column as u32, u32::from(top + row), true);
+            }
+        }
+    }
+    expression
+}
+
+/// Returns `time` as HH:MM, or `--:--` if the time isn't known.  Single
+/// digit hours are padded with a space in 12 hour time.
+pub fn time_text(time: Option<WallTime>, hours_24: bool) -> [u8; 5] {
+    let Some(time) = time else {
+        return *b"--:--";
+    };
+    let minute = time.local_minute_of_day();
+    let (mut hour, minute) = ((minute / 60) as u8, (minute % 60) as u8);
+    if !hours_24 {
+        hour = match hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+    }
+    let tens = match hour / 10 {
+        0 if !hours_24 => b' ',
+        tens => b'0' + tens,
+    };
+    [
+        tens,
+        b'0' + hour % 10,
+        b':',
+        b'0' + minute / 10,
+        b'0' + minute % 10,
+    ]
+}
+
+/// Returns the local date of `time` as YYYY-MM-DD.
+pub fn date_text(time: WallTime) -> [u8; 10] {
+    let local = time.unix_millis as i64 + i64::from(time.utc_offset_minutes) * 60 * 1000;
+    let days = local.div_euclid(MILLIS_PER_DAY.into());
+
+    // Days to the civil calendar, from Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let digit = |value: i64, place: i64| b'0' + (value / place % 10) as u8;
+    [
+        digit(year, 1000),
+        digit(year, 100),
+        digit(year, 10),
+        digit(year, 1),
+        b'-',
+        digit(month, 10),
+        digit(month, 1),
+        b'-',
+        digit(day, 10),
+        digit(day, 1),
+    ]
+}
+
+/// Draws `time` as HH:MM, centered on the display.
+pub fn render_time(time: Option<WallTime>, hours_24: bool, width: u8, height: u8) -> Expression {
+    let text = time_text(time, hours_24);
+    // The colon goes without the usual spacing to fit in 15 pixels.
+    let time_columns = columns(&text[..2])
+        .chain(glyph(b':').iter().copied())
+        .chain(columns(&text[3..]));
+    let len = time_columns.clone().count() as i32;
+    let x = (i32::from(width) - len).max(0) / 2;
+    draw(time_columns, x, width, height)
+}
+
+/// What `ClockFace` shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Time,
+    Date,
+    Face,
+}
+
+/// Cycles between the time, the scrolling date and the face.
+pub struct ClockFace<C: MonotonicClock> {
+    clock: C,
+    width: u8,
+    height: u8,
+    config: ClockConfig,
+    /// When the current cycle started.
+    start_ms: u64,
+}
+
+impl<C: MonotonicClock> ClockFace<C> {
+    pub fn new(clock: C, width: u8, height: u8, config: ClockConfig) -> Self {
+        let start_ms = clock.now_ms();
+        Self {
+            clock,
+            width,
+            height,
+            config,
+            start_ms,
+        }
+    }
+
+    pub fn set_config(&mut self, config: ClockConfig) {
+        self.config = config;
+        self.restart();
+    }
+
+    /// Starts the cycle again from the time, e.g. when the clock is shown.
+    pub fn restart(&mut self) {
+        self.start_ms = self.clock.now_ms();
+    }
+
+    fn date_steps(&self) -> u64 {
+        (columns(b"0000-00-00").count() + usize::from(self.width)) as u64
+    }
+
+    /// Returns the current phase, how long it has been shown for and how
+    /// much longer it will be, in milliseconds.  The date is skipped while
+    /// `time` isn't known.
+    fn phase(&self, time: Option<WallTime>) -> (Phase, u64, u64) {
+        let time_ms = u64::from(self.config.clock_secs.max(1)) * 1000;
+        let date_ms = match time {
+            Some(_) if self.config.show_date => self.date_steps() * SCROLL_STEP_MS,
+            _ => 0,
+        };
+        let face_ms = u64::from(self.config.face_secs) * 1000;
+
+        let elapsed = self.clock.now_ms().saturating_sub(self.start_ms);
+        let elapsed = elapsed % (time_ms + date_ms + face_ms);
+        if elapsed < time_ms {
+            (Phase::Time, elapsed, time_ms - elapsed)
+        } else if elapsed < time_ms + date_ms {
+            let elapsed = elapsed - time_ms;
+            (Phase::Date, elapsed, date_ms - elapsed)
+        } else {
+            let elapsed = elapsed - time_ms - date_ms;
+            (Phase::Face, elapsed, face_ms - elapsed)
+        }
+    }
+
+    /// Returns the frame to show when the time is `time`, or `None` while
+    /// the face should be shown.
+    pub fn frame(&self, time: Option<WallTime>) -> Option<Expression> {
+        match (self.phase(time), time) {
+            ((Phase::Date, elapsed, _), Some(time)) => {
+                let step = (elapsed / SCROLL_STEP_MS) as i32;
+                let text = date_text(time);
+                let x = i32::from(self.width) - step;
+                Some(draw(columns(&text), x, self.width, self.height))
+            }
+            ((Phase::Face, _, _), _) => None,
+            _ => Some(render_time(
+                time,
+                self.config.hours_24,
+                self.width,
+                self.height,
+            )),
+        }
+    }
+
+    /// Returns when `frame()` next changes: the next step of the date, the
+    /// next minute or the end of the phase.
+    pub fn deadline_ms(&self, time: Option<WallTime>) -> u64 {
+        let now = self.clock.now_ms();
+        let (phase, elapsed, remaining) = self.phase(time);
+        let change = match (phase, time) {
+            (Phase::Time, Some(time)) => {
+                let into_minute = time.local_millis_of_day() % MILLIS_PER_MINUTE;
+                now + u64::from(MILLIS_PER_MINUTE - into_minute)
+            }
+            (Phase::Date, _) => now + SCROLL_STEP_MS - elapsed % SCROLL_STEP_MS,
+            _ => u64::MAX,
+        };
+        change.min(now + remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// 2024-02-29 23:30 UTC.
+    const LEAP_NIGHT_MS: u64 = 1_709_249_400_000;
+
+    fn at(hour: u64, minute: u64) -> Option<WallTime> {
+        Some(WallTime::new((hour * 60 + minute) * 60 * 1000, 0))
+    }
+
+    fn phases(
+        face: &ClockFace<&MockClock>,
+        clock: &MockClock,
+        time: Option<WallTime>,
+    ) -> Vec<Phase> {
+        (0..4)
+            .map(|_| {
+                let (phase, _, remaining) = face.phase(time);
+                clock.advance(remaining);
+                phase
+            })
+            .collect()
+    }
+
+    #[test]
+    fn time_in_12_hours() {
+        assert_eq!(&time_text(at(0, 5), false), b"12:05");
+        assert_eq!(&time_text(at(1, 5), false), b" 1:05");
+        assert_eq!(&time_text(at(12, 0), false), b"12:00");
+        assert_eq!(&time_text(at(13, 5), false), b" 1:05");
+        assert_eq!(&time_text(at(23, 59), false), b"11:59");
+        assert_eq!(&time_text(None, false), b"--:--");
+    }
+
+    #[test]
+    fn time_in_24_hours() {
+        assert_eq!(&time_text(at(0, 5), true), b"00:05");
+        assert_eq!(&time_text(at(1, 5), true), b"01:05");
+        assert_eq!(&time_text(at(13, 5), true), b"13:05");
+        assert_eq!(&time_text(None, true), b"--:--");
+    }
+
+    #[test]
+    fn time_uses_utc_offset() {
+        let time = WallTime::new(LEAP_NIGHT_MS, 90);
+        assert_eq!(&time_text(Some(time), true), b"01:00");
+        let time = WallTime::new(LEAP_NIGHT_MS, -23 * 60 - 30);
+        assert_eq!(&time_text(Some(time), true), b"00:00");
+    }
+
+    #[test]
+    fn date_rolls_over_with_utc_offset() {
+        assert_eq!(&date_text(WallTime::new(0, 0)), b"1970-01-01");
+        assert_eq!(&date_text(WallTime::new(LEAP_NIGHT_MS, 0)), b"2024-02-29");
+        assert_eq!(&date_text(WallTime::new(LEAP_NIGHT_MS, 30)), b"2024-03-01");
+        assert_eq!(&date_text(WallTime::new(LEAP_NIGHT_MS, 29)), b"2024-02-29");
+        // 2024-03-01 00:30 UTC is still February behind UTC.
+        let time = WallTime::new(LEAP_NIGHT_MS + 60 * 60 * 1000, -31);
+        assert_eq!(&date_text(time), b"2024-02-29");
+        // 2023-02-28 23:30 UTC, without a leap day to roll over to.
+        let time = WallTime::new(LEAP_NIGHT_MS - 366 * MILLIS_PER_DAY as u64, 30);
+        assert_eq!(&date_text(time), b"2023-03-01");
+    }
+
+    #[test]
+    fn cycles_time_date_face() {
+        let clock = MockClock::new(1000);
+        let face = ClockFace::new(&clock, 15, 7, ClockConfig::new(true, true, 2, 3));
+        let time = Some(WallTime::new(LEAP_NIGHT_MS, 0));
+        let date_ms = face.date_steps() * SCROLL_STEP_MS;
+
+        assert_eq!(face.phase(time), (Phase::Time, 0, 2000));
+        assert_eq!(face.frame(time), Some(render_time(time, true, 15, 7)));
+        clock.advance(1999);
+        assert_eq!(face.phase(time).0, Phase::Time);
+        clock.advance(1);
+        assert_eq!(face.phase(time), (Phase::Date, 0, date_ms));
+        assert_ne!(face.frame(time), Some(render_time(time, true, 15, 7)));
+        clock.advance(date_ms);
+        assert_eq!(face.phase(time), (Phase::Face, 0, 3000));
+        assert_eq!(face.frame(time), None);
+        clock.advance(3000);
+        assert_eq!(face.phase(time), (Phase::Time, 0, 2000));
+    }
+
+    #[test]
+    fn date_scrolls_in_steps() {
+        let clock = MockClock::new(0);
+        let face = ClockFace::new(&clock, 15, 7, ClockConfig::new(true, true, 1, 0));
+        let time = Some(WallTime::new(LEAP_NIGHT_MS, 0));
+        clock.set(1000);
+        // The date starts just off the right edge.
+        assert_eq!(face.frame(time), Some(Expression::default()));
+        assert_eq!(face.deadline_ms(time), 1000 + SCROLL_STEP_MS);
+        clock.advance(SCROLL_STEP_MS - 1);
+        assert_eq!(face.frame(time), Some(Expression::default()));
+        assert_eq!(face.deadline_ms(time), 1000 + SCROLL_STEP_MS);
+        clock.advance(1);
+        assert_ne!(face.frame(time), Some(Expression::default()));
+    }
+
+    #[test]
+    fn no_face_without_face_secs() {
+        let clock = MockClock::new(0);
+        let face = ClockFace::new(&clock, 15, 7, ClockConfig::new(true, true, 2, 0));
+        let time = Some(WallTime::new(LEAP_NIGHT_MS, 0));
+        assert_eq!(
+            phases(&face, &clock, time),
+            [Phase::Time, Phase::Date, Phase::Time, Phase::Date]
+        );
+
+        let face = ClockFace::new(&clock, 15, 7, ClockConfig::new(true, false, 2, 0));
+        assert_eq!(phases(&face, &clock, time), [Phase::Time; 4]);
+        assert!(face.frame(time).is_some());
+        assert!(face.deadline_ms(time) < u64::MAX);
+    }
+
+    #[test]
+    fn no_date_without_time() {
+        let clock = MockClock::new(0);
+        let face = ClockFace::new(&clock, 15, 7, ClockConfig::new(false, true, 2, 3));
+        assert_eq!(
+            phases(&face, &clock, None),
+            [Phase::Time, Phase::Face, Phase::Time, Phase::Face]
+        );
+        assert_eq!(face.frame(None), Some(render_time(None, false, 15, 7)));
+        assert_eq!(face.deadline_ms(None), clock.now_ms() + 2000);
+    }
+
+    #[test]
+    fn restart_shows_time() {
+        let clock = MockClock::new(0);
+        let mut face = ClockFace::new(&clock, 15, 7, ClockConfig::new(true, false, 2, 3));
+        clock.advance(2500);
+        assert_eq!(face.phase(None).0, Phase::Face);
+        face.restart();
+        assert_eq!(face.phase(None), (Phase::Time, 0, 2000));
+        clock.advance(2500);
+        face.set_config(ClockConfig::new(true, false, 5, 3));
+        assert_eq!(face.phase(None), (Phase::Time, 0, 5000));
+    }
+}
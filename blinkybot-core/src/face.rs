@@ -0,0 +1,525 @@
+//! Procedural faces.
+//!
+//! A face is described by a handful of `FaceParams` which are rendered into
+//! an `Expression` for any display size.  `Animator` eases the parameters
+//! towards a target one frame at a time so that the face changes smoothly
+//! instead of jumping between drawings.
+
+use blinkybot_rpc::{Expression, Mood, MAX_HEIGHT, MAX_WIDTH};
+
+use crate::clock::MonotonicClock;
+
+/// Time each frame of an animation takes.
+pub const FRAME_MS: u64 = 33;
+/// Fraction of the remaining distance to the target covered each frame.
+const EASING: f32 = 0.25;
+/// Parameters closer than this to their target snap to it.
+const SETTLED: f32 = 0.01;
+/// Frames a blink takes to close the eyes and open them again.
+const BLINK_FRAMES: u8 = 6;
+
+/// Shape of a face.
+///
+/// Distances are in pixels and positions are relative to the middle of the
+/// display, with y growing downwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaceParams {
+    pub eye_width: f32,
+    pub eye_height: f32,
+    /// Distance between the middles of the eyes.
+    pub eye_spacing: f32,
+    /// Vertical position of the middle of the eyes.
+    pub eye_y: f32,
+    /// Diameter of the pupils, which are drawn as unlit holes in the eyes.
+    pub pupil_size: f32,
+    /// Where the pupils point, from -1.0 (left) to 1.0 (right).
+    pub gaze_x: f32,
+    /// Where the pupils point, from -1.0 (up) to 1.0 (down).
+    pub gaze_y: f32,
+    /// How far the eyelids are open, from 0.0 (closed) to 1.0.
+    pub openness: f32,
+    pub mouth_width: f32,
+    /// Vertical position of the top of the mouth.
+    pub mouth_y: f32,
+    /// How far the middle of the mouth sits below its corners.  Negative
+    /// values frown.
+    pub mouth_curve: f32,
+}
+
+impl Default for FaceParams {
+    /// A content face sized for a 15x7 display.
+    fn default() -> Self {
+        Self {
+            eye_width: 4.0,
+            eye_height: 4.0,
+            eye_spacing: 8.0,
+            eye_y: -1.0,
+            pupil_size: 1.5,
+            gaze_x: 0.0,
+            gaze_y: 0.0,
+            openness: 1.0,
+            mouth_width: 5.0,
+            mouth_y: 1.5,
+            mouth_curve: 1.0,
+        }
+    }
+}
+
+impl FaceParams {
+    /// Returns the default face adjusted to show `mood`.
+    pub fn for_mood(mood: Mood) -> Self {
+        let mut params = Self::default();
+        match mood {
+            Mood::Idle => {}
+            Mood::Happy => {
+                params.eye_height = 5.0;
+                params.mouth_curve = 1.5;
+            }
+            Mood::Sleepy => {
+                params.openness = 0.3;
+                params.gaze_y = 1.0;
+                params.mouth_curve = 0.0;
+            }
+            Mood::Surprised => {
+                params.eye_width = 5.0;
+                params.eye_height = 5.0;
+                params.pupil_size = 1.0;
+                params.mouth_width = 3.0;
+                params.mouth_curve = 0.5;
+            }
+            Mood::Bored => {
+                params.openness = 0.6;
+                params.mouth_curve = 0.0;
+            }
+        }
+        params
+    }
+
+    fn fields(&self) -> [f32; 11] {
+        [
+            self.eye_width,
+            self.eye_height,
+            self.eye_spacing,
+            self.eye_y,
+            self.pupil_size,
+            self.gaze_x,
+            self.gaze_y,
+            self.openness,
+            self.mouth_width,
+            self.mouth_y,
+            self.mouth_curve,
+        ]
+    }
+
+    fn fields_mut(&mut self) -> [&mut f32; 11] {
+        [
+            &mut self.eye_width,
+            &mut self.eye_height,
+            &mut self.eye_spacing,
+            &mut self.eye_y,
+            &mut self.pupil_size,
+            &mut self.gaze_x,
+            &mut self.gaze_y,
+            &mut self.openness,
+            &mut self.mouth_width,
+            &mut self.mouth_y,
+            &mut self.mouth_curve,
+        ]
+    }
+
+    /// Moves every parameter `amount`, from 0.0 to 1.0, of the way towards
+    /// `target`.  Parameters that end up close to their target snap to it.
+    pub fn approach(&mut self, target: &FaceParams, amount: f32) {
+        for (field, target) in self.fields_mut().into_iter().zip(target.fields()) {
+            let next = *field + (target - *field) * amount;
+            *field = if abs(target - next) < SETTLED {
+                target
+            } else {
+                next
+            };
+        }
+    }
+
+    /// Renders the face centered on a `width` by `height` display.
+    pub fn render(&self, width: u8, height: u8) -> Expression {
+        let mut face = Expression::default();
+        let width = (width as u32).min(MAX_WIDTH);
+        let height = (height as u32).min(MAX_HEIGHT);
+        let mid_x = width as f32 / 2.0;
+        let mid_y = height as f32 / 2.0;
+
+        let eye_y = mid_y + self.eye_y;
+        for eye_x in [
+            mid_x - self.eye_spacing / 2.0,
+            mid_x + self.eye_spacing / 2.0,
+        ] {
+            self.draw_eye(&mut face, eye_x, eye_y, width, height);
+        }
+        self.draw_mouth(&mut face, mid_x, mid_y + self.mouth_y, width, height);
+        face
+    }
+
+    fn draw_eye(&self, face: &mut Expression, cx: f32, cy: f32, width: u32, height: u32) {
+        let rx = self.eye_width / 2.0;
+        let ry = self.eye_height / 2.0;
+        let pupil_r = self.pupil_size / 2.0;
+        let pupil_x = cx + self.gaze_x.clamp(-1.0, 1.0) * (rx - pupil_r).max(0.0);
+        let pupil_y = cy + self.gaze_y.clamp(-1.0, 1.0) * (ry - pupil_r).max(0.0);
+        let open = ry * self.openness.clamp(0.0, 1.0);
+        // Eyes open less than a pixel are drawn as a line through their
+        // middle.
+        let closed = open < 0.5;
+        let middle_row = floor(cy);
+
+        for y in 0..height {
+            for x in 0..width {
+                let px = x as f32 + 0.5;
+                let py = y as f32 + 0.5;
+                if !in_ellipse(px - cx, py - cy, rx, ry) {
+                    continue;
+                }
+                let lit = if !closed && abs(py - cy) <= open {
+                    !in_ellipse(px - pupil_x, py - pupil_y, pupil_r, pupil_r)
+                } else {
+                    y as i32 == middle_row
+                };
+                if lit {
+                    face.set_pixel(x, y, true);
+                }
+            }
+        }
+    }
+
+    fn draw_mouth(&self, face: &mut Expression, cx: f32, top: f32, width: u32, height: u32) {
+        let half_width = self.mouth_width / 2.0;
+        if half_width <= 0.0 {
+            return;
+        }
+        for x in 0..width {
+            let dx = (x as f32 + 0.5 - cx) / half_width;
+            if abs(dx) > 1.0 {
+                continue;
+            }
+            let y = if self.mouth_curve >= 0.0 {
+                floor(top + self.mouth_curve * (1.0 - dx * dx))
+            } else {
+                floor(top - self.mouth_curve * dx * dx)
+            };
+            if (0..height as i32).contains(&y) {
+                face.set_pixel(x, y as u32, true);
+            }
+        }
+    }
+}
+
+/// Animates a face by easing it towards a target, a frame every
+/// `FRAME_MS`.
+pub struct Animator<C: MonotonicClock> {
+    clock: C,
+    current: FaceParams,
+    target: FaceParams,
+    /// Frame of the blink in progress.
+    blink_frame: Option<u8>,
+    /// When the last frame started.
+    frame_ms: u64,
+}
+
+impl<C: MonotonicClock> Animator<C> {
+    pub fn new(clock: C, params: FaceParams) -> Self {
+        let frame_ms = clock.now_ms();
+        Self {
+            clock,
+            current: params,
+            target: params,
+            blink_frame: None,
+            frame_ms,
+        }
+    }
+
+    pub fn target(&self) -> &FaceParams {
+        &self.target
+    }
+
+    pub fn set_target(&mut self, target: FaceParams) {
+        self.wake();
+        self.target = target;
+    }
+
+    /// Points the pupils towards `x` and `y`, each from -1.0 to 1.0.
+    pub fn look(&mut self, x: f32, y: f32) {
+        self.wake();
+        self.target.gaze_x = x.clamp(-1.0, 1.0);
+        self.target.gaze_y = y.clamp(-1.0, 1.0);
+    }
+
+    /// Looks somewhere picked by `random`, mostly along the horizon.
+    pub fn wander(&mut self, random: u32) {
+        let x = (random & 0xff) as f32 / 127.5 - 1.0;
+        let y = ((random >> 8) & 0xff) as f32 / 127.5 - 1.0;
+        self.look(x, y / 2.0);
+    }
+
+    /// Starts a blink unless one is already in progress.
+    pub fn blink(&mut self) {
+        self.wake();
+        if self.blink_frame.is_none() {
+            self.blink_frame = Some(0);
+        }
+    }
+
+    /// Returns true until the face has reached its target and finished
+    /// blinking.
+    pub fn is_animating(&self) -> bool {
+        self.blink_frame.is_some() || self.current != self.target
+    }
+
+    /// Starts counting frames from now if the face was at rest, so that the
+    /// time spent at rest isn't animated all at once.
+    fn wake(&mut self) {
+        if !self.is_animating() {
+            self.frame_ms = self.clock.now_ms();
+        }
+    }
+
+    /// Advances the animation by the frames due since the last update.
+    pub fn update(&mut self) {
+        let now = self.clock.now_ms();
+        while self.is_animating() && now >= self.frame_ms + FRAME_MS {
+            self.frame_ms += FRAME_MS;
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        self.current.approach(&self.target, EASING);
+        self.blink_frame = self
+            .blink_frame
+            .map(|frame| frame + 1)
+            .filter(|&frame| frame < BLINK_FRAMES);
+    }
+
+    /// Returns the face to show for the current frame.
+    pub fn frame(&self) -> FaceParams {
+        let mut params = self.current;
+        if let Some(frame) = self.blink_frame {
+            // Close linearly over the first half of the blink and open
+            // over the second.
+            let half = (BLINK_FRAMES / 2) as f32;
+            params.openness *= abs(frame as f32 - half) / half;
+        }
+        params
+    }
+}
+
+fn abs(value: f32) -> f32 {
+    if value < 0.0 {
+        -value
+    } else {
+        value
+    }
+}
+
+fn floor(value: f32) -> i32 {
+    let truncated = value as i32;
+    if (truncated as f32) > value {
+        truncated - 1
+    } else {
+        truncated
+    }
+}
+
+fn in_ellipse(dx: f32, dy: f32, rx: f32, ry: f32) -> bool {
+    if rx <= 0.0 || ry <= 0.0 {
+        return false;
+    }
+    (dx * dx) / (rx * rx) + (dy * dy) / (ry * ry) <= 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// Returns the face drawn as rows of `#` for lit and `.` for unlit
+    /// pixels.
+    fn rows(face: &Expression, width: u32, height: u32) -> Vec<String> {
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| if face.get_pixel(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn renders_default_face() {
+        let face = FaceParams::default().render(15, 7);
+        assert_eq!(
+            rows(&face, 15, 7),
+            [
+                "...#.......#...",
+                "..###.....###..",
+                ".##.##...##.##.",
+                "..###.....###..",
+                "...#.......#...",
+                ".....##.##.....",
+                ".......#.......",
+            ]
+        );
+    }
+
+    #[test]
+    fn closed_eyes_are_a_line() {
+        let mut params = FaceParams::default();
+        for openness in [0.0, 0.2] {
+            params.openness = openness;
+            assert_eq!(
+                rows(&params.render(15, 7), 15, 7)[..5],
+                [
+                    "...............",
+                    "...............",
+                    ".#####...#####.",
+                    "...............",
+                    "...............",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn renders_frown() {
+        let params = FaceParams {
+            mouth_curve: -2.0,
+            ..FaceParams::default()
+        };
+        assert_eq!(
+            rows(&params.render(15, 7), 15, 7)[5..],
+            ["......###......", ".....#...#....."]
+        );
+    }
+
+    #[test]
+    fn flat_mouth() {
+        let params = FaceParams {
+            mouth_curve: 0.0,
+            ..FaceParams::default()
+        };
+        assert_eq!(
+            rows(&params.render(15, 7), 15, 7)[5..],
+            [".....#####.....", "..............."]
+        );
+    }
+
+    #[test]
+    fn size_is_clamped() {
+        let params = FaceParams::default();
+        let face = params.render(u8::MAX, u8::MAX);
+        assert_eq!(face, params.render(MAX_WIDTH as u8, MAX_HEIGHT as u8));
+        let rows = rows(&face, MAX_WIDTH, MAX_HEIGHT);
+        assert_eq!(rows[7], "..#..#....#..#..");
+        assert_eq!(rows[10], "......####......");
+    }
+
+    #[test]
+    fn nothing_outside_the_display() {
+        let params = FaceParams {
+            eye_spacing: 30.0,
+            mouth_width: 40.0,
+            mouth_y: 20.0,
+            ..FaceParams::default()
+        };
+        assert_eq!(params.render(15, 7), Expression::default());
+    }
+
+    #[test]
+    fn eases_towards_the_target_a_frame_at_a_time() {
+        let clock = MockClock::new(1000);
+        let mut animator = Animator::new(&clock, FaceParams::default());
+        animator.set_target(FaceParams::for_mood(Mood::Happy));
+
+        clock.advance(FRAME_MS - 1);
+        animator.update();
+        assert_eq!(animator.frame().eye_height, 4.0);
+
+        clock.advance(1);
+        animator.update();
+        assert_eq!(animator.frame().eye_height, 4.25);
+
+        clock.advance(FRAME_MS * 2);
+        animator.update();
+        assert_eq!(
+            animator.frame().eye_height,
+            4.25 + 0.75 * 0.25 + 0.75 * 0.75 * 0.25
+        );
+    }
+
+    #[test]
+    fn settles_on_the_target() {
+        let clock = MockClock::new(0);
+        let mut animator = Animator::new(&clock, FaceParams::default());
+        let target = FaceParams::for_mood(Mood::Surprised);
+        animator.set_target(target);
+        assert!(animator.is_animating());
+
+        clock.advance(FRAME_MS * 100);
+        animator.update();
+        assert!(!animator.is_animating());
+        assert_eq!(animator.frame(), target);
+    }
+
+    #[test]
+    fn time_at_rest_is_not_animated() {
+        let clock = MockClock::new(0);
+        let mut animator = Animator::new(&clock, FaceParams::default());
+        clock.advance(60_000);
+        animator.update();
+
+        animator.look(1.0, 0.0);
+        animator.update();
+        assert_eq!(animator.frame().gaze_x, 0.0);
+        clock.advance(FRAME_MS);
+        animator.update();
+        assert_eq!(animator.frame().gaze_x, 0.25);
+    }
+
+    #[test]
+    fn blinks_closed_and_open_again() {
+        let clock = MockClock::new(0);
+        let mut animator = Animator::new(&clock, FaceParams::default());
+        animator.blink();
+        assert!(animator.is_animating());
+        assert_eq!(animator.frame().openness, 1.0);
+
+        clock.advance(FRAME_MS * u64::from(BLINK_FRAMES / 2));
+        animator.update();
+        assert_eq!(animator.frame().openness, 0.0);
+
+        // Blinking again mid-blink doesn't start over.
+        animator.blink();
+        clock.advance(FRAME_MS);
+        animator.update();
+        assert!(animator.frame().openness > 0.0);
+
+        clock.advance(FRAME_MS * u64::from(BLINK_FRAMES));
+        animator.update();
+        assert!(!animator.is_animating());
+        assert_eq!(animator.frame().openness, 1.0);
+    }
+
+    #[test]
+    fn gaze_is_clamped() {
+        let clock = MockClock::new(0);
+        let mut animator = Animator::new(&clock, FaceParams::default());
+        animator.look(3.0, -2.0);
+        assert_eq!(animator.target().gaze_x, 1.0);
+        assert_eq!(animator.target().gaze_y, -1.0);
+
+        animator.wander(0);
+        assert_eq!(animator.target().gaze_x, -1.0);
+        assert_eq!(animator.target().gaze_y, -0.5);
+        animator.wander(0xffff);
+        assert_eq!(animator.target().gaze_x, 1.0);
+        assert_eq!(animator.target().gaze_y, 0.5);
+    }
+}
@@ -0,0 +1,158 @@
+//! Perceptual brightness to PWM conversion.
+//!
+//! The IS31FL3731 PWM registers are linear in light output which the eye
+//! perceives as very non-linear.  Everything above the display driver works
+//! in perceptual levels from 0 to [`MAX_BRIGHTNESS`] and is converted to a PWM
+//! duty here.  Non-zero levels always map to a non-zero duty so that the
+//! lowest setting never turns the display off.
+//!
+//! Partly lit pixels of transition frames are dimmed in perceptual levels
+//! too, with `Brightness::scaled_pwm()`, so fades follow the curve.
+
+use blinkybot_rpc::{BrightnessCurve, MAX_BRIGHTNESS};
+
+use crate::transition::FULL;
+
+/// `round(255 * (level / 100) ^ 2.2)`, raised to 1 for non-zero levels.
+const GAMMA22: [u8; MAX_BRIGHTNESS as usize + 1] = [
+    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 3, 3, 4, 5, 5, 6, 7, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    17, 18, 19, 21, 22, 24, 25, 27, 29, 30, 32, 34, 36, 38, 40, 42, 44, 46, 48, 51, 53, 55, 58, 60,
+    63, 66, 68, 71, 74, 77, 80, 83, 86, 89, 92, 96, 99, 102, 106, 109, 113, 116, 120, 124, 128,
+    131, 135, 139, 143, 148, 152, 156, 160, 165, 169, 174, 178, 183, 188, 192, 197, 202, 207, 212,
+    217, 223, 228, 233, 238, 244, 249, 255,
+];
+
+/// CIE 1931 lightness (L*) to relative luminance, scaled to 255 and rounded,
+/// raised to 1 for non-zero levels.
+const CIE1931: [u8; MAX_BRIGHTNESS as usize + 1] = [
+    0, 1, 1, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 6, 6, 7, 8, 8, 9, 10, 10, 11, 12, 13, 14,
+    15, 16, 17, 18, 19, 20, 22, 23, 24, 26, 27, 29, 30, 32, 34, 35, 37, 39, 41, 43, 45, 47, 49, 51,
+    54, 56, 58, 61, 64, 66, 69, 72, 75, 78, 81, 84, 87, 90, 93, 97, 100, 104, 108, 111, 115, 119,
+    123, 127, 131, 136, 140, 145, 149, 154, 159, 163, 168, 173, 179, 184, 189, 195, 200, 206, 212,
+    217, 223, 230, 236, 242, 248, 255,
+];
+
+/// A perceptual brightness level and the curve it is shown with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Brightness {
+    pub curve: BrightnessCurve,
+    /// From 0 to [`MAX_BRIGHTNESS`].
+    pub level: u8,
+}
+
+impl Brightness {
+    pub fn new(curve: BrightnessCurve, level: u8) -> Self {
+        Self { curve, level }
+    }
+
+    /// Returns the PWM duty of fully lit pixels.
+    pub fn pwm(self) -> u8 {
+        pwm(self.curve, self.level)
+    }
+
+    /// Returns the PWM duty of a pixel lit to `fraction` out of `FULL`.
+    pub fn scaled_pwm(self, fraction: u8) -> u8 {
+        let level =
+            (u16::from(self.level) * u16::from(fraction) + u16::from(FULL) / 2) / u16::from(FULL);
+        pwm(self.curve, level as u8)
+    }
+}
+
+/// Converts a perceptual `level` (0-100) into a PWM duty using `curve`.
+///
+/// Levels above [`MAX_BRIGHTNESS`] are clamped.
+pub fn pwm(curve: BrightnessCurve, level: u8) -> u8 {
+    let level = level.min(MAX_BRIGHTNESS);
+    match curve {
+        BrightnessCurve::Linear => {
+            ((level as u16 * 255 + MAX_BRIGHTNESS as u16 / 2) / MAX_BRIGHTNESS as u16) as u8
+        }
+        BrightnessCurve::Gamma22 => GAMMA22[level as usize],
+        BrightnessCurve::Cie1931 => CIE1931[level as usize],
+    }
+}
+
+/// Returns the perceptual level whose PWM duty using `curve` is nearest to
+/// `duty`, e.g. to migrate a raw PWM setting.
+pub fn level(curve: BrightnessCurve, duty: u8) -> u8 {
+    (0..=MAX_BRIGHTNESS)
+        .min_by_key(|&level| pwm(curve, level).abs_diff(duty))
+        .unwrap_or(MAX_BRIGHTNESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [BrightnessCurve; 3] = [
+        BrightnessCurve::Linear,
+        BrightnessCurve::Gamma22,
+        BrightnessCurve::Cie1931,
+    ];
+
+    #[test]
+    fn pwm_is_monotonic() {
+        for curve in CURVES {
+            assert_eq!(pwm(curve, 0), 0);
+            assert_eq!(pwm(curve, MAX_BRIGHTNESS), 255);
+            for level in 1..=MAX_BRIGHTNESS {
+                assert!(
+                    pwm(curve, level) >= pwm(curve, level - 1),
+                    "{curve:?} {level}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn non_zero_levels_light() {
+        for curve in CURVES {
+            for level in 1..=MAX_BRIGHTNESS {
+                assert_ne!(pwm(curve, level), 0, "{curve:?} {level}");
+            }
+        }
+    }
+
+    #[test]
+    fn pwm_clamps_level() {
+        for curve in CURVES {
+            assert_eq!(pwm(curve, MAX_BRIGHTNESS + 1), 255);
+            assert_eq!(pwm(curve, u8::MAX), 255);
+        }
+    }
+
+    #[test]
+    fn level_inverts_pwm() {
+        for curve in CURVES {
+            for x in 0..=MAX_BRIGHTNESS {
+                let back = level(curve, pwm(curve, x));
+                assert_eq!(pwm(curve, back), pwm(curve, x), "{curve:?} {x}");
+                // Low levels can share a duty, which maps back to the
+                // lowest of them.
+                if x == 0 || pwm(curve, x - 1) != pwm(curve, x) {
+                    assert_eq!(back, x, "{curve:?}");
+                }
+            }
+        }
+        assert_eq!(level(BrightnessCurve::Linear, 128), 50);
+        assert_eq!(level(BrightnessCurve::Gamma22, 255), MAX_BRIGHTNESS);
+    }
+
+    #[test]
+    fn scaled_pwm_follows_curve() {
+        let brightness = Brightness::new(BrightnessCurve::Gamma22, 80);
+        assert_eq!(brightness.scaled_pwm(FULL), brightness.pwm());
+        assert_eq!(brightness.scaled_pwm(0), 0);
+        // Half lit is half the perceptual level, not half the duty.
+        assert_eq!(
+            brightness.scaled_pwm(128),
+            pwm(BrightnessCurve::Gamma22, 40)
+        );
+        let mut last = 0;
+        for fraction in 0..=FULL {
+            let duty = brightness.scaled_pwm(fraction);
+            assert!(duty >= last);
+            last = duty;
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Bot behavior that does not depend on the hardware.
+//!
+//! Everything here is `no_std` and free of timers and I/O so that it can run
+//! in the firmware as well as on the host.  Code that needs the time takes a
+//! `clock::MonotonicClock`, which tests can replace with a `MockClock`.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod ambient;
+pub mod button;
+pub mod clock;
+pub mod clock_face;
+pub mod face;
+pub mod gamma;
+pub mod link;
+pub mod mood;
+pub mod pack;
+pub mod script;
+pub mod transition;
@@ -0,0 +1,426 @@
+//! Bot-to-bot link over a shared open-drain line.
+//!
+//! Bots that see each other take turns announcing themselves with a frame:
+//!
+//! | bytes  | field                                     |
+//! |--------|-------------------------------------------|
+//! | 1      | `PREAMBLE`                                |
+//! | 1      | payload length                            |
+//! | 8      | serial, little endian                     |
+//! | 1      | mood                                      |
+//! | 1      | name length                               |
+//! | 0-32   | name                                      |
+//! | 2      | time to the next blink, little endian     |
+//! | 1      | CRC-8 (polynomial 0x07) of the payload    |
+//!
+//! The line idles high between frames, so a `Wire` can find the start of
+//! each byte.  It is shared, so both bots may talk at once.  Colliding frames fail
+//! their CRC and are dropped, and the random gaps between announcements make
+//! sure they get through eventually.  A bot hears its own frames too, which
+//! `Link` filters out by serial.
+//!
+//! The time to the next blink counts from the start of the frame, so that
+//! a friend can blink in time with the sender.  It is `NO_BLINK` if the
+//! sender has no blink coming up.
+
+use blinkybot_rpc::{DeviceName, Mood, PeerInfo, MAX_NAME_LEN};
+
+use crate::clock::MonotonicClock;
+
+pub const PREAMBLE: u8 = 0xa5;
+pub const MAX_PAYLOAD_LEN: usize = 8 + 1 + 1 + MAX_NAME_LEN + 2;
+pub const MAX_FRAME_LEN: usize = 2 + MAX_PAYLOAD_LEN + 1;
+const NO_BLINK: u16 = u16::MAX;
+
+/// What a bot says to its friends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hello {
+    pub peer: PeerInfo,
+    /// Milliseconds from the start of the frame to the sender's next blink.
+    pub next_blink_ms: Option<u16>,
+}
+
+impl Hello {
+    /// Length of the frame carrying this hello.
+    pub fn frame_len(&self) -> usize {
+        2 + payload_len(&self.peer) + 1
+    }
+}
+
+fn payload_len(info: &PeerInfo) -> usize {
+    8 + 1 + 1 + info.name.as_str().len() + 2
+}
+
+/// Byte level access to the line.
+// Only used from single threaded executors, so the futures don't need to
+// be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait Wire {
+    type Error;
+
+    /// Sends `bytes` and lets go of the line.
+    async fn send(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Returns the next byte, or `None` if none starts within `timeout_ms`.
+    async fn recv(&mut self, timeout_ms: u32) -> Result<Option<u8>, Self::Error>;
+}
+
+/// Encodes `hello` into `frame` and returns the length of the frame.
+pub fn encode(hello: &Hello, frame: &mut [u8; MAX_FRAME_LEN]) -> usize {
+    let info = &hello.peer;
+    let name = info.name.as_str().as_bytes();
+    let payload_len = payload_len(info);
+    frame[0] = PREAMBLE;
+    frame[1] = payload_len as u8;
+    let payload = &mut frame[2..2 + payload_len];
+    payload[..8].copy_from_slice(&info.serial.to_le_bytes());
+    payload[8] = info.mood as u8;
+    payload[9] = name.len() as u8;
+    payload[10..10 + name.len()].copy_from_slice(name);
+    let next_blink_ms = hello
+        .next_blink_ms
+        .map_or(NO_BLINK, |ms| ms.min(NO_BLINK - 1));
+    payload[10 + name.len()..].copy_from_slice(&next_blink_ms.to_le_bytes());
+    frame[2 + payload_len] = crc8(&frame[2..2 + payload_len]);
+    hello.frame_len()
+}
+
+fn decode_payload(payload: &[u8]) -> Option<Hello> {
+    let (serial, rest) = payload.split_first_chunk::<8>()?;
+    let (&[mood, name_len], rest) = rest.split_first_chunk::<2>()?;
+    let (name, next_blink_ms) = rest.split_last_chunk::<2>()?;
+    if name.len() != name_len as usize {
+        return None;
+    }
+    let next_blink_ms = u16::from_le_bytes(*next_blink_ms);
+    Some(Hello {
+        peer: PeerInfo {
+            serial: u64::from_le_bytes(*serial),
+            name: DeviceName::new(core::str::from_utf8(name).ok()?)?,
+            mood: *Mood::ALL.get(mood as usize)?,
+        },
+        next_blink_ms: (next_blink_ms != NO_BLINK).then_some(next_blink_ms),
+    })
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Reassembles frames from received bytes.
+pub struct Decoder {
+    frame: [u8; MAX_FRAME_LEN],
+    len: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            frame: [0; MAX_FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    /// Drops any partial frame.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Adds a byte and returns the frame it completes, if it is valid.
+    pub fn push(&mut self, byte: u8) -> Option<Hello> {
+        if self.len == 0 && byte != PREAMBLE {
+            return None;
+        }
+        self.frame[self.len] = byte;
+        self.len += 1;
+
+        while self.len >= 2 {
+            let payload_len = self.frame[1] as usize;
+            if payload_len > MAX_PAYLOAD_LEN {
+                self.skip(1);
+                continue;
+            }
+            let frame_len = 2 + payload_len + 1;
+            if self.len < frame_len {
+                return None;
+            }
+            let payload = &self.frame[2..2 + payload_len];
+            if crc8(payload) == self.frame[2 + payload_len] {
+                if let Some(hello) = decode_payload(payload) {
+                    self.skip(frame_len);
+                    return Some(hello);
+                }
+            }
+            // The preamble may have been noise, so look for a frame
+            // starting later on.
+            self.skip(1);
+        }
+        None
+    }
+
+    /// Drops the first `len` bytes and any after them up to the next
+    /// preamble, keeping the rest for the next frame.
+    fn skip(&mut self, len: usize) {
+        let start = self.frame[len..self.len]
+            .iter()
+            .position(|&byte| byte == PREAMBLE)
+            .map_or(self.len, |i| len + i);
+        self.frame.copy_within(start..self.len, 0);
+        self.len -= start;
+    }
+}
+
+/// Announces this bot and listens for others.
+pub struct Link<W, C> {
+    wire: W,
+    clock: C,
+    decoder: Decoder,
+}
+
+impl<W: Wire, C: MonotonicClock> Link<W, C> {
+    pub fn new(wire: W, clock: C) -> Self {
+        Self {
+            wire,
+            clock,
+            decoder: Decoder::new(),
+        }
+    }
+
+    pub fn wire(&mut self) -> &mut W {
+        &mut self.wire
+    }
+
+    pub async fn announce(&mut self, hello: &Hello) -> Result<(), W::Error> {
+        let mut frame = [0; MAX_FRAME_LEN];
+        let len = encode(hello, &mut frame);
+        self.wire.send(&frame[..len]).await
+    }
+
+    /// Returns the first other bot heard before `until_ms`.  Frames from
+    /// `own_serial` are ignored.
+    pub async fn listen(
+        &mut self,
+        own_serial: u64,
+        until_ms: u64,
+    ) -> Result<Option<Hello>, W::Error> {
+        self.decoder.reset();
+        loop {
+            let now_ms = self.clock.now_ms();
+            if now_ms >= until_ms {
+                return Ok(None);
+            }
+            let timeout_ms = (until_ms - now_ms).min(u32::MAX as u64) as u32;
+            let Some(byte) = self.wire.recv(timeout_ms).await? else {
+                return Ok(None);
+            };
+            if let Some(hello) = self.decoder.push(byte) {
+                if hello.peer.serial != own_serial {
+                    return Ok(Some(hello));
+                }
+            }
+        }
+    }
+}
+
+/// A wire that hears everything sent on it and nothing else, for running
+/// a `Link` on the host.
+pub struct Loopback {
+    buffer: [u8; Self::CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Loopback {
+    const CAPACITY: usize = 4 * MAX_FRAME_LEN;
+
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; Self::CAPACITY],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Sends `bytes` as if from another bot.  Bytes that don't fit are
+    /// dropped.
+    pub fn inject(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len == Self::CAPACITY {
+                return;
+            }
+            self.buffer[(self.start + self.len) % Self::CAPACITY] = byte;
+            self.len += 1;
+        }
+    }
+}
+
+impl Wire for Loopback {
+    type Error = core::convert::Infallible;
+
+    async fn send(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.inject(bytes);
+        Ok(())
+    }
+
+    /// Never waits: an empty buffer times out immediately.
+    async fn recv(&mut self, _timeout_ms: u32) -> Result<Option<u8>, Self::Error> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        let byte = self.buffer[self.start];
+        self.start = (self.start + 1) % Self::CAPACITY;
+        self.len -= 1;
+        Ok(Some(byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    /// Runs a future that never waits, as everything on a `Loopback` does.
+    fn run<F: Future>(future: F) -> F::Output {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("a loopback link never waits"),
+        }
+    }
+
+    fn hello(serial: u64, name: &str, next_blink_ms: Option<u16>) -> Hello {
+        Hello {
+            peer: PeerInfo {
+                serial,
+                name: DeviceName::new(name).unwrap(),
+                mood: Mood::Happy,
+            },
+            next_blink_ms,
+        }
+    }
+
+    fn frame(hello: &Hello) -> Vec<u8> {
+        let mut frame = [0; MAX_FRAME_LEN];
+        let len = encode(hello, &mut frame);
+        frame[..len].to_vec()
+    }
+
+    #[test]
+    fn announcements_are_heard() {
+        let clock = MockClock::new(0);
+        let mut link = Link::new(Loopback::new(), &clock);
+        for sent in [
+            hello(1, "", None),
+            hello(u64::MAX, "blinky", Some(0)),
+            hello(3, &"x".repeat(MAX_NAME_LEN), Some(1234)),
+        ] {
+            run(link.announce(&sent)).unwrap();
+            assert_eq!(run(link.listen(2, 1000)), Ok(Some(sent)));
+        }
+    }
+
+    #[test]
+    fn own_announcements_are_ignored() {
+        let clock = MockClock::new(0);
+        let mut link = Link::new(Loopback::new(), &clock);
+        let own = hello(1, "me", None);
+        let other = hello(2, "you", None);
+        run(link.announce(&own)).unwrap();
+        assert_eq!(run(link.listen(1, 1000)), Ok(None));
+        run(link.announce(&own)).unwrap();
+        link.wire().inject(&frame(&other));
+        assert_eq!(run(link.listen(1, 1000)), Ok(Some(other)));
+    }
+
+    #[test]
+    fn listening_stops_at_the_deadline() {
+        let clock = MockClock::new(1000);
+        let mut link = Link::new(Loopback::new(), &clock);
+        run(link.announce(&hello(2, "", None))).unwrap();
+        assert_eq!(run(link.listen(1, 1000)), Ok(None));
+    }
+
+    #[test]
+    fn blink_times_are_clamped() {
+        let sent = hello(2, "", Some(NO_BLINK));
+        let mut decoder = Decoder::new();
+        let heard = frame(&sent).into_iter().find_map(|b| decoder.push(b));
+        assert_eq!(heard, Some(hello(2, "", Some(NO_BLINK - 1))));
+    }
+
+    #[test]
+    fn corrupt_frames_are_dropped() {
+        let clock = MockClock::new(0);
+        let mut link = Link::new(Loopback::new(), &clock);
+        let sent = hello(2, "friend", Some(500));
+        let mut corrupt = frame(&sent);
+        corrupt[5] ^= 0x10;
+        link.wire().inject(&corrupt);
+        assert_eq!(run(link.listen(1, 1000)), Ok(None));
+
+        link.wire().inject(&corrupt);
+        link.wire().inject(&frame(&sent));
+        assert_eq!(run(link.listen(1, 1000)), Ok(Some(sent)));
+    }
+
+    #[test]
+    fn frames_are_found_after_noise() {
+        let sent = hello(2, "friend", None);
+        let noise: [&[u8]; 4] = [
+            &[0x00, 0xff],
+            // A preamble with an impossible length.
+            &[PREAMBLE, 0xff],
+            // A preamble whose frame would swallow the start of the real one.
+            &[PREAMBLE, 20, 1, 2],
+            &[PREAMBLE, PREAMBLE],
+        ];
+        for noise in noise {
+            let mut decoder = Decoder::new();
+            let heard = noise
+                .iter()
+                .chain(&frame(&sent))
+                .find_map(|&b| decoder.push(b));
+            assert_eq!(heard, Some(sent), "after {noise:02x?}");
+        }
+    }
+
+    #[test]
+    fn frames_swallowed_by_noise_are_all_heard() {
+        let sent = [hello(2, "", None), hello(3, "", None), hello(4, "", None)];
+        // Long enough to cover the first two frames and the start of the
+        // third.
+        let mut bytes = vec![PREAMBLE, 2 * sent[0].frame_len() as u8];
+        for hello in &sent {
+            bytes.extend(frame(hello));
+        }
+
+        let mut decoder = Decoder::new();
+        let heard: Vec<Hello> = bytes.into_iter().filter_map(|b| decoder.push(b)).collect();
+        assert_eq!(heard, sent);
+    }
+}
@@ -0,0 +1,284 @@
+//! Mood state machine.
+//!
+//! The bot starts out `Idle`, gets `Bored` and then `Sleepy` when nothing
+//! happens, is `Surprised` by friends and touch and stays `Happy` while a
+//! friend is around.  The host can force a mood, which holds until it is
+//! released.
+
+use blinkybot_rpc::Mood;
+
+use crate::clock::MonotonicClock;
+
+/// Time spent `Idle` before getting `Bored`.
+pub const BORED_AFTER_MS: u64 = 60_000;
+/// Time spent `Bored` before getting `Sleepy`.
+pub const SLEEPY_AFTER_MS: u64 = 120_000;
+/// How long surprise lasts.
+pub const SURPRISED_MS: u64 = 1_500;
+/// How long a touch keeps the bot `Happy` without a friend around.
+pub const HAPPY_MS: u64 = 20_000;
+
+/// Something that happened to the bot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stimulus {
+    /// Whether a friend is seen.  Only changes are acted on, so this can be
+    /// sent every time the sensor is read.
+    Friend(bool),
+    Touch,
+}
+
+pub struct MoodMachine<C> {
+    clock: C,
+    mood: Mood,
+    /// When `mood` was entered.
+    since_ms: u64,
+    friend: bool,
+    forced: Option<Mood>,
+}
+
+impl<C: MonotonicClock> MoodMachine<C> {
+    pub fn new(clock: C) -> Self {
+        let since_ms = clock.now_ms();
+        Self {
+            clock,
+            mood: Mood::Idle,
+            since_ms,
+            friend: false,
+            forced: None,
+        }
+    }
+
+    /// Returns the forced mood if there is one, otherwise the bot's own.
+    pub fn mood(&self) -> Mood {
+        self.forced.unwrap_or(self.mood)
+    }
+
+    pub fn forced(&self) -> Option<Mood> {
+        self.forced
+    }
+
+    /// Holds the mood at `mood` until called again with `None`.  Stimuli
+    /// are still tracked while forced, and the bot's own mood starts over
+    /// when released.
+    pub fn force(&mut self, mood: Option<Mood>) {
+        if self.forced.is_some() && mood.is_none() {
+            self.enter(if self.friend { Mood::Happy } else { Mood::Idle });
+        }
+        self.forced = mood;
+    }
+
+    /// Forces the mood after the forced one in `Mood::ALL`, releasing it
+    /// after the last.
+    pub fn cycle(&mut self) {
+        let next = match self.forced {
+            None => Some(Mood::ALL[0]),
+            Some(mood) => Mood::ALL
+                .iter()
+                .position(|&other| other == mood)
+                .and_then(|index| Mood::ALL.get(index + 1))
+                .copied(),
+        };
+        self.force(next);
+    }
+
+    pub fn stimulate(&mut self, stimulus: Stimulus) {
+        self.update();
+        match stimulus {
+            Stimulus::Friend(friend) => {
+                if friend == self.friend {
+                    return;
+                }
+                self.friend = friend;
+                if friend {
+                    self.enter(Mood::Surprised);
+                } else if self.mood == Mood::Happy {
+                    self.enter(Mood::Idle);
+                }
+            }
+            Stimulus::Touch => match self.mood {
+                Mood::Idle | Mood::Happy => self.enter(Mood::Happy),
+                Mood::Bored | Mood::Sleepy => self.enter(Mood::Surprised),
+                Mood::Surprised => {}
+            },
+        }
+    }
+
+    /// Applies any timeouts that have passed.
+    pub fn update(&mut self) {
+        while let Some(deadline) = self.own_deadline_ms() {
+            if self.clock.now_ms() < deadline {
+                break;
+            }
+            let next = match self.mood {
+                Mood::Idle => Mood::Bored,
+                Mood::Bored => Mood::Sleepy,
+                Mood::Surprised if self.friend => Mood::Happy,
+                Mood::Surprised | Mood::Happy => Mood::Idle,
+                Mood::Sleepy => break,
+            };
+            // Chained timeouts count from when the previous one expired, not
+            // from when it was noticed.
+            self.mood = next;
+            self.since_ms = deadline;
+        }
+    }
+
+    /// Returns when `update()` next needs calling, or `None` if the mood
+    /// only changes on a stimulus.
+    pub fn deadline_ms(&self) -> Option<u64> {
+        if self.forced.is_some() {
+            return None;
+        }
+        self.own_deadline_ms()
+    }
+
+    fn own_deadline_ms(&self) -> Option<u64> {
+        let timeout = match self.mood {
+            Mood::Idle => BORED_AFTER_MS,
+            Mood::Bored => SLEEPY_AFTER_MS,
+            Mood::Surprised => SURPRISED_MS,
+            Mood::Happy if !self.friend => HAPPY_MS,
+            Mood::Happy | Mood::Sleepy => return None,
+        };
+        Some(self.since_ms + timeout)
+    }
+
+    fn enter(&mut self, mood: Mood) {
+        self.mood = mood;
+        self.since_ms = self.clock.now_ms();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn gets_bored_then_sleepy() {
+        let clock = MockClock::new(5);
+        let mut moods = MoodMachine::new(&clock);
+        assert_eq!(moods.mood(), Mood::Idle);
+        assert_eq!(moods.deadline_ms(), Some(5 + BORED_AFTER_MS));
+
+        clock.advance(BORED_AFTER_MS - 1);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Idle);
+        clock.advance(1);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Bored);
+        assert_eq!(
+            moods.deadline_ms(),
+            Some(5 + BORED_AFTER_MS + SLEEPY_AFTER_MS)
+        );
+
+        clock.advance(SLEEPY_AFTER_MS);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Sleepy);
+        assert_eq!(moods.deadline_ms(), None);
+    }
+
+    #[test]
+    fn late_updates_apply_every_timeout() {
+        let clock = MockClock::new(0);
+        let mut moods = MoodMachine::new(&clock);
+        clock.advance(BORED_AFTER_MS + SLEEPY_AFTER_MS);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Sleepy);
+    }
+
+    #[test]
+    fn friends_surprise_then_please() {
+        let clock = MockClock::new(0);
+        let mut moods = MoodMachine::new(&clock);
+        moods.stimulate(Stimulus::Friend(true));
+        assert_eq!(moods.mood(), Mood::Surprised);
+        assert_eq!(moods.deadline_ms(), Some(SURPRISED_MS));
+
+        // Seeing the same friend again changes nothing.
+        clock.advance(SURPRISED_MS - 1);
+        moods.stimulate(Stimulus::Friend(true));
+        assert_eq!(moods.deadline_ms(), Some(SURPRISED_MS));
+
+        clock.advance(1);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Happy);
+        assert_eq!(moods.deadline_ms(), None);
+
+        clock.advance(BORED_AFTER_MS * 10);
+        moods.stimulate(Stimulus::Friend(false));
+        assert_eq!(moods.mood(), Mood::Idle);
+        assert_eq!(moods.deadline_ms(), Some(clock.now_ms() + BORED_AFTER_MS));
+    }
+
+    #[test]
+    fn touch_pleases_for_a_while() {
+        let clock = MockClock::new(0);
+        let mut moods = MoodMachine::new(&clock);
+        moods.stimulate(Stimulus::Touch);
+        assert_eq!(moods.mood(), Mood::Happy);
+
+        clock.advance(HAPPY_MS);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Idle);
+    }
+
+    #[test]
+    fn touch_wakes_with_a_surprise() {
+        let clock = MockClock::new(0);
+        let mut moods = MoodMachine::new(&clock);
+        clock.advance(BORED_AFTER_MS + SLEEPY_AFTER_MS);
+        moods.stimulate(Stimulus::Touch);
+        assert_eq!(moods.mood(), Mood::Surprised);
+
+        // Surprise isn't extended by more touches.
+        clock.advance(SURPRISED_MS / 2);
+        moods.stimulate(Stimulus::Touch);
+        clock.advance(SURPRISED_MS / 2);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Idle);
+    }
+
+    #[test]
+    fn forced_mood_holds_until_released() {
+        let clock = MockClock::new(0);
+        let mut moods = MoodMachine::new(&clock);
+        moods.force(Some(Mood::Sleepy));
+        assert_eq!(moods.mood(), Mood::Sleepy);
+        assert_eq!(moods.forced(), Some(Mood::Sleepy));
+        assert_eq!(moods.deadline_ms(), None);
+
+        // Stimuli are tracked while forced.
+        moods.stimulate(Stimulus::Friend(true));
+        clock.advance(BORED_AFTER_MS * 10);
+        moods.update();
+        assert_eq!(moods.mood(), Mood::Sleepy);
+
+        moods.force(None);
+        assert_eq!(moods.forced(), None);
+        assert_eq!(moods.mood(), Mood::Happy);
+    }
+
+    #[test]
+    fn released_mood_starts_over() {
+        let clock = MockClock::new(0);
+        let mut moods = MoodMachine::new(&clock);
+        moods.force(Some(Mood::Happy));
+        clock.advance(BORED_AFTER_MS * 10);
+        moods.force(None);
+        assert_eq!(moods.mood(), Mood::Idle);
+        assert_eq!(moods.deadline_ms(), Some(clock.now_ms() + BORED_AFTER_MS));
+    }
+
+    #[test]
+    fn cycle_forces_each_mood_then_releases() {
+        let clock = MockClock::new(0);
+        let mut moods = MoodMachine::new(&clock);
+        for mood in Mood::ALL {
+            moods.cycle();
+            assert_eq!(moods.forced(), Some(mood));
+        }
+        moods.cycle();
+        assert_eq!(moods.forced(), None);
+    }
+}
@@ -0,0 +1,490 @@
+//! Expression packs: faces and settings bundled up to share between bots.
+//!
+//! A `Pack` holds a `Manifest` naming it and any of the bot's stored
+//! expressions, its behavior script (the pack's animation), behavior
+//! settings and brightness.  Parts left out are kept as they are on the bot
+//! the pack is applied to.
+//!
+//! Packs are saved as `MAGIC`, the `PACK_VERSION` byte and the pack in
+//! postcard, which is what pack files contain.  Share links carry the same
+//! bytes in unpadded base64url, see `encode_text()`.  Decoding checks the
+//! version, so later versions can change the layout.
+//!
+//! Hosts export packs with `read()` and import them with `apply()`, through
+//! their own client implementing `Requests`.
+
+use blinkybot_rpc::{
+    AutoBrightness, BlinkSync, BrightnessCurve, BrightnessMode, DeviceName, Expression,
+    ExpressionIndex, FaceMode, GetAutoBrightnessEndpoint, GetBlinkSyncEndpoint,
+    GetBrightnessCurveEndpoint, GetBrightnessEndpoint, GetBrightnessModeEndpoint,
+    GetExpressionEndpoint, GetFaceModeEndpoint, GetMoodStyleEndpoint, GetScriptEndpoint,
+    GetTransitionEndpoint, Mood, MoodStyle, Script, ScriptError, SetAutoBrightnessEndpoint,
+    SetBlinkSyncEndpoint, SetBrightnessCurveEndpoint, SetBrightnessEndpoint,
+    SetBrightnessModeEndpoint, SetExpression, SetExpressionEndpoint, SetFaceModeEndpoint,
+    SetMoodStyle, SetMoodStyleEndpoint, SetScriptEndpoint, SetTransition, SetTransitionEndpoint,
+    TransitionEffect, TransitionKind, TransitionStyle,
+};
+use postcard::experimental::schema::Schema;
+use postcard_rpc::Endpoint;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Start of every encoded pack.
+pub const MAGIC: [u8; 2] = *b"BB";
+/// Version of the encoding written by `encode()`.
+pub const PACK_VERSION: u8 = 1;
+/// Longest encoded pack, in bytes.
+pub const MAX_PACK_LEN: usize = 1024;
+/// Longest pack as base64url text.
+pub const MAX_TEXT_LEN: usize = MAX_PACK_LEN.div_ceil(3) * 4;
+
+/// Why a pack could not be encoded or decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackError {
+    /// Not a pack.
+    BadMagic,
+    /// Made by a newer version.
+    UnsupportedVersion(u8),
+    /// Corrupted, or not valid for its version.
+    Malformed,
+    /// The text is not base64url.
+    BadText,
+    /// The output buffer is too small.
+    TooLarge,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// What the pack is called, up to `MAX_NAME_LEN` bytes like a bot's
+    /// name.
+    #[serde(with = "compact_name")]
+    pub name: DeviceName,
+    #[serde(with = "compact_name")]
+    pub author: DeviceName,
+}
+
+/// How the bot behaves in `FaceMode::Expressions`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Behavior {
+    pub face_mode: FaceMode,
+    /// Indexed by `Mood`.
+    pub mood_styles: [MoodStyle; Mood::ALL.len()],
+    /// Indexed by `TransitionKind`.
+    pub transitions: [TransitionStyle; TransitionKind::ALL.len()],
+    pub blink_sync: BlinkSync,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Brightness {
+    pub brightness: u8,
+    pub curve: BrightnessCurve,
+    pub mode: BrightnessMode,
+    pub auto: AutoBrightness,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pack {
+    pub manifest: Manifest,
+    /// Indexed by `ExpressionIndex`.
+    pub expressions: [Option<Expression>; ExpressionIndex::ALL.len()],
+    #[serde(with = "compact_script")]
+    pub script: Option<Script>,
+    pub behavior: Option<Behavior>,
+    pub brightness: Option<Brightness>,
+}
+
+/// `DeviceName` as just its text.
+mod compact_name {
+    use blinkybot_rpc::DeviceName;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(name: &DeviceName, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(name.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DeviceName, D::Error> {
+        let name = <&str>::deserialize(deserializer)?;
+        DeviceName::new(name).ok_or_else(|| D::Error::custom("name too long"))
+    }
+}
+
+/// `Script` as just its bytecode, instead of its whole buffer.
+mod compact_script {
+    use blinkybot_rpc::Script;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    struct Code<'a>(&'a [u8]);
+
+    impl Serialize for Code<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    pub fn serialize<S: Serializer>(
+        script: &Option<Script>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        script
+            .as_ref()
+            .map(|script| Code(script.code()))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Script>, D::Error> {
+        Option::<&[u8]>::deserialize(deserializer)?
+            .map(|code| Script::new(code).ok_or_else(|| D::Error::custom("script too long")))
+            .transpose()
+    }
+}
+
+/// Encodes `pack` into `buffer`, returning the used part.
+pub fn encode<'a>(pack: &Pack, buffer: &'a mut [u8]) -> Result<&'a mut [u8], PackError> {
+    let header = MAGIC.len() + 1;
+    if buffer.len() < header {
+        return Err(PackError::TooLarge);
+    }
+    buffer[..MAGIC.len()].copy_from_slice(&MAGIC);
+    buffer[MAGIC.len()] = PACK_VERSION;
+    let len = postcard::to_slice(pack, &mut buffer[header..])
+        .map_err(|_| PackError::TooLarge)?
+        .len();
+    Ok(&mut buffer[..header + len])
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Pack, PackError> {
+    let rest = bytes.strip_prefix(&MAGIC).ok_or(PackError::BadMagic)?;
+    match rest.split_first() {
+        Some((&PACK_VERSION, pack)) => postcard::from_bytes(pack).map_err(|_| PackError::Malformed),
+        Some((&version, _)) => Err(PackError::UnsupportedVersion(version)),
+        None => Err(PackError::Malformed),
+    }
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encodes `bytes` as unpadded base64url into `buffer`, for putting packs
+/// in links.
+pub fn encode_text<'a>(bytes: &[u8], buffer: &'a mut [u8]) -> Result<&'a str, PackError> {
+    let len = (bytes.len() * 4).div_ceil(3);
+    let text = buffer.get_mut(..len).ok_or(PackError::TooLarge)?;
+    let mut out = text.iter_mut();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..=chunk.len() {
+            if let Some(c) = out.next() {
+                *c = ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3f];
+            }
+        }
+    }
+    // Only ASCII was written.
+    core::str::from_utf8(text).map_err(|_| PackError::BadText)
+}
+
+/// Decodes unpadded base64url `text` into `buffer`, returning the used part.
+pub fn decode_text<'a>(text: &str, buffer: &'a mut [u8]) -> Result<&'a [u8], PackError> {
+    let text = text.trim().as_bytes();
+    if text.len() % 4 == 1 {
+        return Err(PackError::BadText);
+    }
+    let len = text.len() * 3 / 4;
+    let bytes = buffer.get_mut(..len).ok_or(PackError::TooLarge)?;
+    let mut out = bytes.iter_mut();
+    for chunk in text.chunks(4) {
+        let mut bits = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or(PackError::BadText)?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            if let Some(byte) = out.next() {
+                *byte = (bits >> (16 - 8 * i)) as u8;
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// A connection to a bot, for `read()` and `apply()`.
+// Hosts await the futures directly, so they needn't be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait Requests {
+    type Error;
+
+    /// Sends `request` to `E` and waits for the response.
+    async fn send<E: Endpoint>(&self, request: &E::Request) -> Result<E::Response, Self::Error>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: Serialize + DeserializeOwned + Schema;
+}
+
+/// Why `read()` or `apply()` failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransferError<E> {
+    /// The request to the endpoint at `path` failed.
+    Request { path: &'static str, error: E },
+    /// The bot rejected the pack's script.
+    ScriptRejected(ScriptError),
+}
+
+async fn send<E: Endpoint, R: Requests>(
+    bot: &R,
+    request: &E::Request,
+) -> Result<E::Response, TransferError<R::Error>>
+where
+    E::Request: Serialize + Schema,
+    E::Response: Serialize + DeserializeOwned + Schema,
+{
+    bot.send::<E>(request)
+        .await
+        .map_err(|error| TransferError::Request {
+            path: E::PATH,
+            error,
+        })
+}
+
+/// Returns a pack described by `manifest` of everything `bot` has.
+pub async fn read<R: Requests>(
+    bot: &R,
+    manifest: Manifest,
+) -> Result<Pack, TransferError<R::Error>> {
+    let mut pack = Pack {
+        manifest,
+        ..Pack::default()
+    };
+    for (index, expression) in ExpressionIndex::ALL.iter().zip(&mut pack.expressions) {
+        *expression = Some(send::<GetExpressionEndpoint, _>(bot, index).await?);
+    }
+    pack.script = Some(send::<GetScriptEndpoint, _>(bot, &()).await?);
+
+    let unread = MoodStyle::new(ExpressionIndex::Default, ExpressionIndex::Default, 0, 0);
+    let mut mood_styles = [unread; Mood::ALL.len()];
+    for (mood, style) in Mood::ALL.iter().zip(&mut mood_styles) {
+        *style = send::<GetMoodStyleEndpoint, _>(bot, mood).await?;
+    }
+    let mut transitions =
+        [TransitionStyle::new(TransitionEffect::Cut, 0); TransitionKind::ALL.len()];
+    for (kind, style) in TransitionKind::ALL.iter().zip(&mut transitions) {
+        *style = send::<GetTransitionEndpoint, _>(bot, kind).await?;
+    }
+    pack.behavior = Some(Behavior {
+        face_mode: send::<GetFaceModeEndpoint, _>(bot, &()).await?,
+        mood_styles,
+        transitions,
+        blink_sync: send::<GetBlinkSyncEndpoint, _>(bot, &()).await?,
+    });
+
+    pack.brightness = Some(Brightness {
+        brightness: send::<GetBrightnessEndpoint, _>(bot, &()).await?,
+        curve: send::<GetBrightnessCurveEndpoint, _>(bot, &()).await?,
+        mode: send::<GetBrightnessModeEndpoint, _>(bot, &()).await?,
+        auto: send::<GetAutoBrightnessEndpoint, _>(bot, &()).await?,
+    });
+    Ok(pack)
+}
+
+/// Most requests `apply()` sends, one for each setting it changes: the
+/// script, the expressions, the behavior and the brightness.
+pub const MAX_APPLIED: usize =
+    1 + ExpressionIndex::ALL.len() + Mood::ALL.len() + TransitionKind::ALL.len() + 2 + 4;
+
+/// Sets everything in `pack` on `bot`.  Parts the pack leaves out are kept
+/// as they are.
+pub async fn apply<R: Requests>(bot: &R, pack: &Pack) -> Result<(), TransferError<R::Error>> {
+    // The script goes first as it's the only part the bot may reject.
+    if let Some(script) = &pack.script {
+        send::<SetScriptEndpoint, _>(bot, script)
+            .await?
+            .map_err(TransferError::ScriptRejected)?;
+    }
+    for (&index, expression) in ExpressionIndex::ALL.iter().zip(&pack.expressions) {
+        let Some(expression) = expression else {
+            continue;
+        };
+        let expression = SetExpression {
+            index,
+            expression: expression.clone(),
+        };
+        send::<SetExpressionEndpoint, _>(bot, &expression).await?;
+    }
+
+    if let Some(behavior) = &pack.behavior {
+        for (&mood, &style) in Mood::ALL.iter().zip(&behavior.mood_styles) {
+            send::<SetMoodStyleEndpoint, _>(bot, &SetMoodStyle { mood, style }).await?;
+        }
+        for (&kind, &style) in TransitionKind::ALL.iter().zip(&behavior.transitions) {
+            send::<SetTransitionEndpoint, _>(bot, &SetTransition { kind, style }).await?;
+        }
+        send::<SetBlinkSyncEndpoint, _>(bot, &behavior.blink_sync).await?;
+        send::<SetFaceModeEndpoint, _>(bot, &behavior.face_mode).await?;
+    }
+
+    if let Some(brightness) = &pack.brightness {
+        send::<SetBrightnessCurveEndpoint, _>(bot, &brightness.curve).await?;
+        send::<SetAutoBrightnessEndpoint, _>(bot, &brightness.auto).await?;
+        send::<SetBrightnessEndpoint, _>(bot, &brightness.brightness).await?;
+        send::<SetBrightnessModeEndpoint, _>(bot, &brightness.mode).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_pack() -> Pack {
+        let style = |expression| MoodStyle::new(expression, ExpressionIndex::Blink, 2000, 6000);
+        Pack {
+            manifest: Manifest {
+                name: DeviceName::new("Sleepy eyes").unwrap(),
+                author: DeviceName::new("Ada").unwrap(),
+            },
+            expressions: [
+                Some(Expression::from_rows(&[0b0110, 0b1001])),
+                None,
+                Some(Expression::default()),
+                None,
+                Some(Expression::from_rows(&[0, 0, 0b1111])),
+            ],
+            script: Script::new(&[1, 2, 3, 4]),
+            behavior: Some(Behavior {
+                face_mode: FaceMode::Script,
+                mood_styles: [style(ExpressionIndex::Default); Mood::ALL.len()],
+                transitions: [TransitionStyle::new(TransitionEffect::Wipe, 300);
+                    TransitionKind::ALL.len()],
+                blink_sync: BlinkSync::new(true, 150),
+            }),
+            brightness: Some(Brightness {
+                brightness: 40,
+                curve: BrightnessCurve::Cie1931,
+                mode: BrightnessMode::Auto,
+                auto: AutoBrightness::new(100, 3000, 5, 80),
+            }),
+        }
+    }
+
+    /// Counts the requests sent, answering each with zeros.
+    #[derive(Default)]
+    struct Counter(core::cell::Cell<usize>);
+
+    impl Requests for Counter {
+        type Error = postcard::Error;
+
+        async fn send<E: Endpoint>(&self, _request: &E::Request) -> Result<E::Response, Self::Error>
+        where
+            E::Request: Serialize + Schema,
+            E::Response: Serialize + DeserializeOwned + Schema,
+        {
+            self.0.set(self.0.get() + 1);
+            // Zeros are `()` and `Ok(())`, all that setters respond with.
+            postcard::from_bytes(&[0; 4])
+        }
+    }
+
+    fn run<F: core::future::Future>(future: F) -> F::Output {
+        let mut context = core::task::Context::from_waker(core::task::Waker::noop());
+        let mut future = core::pin::pin!(future);
+        loop {
+            if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn apply_sends_at_most_max_applied() {
+        let bot = Counter::default();
+        let mut pack = full_pack();
+        pack.expressions = core::array::from_fn(|_| Some(Expression::default()));
+        run(apply(&bot, &pack)).unwrap();
+        assert_eq!(bot.0.get(), MAX_APPLIED);
+
+        let bot = Counter::default();
+        run(apply(&bot, &Pack::default())).unwrap();
+        assert_eq!(bot.0.get(), 0);
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        for pack in [Pack::default(), full_pack()] {
+            let mut buffer = [0; MAX_PACK_LEN];
+            let bytes = encode(&pack, &mut buffer).unwrap();
+            assert_eq!(bytes[..MAGIC.len()], MAGIC);
+            assert_eq!(bytes[MAGIC.len()], PACK_VERSION);
+            assert_eq!(decode(bytes), Ok(pack));
+        }
+    }
+
+    #[test]
+    fn pack_fits_text() {
+        let mut buffer = [0; MAX_PACK_LEN];
+        let bytes = encode(&full_pack(), &mut buffer).unwrap();
+        let mut text = [0; MAX_TEXT_LEN];
+        let text = encode_text(bytes, &mut text).unwrap();
+        let mut decoded = [0; MAX_PACK_LEN];
+        assert_eq!(
+            decode(decode_text(text, &mut decoded).unwrap()),
+            Ok(full_pack())
+        );
+    }
+
+    #[test]
+    fn bad_header() {
+        assert_eq!(decode(b""), Err(PackError::BadMagic));
+        assert_eq!(decode(b"XB\x01"), Err(PackError::BadMagic));
+        assert_eq!(decode(b"BB"), Err(PackError::Malformed));
+        assert_eq!(decode(b"BB\x02"), Err(PackError::UnsupportedVersion(2)));
+        assert_eq!(decode(b"BB\x01\xff"), Err(PackError::Malformed));
+    }
+
+    #[test]
+    fn too_large() {
+        let pack = full_pack();
+        assert_eq!(encode(&pack, &mut [0; 2]), Err(PackError::TooLarge));
+        assert_eq!(encode(&pack, &mut [0; 16]), Err(PackError::TooLarge));
+        assert_eq!(
+            encode_text(&[1, 2, 3, 4], &mut [0; 5]),
+            Err(PackError::TooLarge)
+        );
+        assert_eq!(decode_text("AQIDBA", &mut [0; 3]), Err(PackError::TooLarge));
+    }
+
+    #[test]
+    fn text_round_trip() {
+        // Covers every remainder, so text with no padding, and with one or
+        // two characters of padding left out.
+        let bytes: Vec<u8> = (0..=255).rev().collect();
+        for len in 0..=bytes.len() {
+            let mut text = [0; MAX_TEXT_LEN];
+            let text = encode_text(&bytes[..len], &mut text).unwrap();
+            assert_eq!(text.len(), (len * 4).div_ceil(3));
+            let mut decoded = [0; MAX_PACK_LEN];
+            assert_eq!(decode_text(text, &mut decoded), Ok(&bytes[..len]));
+        }
+    }
+
+    #[test]
+    fn text_is_base64url() {
+        let mut buffer = [0; MAX_TEXT_LEN];
+        assert_eq!(encode_text(b"", &mut buffer), Ok(""));
+        assert_eq!(encode_text(b"f", &mut buffer), Ok("Zg"));
+        assert_eq!(encode_text(b"fo", &mut buffer), Ok("Zm8"));
+        assert_eq!(encode_text(b"foo", &mut buffer), Ok("Zm9v"));
+        assert_eq!(encode_text(&[0xfb, 0xff], &mut buffer), Ok("-_8"));
+    }
+
+    #[test]
+    fn bad_text() {
+        let mut buffer = [0; MAX_PACK_LEN];
+        assert_eq!(decode_text("Zm9vY", &mut buffer), Err(PackError::BadText));
+        assert_eq!(decode_text("Zm9v+g", &mut buffer), Err(PackError::BadText));
+        assert_eq!(decode_text("Zm8=", &mut buffer), Err(PackError::BadText));
+        assert_eq!(decode_text(" Zm8\n", &mut buffer), Ok(&b"fo"[..]));
+    }
+}
@@ -0,0 +1,691 @@
+//! Behavior scripts.
+//!
+//! A script is bytecode for a tiny sandboxed machine that shows expressions,
+//! waits, branches on chance, friends and the sense input, loops and sets
+//! the brightness.  Each instruction is an opcode byte followed by its
+//! operands, multi-byte ones little endian, and jump targets are byte
+//! offsets into the script.  Scripts are checked by `validate()` before
+//! they run so the `Vm` never meets a bad instruction, and a script that
+//! runs `MAX_BUSY_STEPS` instructions without waiting is stopped.
+//!
+//! Running off the end of a script ends it like `end`, leaving the last
+//! expression shown.
+
+use blinkybot_rpc::{
+    ExpressionIndex, Script, ScriptError, ScriptErrorKind, MAX_BRIGHTNESS, MAX_SCRIPT_LEN,
+};
+
+use crate::clock::MonotonicClock;
+
+/// Number of loop counters.
+pub const COUNTERS: usize = 4;
+/// Instructions a script may run without waiting before it is stopped.
+pub const MAX_BUSY_STEPS: u32 = 256;
+/// Shorter waits are lengthened to this, so that a script can't keep the bot
+/// busy by waiting for nothing.
+pub const MIN_WAIT_MS: u16 = 20;
+/// `bright` operand that returns to the configured brightness.
+pub const CONFIGURED_BRIGHTNESS: u16 = 0xff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `end`: stops the script.
+    End = 0,
+    /// `show expression`
+    Show = 1,
+    /// `wait ms`
+    Wait = 2,
+    /// `waitr min_ms max_ms`: waits for a random time in the range.
+    WaitRandom = 3,
+    /// `jump target`
+    Jump = 4,
+    /// `chance percent target`: jumps with the given probability.
+    Chance = 5,
+    /// `iffriend target`: jumps if a friend is seen.
+    IfFriend = 6,
+    /// `ifadcbelow value target`: jumps if the sense input reads below
+    /// `value`.
+    IfAdcBelow = 7,
+    /// `ifadcabove value target`: jumps if the sense input reads above
+    /// `value`.
+    IfAdcAbove = 8,
+    /// `count counter n`: sets a loop counter.
+    Count = 9,
+    /// `loop counter target`: decrements the counter and jumps unless it
+    /// reached 0, so a loop started with `count counter n` runs `n` times.
+    Loop = 10,
+    /// `bright percent`: overrides the brightness, or returns to the
+    /// configured one with `CONFIGURED_BRIGHTNESS`.
+    Bright = 11,
+}
+
+/// Kinds of instruction operands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// An `ExpressionIndex`, one byte.
+    Expression,
+    /// Milliseconds, two bytes.
+    Ms,
+    /// Offset of an instruction, two bytes.
+    Target,
+    /// 0 to 100, one byte.
+    Percent,
+    /// A sense input reading, two bytes.
+    Adc,
+    /// Index of a loop counter, one byte.
+    Counter,
+    /// Loop count, one byte.
+    Count,
+    /// 0 to `MAX_BRIGHTNESS` or `CONFIGURED_BRIGHTNESS`, one byte.
+    Brightness,
+}
+
+impl Operand {
+    pub const fn encoded_len(self) -> usize {
+        match self {
+            Operand::Ms | Operand::Target | Operand::Adc => 2,
+            _ => 1,
+        }
+    }
+
+    /// Checks an operand of an instruction in a script where `starts` marks
+    /// the offsets at which instructions start.
+    fn check(self, value: u16, starts: &[bool]) -> Result<(), ScriptErrorKind> {
+        let (ok, error) = match self {
+            Operand::Expression => (
+                (value as usize) < ExpressionIndex::ALL.len(),
+                ScriptErrorKind::BadExpression,
+            ),
+            Operand::Target => (
+                starts.get(value as usize).copied().unwrap_or(false),
+                ScriptErrorKind::BadTarget,
+            ),
+            Operand::Percent => (value <= 100, ScriptErrorKind::BadPercent),
+            Operand::Counter => ((value as usize) < COUNTERS, ScriptErrorKind::BadCounter),
+            Operand::Brightness => (
+                value <= MAX_BRIGHTNESS as u16 || value == CONFIGURED_BRIGHTNESS,
+                ScriptErrorKind::BadBrightness,
+            ),
+            Operand::Ms | Operand::Adc | Operand::Count => return Ok(()),
+        };
+        ok.then_some(()).ok_or(error)
+    }
+}
+
+impl Op {
+    pub const ALL: [Op; 12] = [
+        Op::End,
+        Op::Show,
+        Op::Wait,
+        Op::WaitRandom,
+        Op::Jump,
+        Op::Chance,
+        Op::IfFriend,
+        Op::IfAdcBelow,
+        Op::IfAdcAbove,
+        Op::Count,
+        Op::Loop,
+        Op::Bright,
+    ];
+
+    pub fn from_u8(opcode: u8) -> Option<Self> {
+        Self::ALL.get(opcode as usize).copied()
+    }
+
+    /// Returns the name of the instruction in assembly.
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Op::End => "end",
+            Op::Show => "show",
+            Op::Wait => "wait",
+            Op::WaitRandom => "waitr",
+            Op::Jump => "jump",
+            Op::Chance => "chance",
+            Op::IfFriend => "iffriend",
+            Op::IfAdcBelow => "ifadcbelow",
+            Op::IfAdcAbove => "ifadcabove",
+            Op::Count => "count",
+            Op::Loop => "loop",
+            Op::Bright => "bright",
+        }
+    }
+
+    pub const fn operands(self) -> &'static [Operand] {
+        match self {
+            Op::End => &[],
+            Op::Show => &[Operand::Expression],
+            Op::Wait => &[Operand::Ms],
+            Op::WaitRandom => &[Operand::Ms, Operand::Ms],
+            Op::Jump | Op::IfFriend => &[Operand::Target],
+            Op::Chance => &[Operand::Percent, Operand::Target],
+            Op::IfAdcBelow | Op::IfAdcAbove => &[Operand::Adc, Operand::Target],
+            Op::Count => &[Operand::Counter, Operand::Count],
+            Op::Loop => &[Operand::Counter, Operand::Target],
+            Op::Bright => &[Operand::Brightness],
+        }
+    }
+}
+
+/// Longest encoded instruction.
+pub const MAX_INSTRUCTION_LEN: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub op: Op,
+    /// In the order of `op.operands()`, unused ones 0.
+    pub operands: [u16; 2],
+}
+
+impl Instruction {
+    pub fn encoded_len(&self) -> usize {
+        1 + self
+            .op
+            .operands()
+            .iter()
+            .map(|o| o.encoded_len())
+            .sum::<usize>()
+    }
+
+    /// Decodes the instruction at `offset` in `code`.
+    pub fn decode(code: &[u8], offset: usize) -> Result<Self, ScriptErrorKind> {
+        let op = code
+            .get(offset)
+            .and_then(|&opcode| Op::from_u8(opcode))
+            .ok_or(ScriptErrorKind::UnknownOpcode)?;
+        let mut operands = [0; 2];
+        let mut at = offset + 1;
+        for (operand, value) in op.operands().iter().zip(operands.iter_mut()) {
+            let bytes = code
+                .get(at..at + operand.encoded_len())
+                .ok_or(ScriptErrorKind::Truncated)?;
+            *value = match *bytes {
+                [byte] => byte.into(),
+                [low, high] => u16::from_le_bytes([low, high]),
+                _ => unreachable!(),
+            };
+            at += operand.encoded_len();
+        }
+        Ok(Self { op, operands })
+    }
+
+    /// Encodes the instruction into the start of `buffer`, which must hold
+    /// `MAX_INSTRUCTION_LEN` bytes, and returns its length.  Operands too
+    /// large for their encoding are truncated.
+    pub fn encode(&self, buffer: &mut [u8]) -> usize {
+        buffer[0] = self.op as u8;
+        let mut at = 1;
+        for (operand, value) in self.op.operands().iter().zip(self.operands) {
+            let bytes = value.to_le_bytes();
+            buffer[at..at + operand.encoded_len()].copy_from_slice(&bytes[..operand.encoded_len()]);
+            at += operand.encoded_len();
+        }
+        at
+    }
+}
+
+/// Checks `script` with `validate()`, rejecting one received with a length
+/// over `MAX_SCRIPT_LEN` rather than treating it as empty.
+pub fn validate_script(script: &Script) -> Result<(), ScriptError> {
+    match script.try_code() {
+        Some(code) => validate(code),
+        None => Err(ScriptError {
+            offset: MAX_SCRIPT_LEN as u16,
+            kind: ScriptErrorKind::TooLong,
+        }),
+    }
+}
+
+/// Checks that `code` only holds valid instructions that jump to the start
+/// of other instructions, or to the end of the script.
+pub fn validate(code: &[u8]) -> Result<(), ScriptError> {
+    let error = |offset: usize, kind| ScriptError {
+        offset: offset as u16,
+        kind,
+    };
+    if code.len() > MAX_SCRIPT_LEN {
+        return Err(error(MAX_SCRIPT_LEN, ScriptErrorKind::TooLong));
+    }
+    let mut starts = [false; MAX_SCRIPT_LEN + 1];
+    let mut offset = 0;
+    while offset < code.len() {
+        let instruction = Instruction::decode(code, offset).map_err(|kind| error(offset, kind))?;
+        starts[offset] = true;
+        offset += instruction.encoded_len();
+    }
+    starts[code.len()] = true;
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let instruction = Instruction::decode(code, offset).map_err(|kind| error(offset, kind))?;
+        for (operand, value) in instruction.op.operands().iter().zip(instruction.operands) {
+            operand
+                .check(value, &starts)
+                .map_err(|kind| error(offset, kind))?;
+        }
+        offset += instruction.encoded_len();
+    }
+    Ok(())
+}
+
+/// What a script controls and senses.
+pub trait Body {
+    fn show(&mut self, expression: ExpressionIndex);
+
+    /// Overrides the brightness, or returns to the configured one if
+    /// `None`.
+    fn set_brightness(&mut self, brightness: Option<u8>);
+
+    fn sees_friend(&self) -> bool;
+
+    /// Returns the last reading of the sense input.
+    fn adc(&self) -> u16;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// Waiting to carry on at `until_ms`.
+    Waiting { until_ms: u64 },
+    /// Ran to its end.
+    Ended,
+    /// Stopped for running `MAX_BUSY_STEPS` instructions without waiting.
+    Busy,
+}
+
+pub struct Vm<C> {
+    clock: C,
+    script: Script,
+    pc: usize,
+    counters: [u8; COUNTERS],
+    /// Xorshift state, never 0.
+    rng: u32,
+    status: Status,
+}
+
+impl<C: MonotonicClock> Vm<C> {
+    /// Validates `script` and sets it up to start on the next `run()`.
+    /// `seed` picks the random choices it makes.
+    pub fn new(clock: C, script: Script, seed: u32) -> Result<Self, ScriptError> {
+        validate_script(&script)?;
+        let until_ms = clock.now_ms();
+        Ok(Self {
+            clock,
+            script,
+            pc: 0,
+            counters: [0; COUNTERS],
+            rng: seed.max(1),
+            status: Status::Waiting { until_ms },
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Runs the script until it waits or stops, if it is time for it to carry
+    /// on.
+    pub fn run(&mut self, body: &mut impl Body) -> Status {
+        let Status::Waiting { until_ms } = self.status else {
+            return self.status;
+        };
+        let now_ms = self.clock.now_ms();
+        if now_ms < until_ms {
+            return self.status;
+        }
+
+        for _ in 0..MAX_BUSY_STEPS {
+            // Validation leaves running off the end as the only way to fail.
+            let Ok(instruction) = Instruction::decode(self.script.code(), self.pc) else {
+                self.status = Status::Ended;
+                return self.status;
+            };
+            self.pc += instruction.encoded_len();
+            let [a, b] = instruction.operands;
+            match instruction.op {
+                Op::End => {
+                    self.status = Status::Ended;
+                    return self.status;
+                }
+                Op::Show => body.show(ExpressionIndex::ALL[a as usize]),
+                Op::Wait => return self.wait(now_ms, a),
+                Op::WaitRandom => {
+                    let (min, max) = (a.min(b), a.max(b));
+                    let ms = min + self.random(u32::from(max - min) + 1) as u16;
+                    return self.wait(now_ms, ms);
+                }
+                Op::Jump => self.pc = a.into(),
+                Op::Chance => {
+                    if self.random(100) < a.into() {
+                        self.pc = b.into();
+                    }
+                }
+                Op::IfFriend => {
+                    if body.sees_friend() {
+                        self.pc = a.into();
+                    }
+                }
+                Op::IfAdcBelow => {
+                    if body.adc() < a {
+                        self.pc = b.into();
+                    }
+                }
+                Op::IfAdcAbove => {
+                    if body.adc() > a {
+                        self.pc = b.into();
+                    }
+                }
+                Op::Count => self.counters[a as usize] = b as u8,
+                Op::Loop => {
+                    let counter = &mut self.counters[a as usize];
+                    *counter = counter.saturating_sub(1);
+                    if *counter > 0 {
+                        self.pc = b.into();
+                    }
+                }
+                Op::Bright => body.set_brightness((a != CONFIGURED_BRIGHTNESS).then_some(a as u8)),
+            }
+        }
+        self.status = Status::Busy;
+        self.status
+    }
+
+    fn wait(&mut self, now_ms: u64, ms: u16) -> Status {
+        self.status = Status::Waiting {
+            until_ms: now_ms + u64::from(ms.max(MIN_WAIT_MS)),
+        };
+        self.status
+    }
+
+    /// Returns a random number below `n`.
+    fn random(&mut self, n: u32) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn assemble(instructions: &[(Op, [u16; 2])]) -> Vec<u8> {
+        let mut code = Vec::new();
+        for &(op, operands) in instructions {
+            let mut buffer = [0; MAX_INSTRUCTION_LEN];
+            let len = Instruction { op, operands }.encode(&mut buffer);
+            code.extend_from_slice(&buffer[..len]);
+        }
+        code
+    }
+
+    fn script(instructions: &[(Op, [u16; 2])]) -> Script {
+        Script::new(&assemble(instructions)).unwrap()
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        shown: Vec<ExpressionIndex>,
+        brightness: Vec<Option<u8>>,
+        sees_friend: bool,
+        adc: u16,
+    }
+
+    impl Body for Recorder {
+        fn show(&mut self, expression: ExpressionIndex) {
+            self.shown.push(expression);
+        }
+
+        fn set_brightness(&mut self, brightness: Option<u8>) {
+            self.brightness.push(brightness);
+        }
+
+        fn sees_friend(&self) -> bool {
+            self.sees_friend
+        }
+
+        fn adc(&self) -> u16 {
+            self.adc
+        }
+    }
+
+    fn error_kind(instructions: &[(Op, [u16; 2])]) -> Option<ScriptErrorKind> {
+        validate(&assemble(instructions)).err().map(|e| e.kind)
+    }
+
+    #[test]
+    fn instructions_round_trip() {
+        for op in Op::ALL {
+            let instruction = Instruction {
+                op,
+                operands: match op.operands() {
+                    [] => [0, 0],
+                    [_] => [1, 0],
+                    _ => [1, 2],
+                },
+            };
+            let mut buffer = [0; MAX_INSTRUCTION_LEN];
+            let len = instruction.encode(&mut buffer);
+            assert_eq!(len, instruction.encoded_len());
+            assert_eq!(Instruction::decode(&buffer[..len], 0), Ok(instruction));
+            assert_eq!(
+                Instruction::decode(&buffer[..len - 1], 0),
+                Err(if len == 1 {
+                    ScriptErrorKind::UnknownOpcode
+                } else {
+                    ScriptErrorKind::Truncated
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn jump_targets_are_checked() {
+        // `jump` is 3 bytes and `show` 2.
+        assert_eq!(error_kind(&[(Op::Jump, [0, 0])]), None);
+        assert_eq!(error_kind(&[(Op::Jump, [3, 0])]), None);
+        assert_eq!(
+            error_kind(&[(Op::Jump, [4, 0])]),
+            Some(ScriptErrorKind::BadTarget)
+        );
+        assert_eq!(
+            error_kind(&[(Op::Jump, [1, 0])]),
+            Some(ScriptErrorKind::BadTarget)
+        );
+        assert_eq!(
+            error_kind(&[(Op::Show, [0, 0]), (Op::IfFriend, [5, 0])]),
+            None
+        );
+    }
+
+    #[test]
+    fn operands_are_checked() {
+        let cases = [
+            (
+                Op::Show,
+                [ExpressionIndex::ALL.len() as u16, 0],
+                ScriptErrorKind::BadExpression,
+            ),
+            (Op::Chance, [101, 0], ScriptErrorKind::BadPercent),
+            (Op::Count, [COUNTERS as u16, 1], ScriptErrorKind::BadCounter),
+            (
+                Op::Bright,
+                [MAX_BRIGHTNESS as u16 + 1, 0],
+                ScriptErrorKind::BadBrightness,
+            ),
+        ];
+        for (op, operands, kind) in cases {
+            assert_eq!(error_kind(&[(op, operands)]), Some(kind), "{op:?}");
+        }
+        assert_eq!(
+            error_kind(&[(Op::Bright, [CONFIGURED_BRIGHTNESS, 0])]),
+            None
+        );
+        assert_eq!(
+            validate(&[Op::Wait as u8, 0]).map_err(|e| e.kind),
+            Err(ScriptErrorKind::Truncated)
+        );
+        assert_eq!(
+            validate(&[Op::ALL.len() as u8]).map_err(|e| e.kind),
+            Err(ScriptErrorKind::UnknownOpcode)
+        );
+        assert_eq!(
+            validate(&[0; MAX_SCRIPT_LEN + 1]).map_err(|e| e.kind),
+            Err(ScriptErrorKind::TooLong)
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_length() {
+        // A length byte over `MAX_SCRIPT_LEN`, followed by the chunks.
+        let mut bytes = vec![MAX_SCRIPT_LEN as u8 + 1];
+        bytes.resize(1 + MAX_SCRIPT_LEN, 0);
+        let script: Script = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            validate_script(&script).map_err(|e| e.kind),
+            Err(ScriptErrorKind::TooLong)
+        );
+        assert_eq!(validate(script.code()), Ok(()));
+    }
+
+    #[test]
+    fn errors_point_at_the_instruction() {
+        let code = assemble(&[
+            (Op::Show, [0, 0]),
+            (Op::Wait, [10, 0]),
+            (Op::Chance, [200, 0]),
+        ]);
+        assert_eq!(
+            validate(&code),
+            Err(ScriptError {
+                offset: 5,
+                kind: ScriptErrorKind::BadPercent
+            })
+        );
+    }
+
+    #[test]
+    fn waits_until_the_clock_catches_up() {
+        let clock = MockClock::new(1000);
+        let script = script(&[
+            (Op::Show, [ExpressionIndex::Blink as u16, 0]),
+            (Op::Wait, [500, 0]),
+            (Op::Show, [ExpressionIndex::Default as u16, 0]),
+            (Op::End, [0, 0]),
+            (Op::Show, [ExpressionIndex::Sleep as u16, 0]),
+        ]);
+        let mut vm = Vm::new(&clock, script, 1).unwrap();
+        let mut body = Recorder::default();
+        assert_eq!(vm.run(&mut body), Status::Waiting { until_ms: 1500 });
+        assert_eq!(body.shown, [ExpressionIndex::Blink]);
+
+        clock.set(1499);
+        assert_eq!(vm.run(&mut body), Status::Waiting { until_ms: 1500 });
+        assert_eq!(body.shown, [ExpressionIndex::Blink]);
+
+        clock.set(1500);
+        assert_eq!(vm.run(&mut body), Status::Ended);
+        assert_eq!(
+            body.shown,
+            [ExpressionIndex::Blink, ExpressionIndex::Default]
+        );
+        clock.set(5000);
+        assert_eq!(vm.run(&mut body), Status::Ended);
+        assert_eq!(body.shown.len(), 2);
+    }
+
+    #[test]
+    fn short_waits_are_lengthened() {
+        let clock = MockClock::new(0);
+        let mut vm = Vm::new(&clock, script(&[(Op::Wait, [0, 0])]), 1).unwrap();
+        assert_eq!(
+            vm.run(&mut Recorder::default()),
+            Status::Waiting {
+                until_ms: MIN_WAIT_MS.into()
+            }
+        );
+    }
+
+    #[test]
+    fn random_waits_stay_in_range() {
+        let clock = MockClock::new(0);
+        let script = script(&[(Op::WaitRandom, [300, 100]), (Op::Jump, [0, 0])]);
+        let mut vm = Vm::new(&clock, script, 12345).unwrap();
+        let mut body = Recorder::default();
+        for _ in 0..100 {
+            let now_ms = clock.now_ms();
+            let Status::Waiting { until_ms } = vm.run(&mut body) else {
+                panic!("stopped waiting");
+            };
+            assert!((now_ms + 100..=now_ms + 300).contains(&until_ms));
+            clock.set(until_ms);
+        }
+    }
+
+    #[test]
+    fn busy_scripts_are_stopped() {
+        let clock = MockClock::new(0);
+        let mut vm = Vm::new(&clock, script(&[(Op::Jump, [0, 0])]), 1).unwrap();
+        let mut body = Recorder::default();
+        assert_eq!(vm.run(&mut body), Status::Busy);
+        clock.advance(1000);
+        assert_eq!(vm.run(&mut body), Status::Busy);
+    }
+
+    #[test]
+    fn loops_run_the_counted_times() {
+        let clock = MockClock::new(0);
+        let script = script(&[
+            (Op::Count, [1, 3]),
+            (Op::Show, [ExpressionIndex::Blink as u16, 0]),
+            (Op::Loop, [1, 3]),
+        ]);
+        let mut vm = Vm::new(&clock, script, 1).unwrap();
+        let mut body = Recorder::default();
+        assert_eq!(vm.run(&mut body), Status::Ended);
+        assert_eq!(body.shown, [ExpressionIndex::Blink; 3]);
+    }
+
+    #[test]
+    fn branches_follow_the_body() {
+        let clock = MockClock::new(0);
+        // Shows `Friend` for a friend, `Sleep` below 100, or else `Default`.
+        let script = script(&[
+            (Op::IfFriend, [13, 0]),
+            (Op::IfAdcBelow, [100, 16]),
+            (Op::Show, [ExpressionIndex::Default as u16, 0]),
+            // Jumps to the end.
+            (Op::Jump, [18, 0]),
+            (Op::Show, [ExpressionIndex::Friend as u16, 0]),
+            (Op::End, [0, 0]),
+            (Op::Show, [ExpressionIndex::Sleep as u16, 0]),
+        ]);
+        for (sees_friend, adc, shown) in [
+            (true, 0, ExpressionIndex::Friend),
+            (false, 99, ExpressionIndex::Sleep),
+            (false, 100, ExpressionIndex::Default),
+        ] {
+            let mut vm = Vm::new(&clock, script.clone(), 1).unwrap();
+            let mut body = Recorder {
+                sees_friend,
+                adc,
+                ..Default::default()
+            };
+            assert_eq!(vm.run(&mut body), Status::Ended);
+            assert_eq!(body.shown, [shown]);
+        }
+    }
+
+    #[test]
+    fn brightness_is_overridden_and_restored() {
+        let clock = MockClock::new(0);
+        let script = script(&[
+            (Op::Bright, [40, 0]),
+            (Op::Bright, [CONFIGURED_BRIGHTNESS, 0]),
+        ]);
+        let mut vm = Vm::new(&clock, script, 1).unwrap();
+        let mut body = Recorder::default();
+        assert_eq!(vm.run(&mut body), Status::Ended);
+        assert_eq!(body.brightness, [Some(40), None]);
+    }
+}
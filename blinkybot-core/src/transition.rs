@@ -0,0 +1,392 @@
+//! Transitions between expressions.
+//!
+//! `Transitions` moves from one expression to the next with a
+//! `TransitionEffect`, producing `Levels` frames that give every pixel its
+//! own brightness until the new expression is reached.  A new expression
+//! can be set at any time and its transition starts from whatever is being
+//! shown, so nothing jumps when expressions change in quick succession.
+
+use blinkybot_rpc::{
+    Expression, TransitionEffect, TransitionKind, TransitionStyle, MAX_HEIGHT, MAX_WIDTH,
+};
+
+use crate::clock::MonotonicClock;
+
+/// Brightness of a fully lit pixel.
+pub const FULL: u8 = u8::MAX;
+/// Progress of a finished transition.
+const DONE: u32 = 256;
+
+/// Brightness of every pixel, from 0 (off) to `FULL`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Levels {
+    /// Row by row, so `levels[y][x]` is pixel `(x, y)`.
+    pub levels: [[u8; MAX_WIDTH as usize]; MAX_HEIGHT as usize],
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Self {
+            levels: [[0; MAX_WIDTH as usize]; MAX_HEIGHT as usize],
+        }
+    }
+}
+
+impl From<&Expression> for Levels {
+    fn from(expression: &Expression) -> Self {
+        let mut levels = Self::default();
+        for (y, row) in levels.levels.iter_mut().enumerate() {
+            for (x, level) in row.iter_mut().enumerate() {
+                if expression.get_pixel(x as u32, y as u32) {
+                    *level = FULL;
+                }
+            }
+        }
+        levels
+    }
+}
+
+impl Levels {
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        if x < MAX_WIDTH && y < MAX_HEIGHT {
+            self.levels[y as usize][x as usize]
+        } else {
+            0
+        }
+    }
+
+    /// Returns the pixels that are at least half lit, for displays without
+    /// per-pixel brightness.
+    pub fn threshold(&self) -> Expression {
+        let mut expression = Expression::default();
+        for (y, row) in self.levels.iter().enumerate() {
+            for (x, &level) in row.iter().enumerate() {
+                expression.set_pixel(x as u32, y as u32, level > FULL / 2);
+            }
+        }
+        expression
+    }
+}
+
+struct Transition {
+    from: Levels,
+    to: Expression,
+    effect: TransitionEffect,
+    start_ms: u64,
+    duration_ms: u64,
+}
+
+impl Transition {
+    /// Returns how far along the transition is at `now_ms`, from 0 to
+    /// `DONE`.
+    fn progress(&self, now_ms: u64) -> u32 {
+        let elapsed_ms = now_ms.saturating_sub(self.start_ms);
+        if elapsed_ms >= self.duration_ms {
+            return DONE;
+        }
+        (elapsed_ms * DONE as u64 / self.duration_ms) as u32
+    }
+
+    fn render(&self, progress: u32, width: u8, height: u8) -> Levels {
+        let mut levels = Levels::default();
+        let (width, height) = (width as u32, height as u32);
+        for y in 0..height.min(MAX_HEIGHT) {
+            for x in 0..width.min(MAX_WIDTH) {
+                let from = self.from.get(x, y) as u32;
+                let to = if self.to.get_pixel(x, y) { FULL } else { 0 } as u32;
+                let level = match self.effect {
+                    TransitionEffect::Cut => to,
+                    TransitionEffect::CrossFade => mix(from, to, progress),
+                    TransitionEffect::Wipe => {
+                        // A one pixel wide soft edge sweeping across and
+                        // just off the display.
+                        let edge = progress * (width + 1);
+                        mix(from, to, edge.saturating_sub(x * DONE).min(DONE))
+                    }
+                    TransitionEffect::Dissolve => {
+                        if progress > scatter(x, y) {
+                            to
+                        } else {
+                            from
+                        }
+                    }
+                    TransitionEffect::Eyelid => {
+                        // Close over the old expression in the first half and
+                        // open on the new one in the second.
+                        let (face, openness) = if progress < DONE / 2 {
+                            (from, DONE - 2 * progress)
+                        } else {
+                            (to, 2 * progress - DONE)
+                        };
+                        // Distance from the middle row, in half rows.
+                        let distance = (2 * y).abs_diff(height - 1);
+                        if distance * DONE < openness * height {
+                            face
+                        } else {
+                            0
+                        }
+                    }
+                };
+                levels.levels[y as usize][x as usize] = level as u8;
+            }
+        }
+        levels
+    }
+}
+
+/// Mixes `from` into `to` as `progress` goes from 0 to `DONE`.
+fn mix(from: u32, to: u32, progress: u32) -> u32 {
+    (from * (DONE - progress) + to * progress) / DONE
+}
+
+/// Returns a scattered progress from 0 to 255 at which pixel `(x, y)`
+/// dissolves.
+fn scatter(x: u32, y: u32) -> u32 {
+    let mut hash = (x | y << 8).wrapping_mul(0x9e37_79b1);
+    hash ^= hash >> 15;
+    hash.wrapping_mul(0x85eb_ca6b) >> 24
+}
+
+pub struct Transitions<C> {
+    clock: C,
+    width: u8,
+    height: u8,
+    /// Ordered like `TransitionKind::ALL`.
+    styles: [TransitionStyle; TransitionKind::ALL.len()],
+    /// Expression being shown or transitioned to.
+    target: Option<Expression>,
+    active: Option<Transition>,
+}
+
+impl<C: MonotonicClock> Transitions<C> {
+    /// Creates transitions for a `width` by `height` display.
+    pub fn new(
+        clock: C,
+        width: u8,
+        height: u8,
+        styles: [TransitionStyle; TransitionKind::ALL.len()],
+    ) -> Self {
+        Self {
+            clock,
+            width,
+            height,
+            styles,
+            target: None,
+            active: None,
+        }
+    }
+
+    pub fn style(&self, kind: TransitionKind) -> TransitionStyle {
+        self.styles[kind as usize]
+    }
+
+    /// Sets the styles used by transitions started afterwards.
+    pub fn set_styles(&mut self, styles: [TransitionStyle; TransitionKind::ALL.len()]) {
+        self.styles = styles;
+    }
+
+    /// Moves to `face` with the `kind` transition.  Does nothing if `face`
+    /// is already being shown or moved to.  The first face is shown
+    /// straight away.
+    pub fn show(&mut self, face: &Expression, kind: TransitionKind) {
+        if self.target.as_ref() == Some(face) {
+            return;
+        }
+        let now_ms = self.clock.now_ms();
+        let from = match (&self.active, &self.target) {
+            (Some(active), _) => active.render(active.progress(now_ms), self.width, self.height),
+            (None, Some(target)) => Levels::from(target),
+            (None, None) => {
+                self.target = Some(face.clone());
+                return;
+            }
+        };
+        let style = self.style(kind);
+        self.active =
+            (style.effect != TransitionEffect::Cut && style.duration_ms > 0).then(|| Transition {
+                from,
+                to: face.clone(),
+                effect: style.effect,
+                start_ms: now_ms,
+                duration_ms: style.duration_ms.into(),
+            });
+        self.target = Some(face.clone());
+    }
+
+    /// Forgets what is shown, so that the next face is shown straight away.
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.active = None;
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Returns the frame to show now, or `None` once the transition is over
+    /// and the target expression can be shown as it is.
+    pub fn frame(&mut self) -> Option<Levels> {
+        let active = self.active.as_ref()?;
+        let progress = active.progress(self.clock.now_ms());
+        if progress >= DONE {
+            self.active = None;
+            return None;
+        }
+        Some(active.render(progress, self.width, self.height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    const WIDTH: u8 = 4;
+    const HEIGHT: u8 = 5;
+
+    fn transitions(clock: &MockClock, effect: TransitionEffect) -> Transitions<&MockClock> {
+        let style = TransitionStyle::new(effect, 1000);
+        Transitions::new(clock, WIDTH, HEIGHT, [style; TransitionKind::ALL.len()])
+    }
+
+    /// Starts moving from a fully lit display to `to` at time 0.
+    fn start<'a>(
+        clock: &'a MockClock,
+        effect: TransitionEffect,
+        to: &Expression,
+    ) -> Transitions<&'a MockClock> {
+        clock.set(0);
+        let mut transitions = transitions(clock, effect);
+        transitions.show(&lit(), TransitionKind::Mood);
+        transitions.show(to, TransitionKind::Mood);
+        transitions
+    }
+
+    fn lit() -> Expression {
+        Expression::from_rows(&[0xf; HEIGHT as usize])
+    }
+
+    fn row(levels: &Levels, y: u32) -> Vec<u8> {
+        (0..WIDTH.into()).map(|x| levels.get(x, y)).collect()
+    }
+
+    fn column(levels: &Levels, x: u32) -> Vec<u8> {
+        (0..HEIGHT.into()).map(|y| levels.get(x, y)).collect()
+    }
+
+    #[test]
+    fn first_face_is_shown_straight_away() {
+        let clock = MockClock::new(0);
+        let mut transitions = transitions(&clock, TransitionEffect::CrossFade);
+        transitions.show(&lit(), TransitionKind::Mood);
+        assert!(!transitions.is_animating());
+        assert_eq!(transitions.frame(), None);
+    }
+
+    #[test]
+    fn cut_is_not_animated() {
+        let clock = MockClock::new(0);
+        let mut transitions = start(&clock, TransitionEffect::Cut, &Expression::default());
+        assert!(!transitions.is_animating());
+        assert_eq!(transitions.frame(), None);
+    }
+
+    #[test]
+    fn cross_fade_mixes_the_faces() {
+        let clock = MockClock::new(0);
+        let mut transitions = start(&clock, TransitionEffect::CrossFade, &Expression::default());
+        assert_eq!(row(&transitions.frame().unwrap(), 0), [FULL; 4]);
+        clock.set(500);
+        assert_eq!(row(&transitions.frame().unwrap(), 0), [127; 4]);
+        clock.set(1000);
+        assert_eq!(transitions.frame(), None);
+        assert!(!transitions.is_animating());
+    }
+
+    #[test]
+    fn wipe_sweeps_a_soft_edge_across() {
+        let clock = MockClock::new(0);
+        let mut transitions = start(&clock, TransitionEffect::Wipe, &Expression::default());
+        assert_eq!(row(&transitions.frame().unwrap(), 2), [FULL; 4]);
+        clock.set(500);
+        let frame = transitions.frame().unwrap();
+        for y in 0..HEIGHT.into() {
+            assert_eq!(row(&frame, y), [0, 0, 127, FULL]);
+        }
+        clock.set(700);
+        assert_eq!(row(&transitions.frame().unwrap(), 2), [0, 0, 0, 128]);
+        // The edge is off the display before the end.
+        clock.set(999);
+        assert_eq!(row(&transitions.frame().unwrap(), 2), [0; 4]);
+    }
+
+    #[test]
+    fn eyelid_closes_on_the_old_face_and_opens_on_the_new() {
+        let clock = MockClock::new(0);
+        let left = Expression::from_rows(&[1; HEIGHT as usize]);
+        let mut transitions = start(&clock, TransitionEffect::Eyelid, &left);
+        assert_eq!(column(&transitions.frame().unwrap(), 1), [FULL; 5]);
+        clock.set(250);
+        assert_eq!(
+            column(&transitions.frame().unwrap(), 1),
+            [0, FULL, FULL, FULL, 0]
+        );
+        clock.set(500);
+        let shut = transitions.frame().unwrap();
+        assert_eq!(shut, Levels::default());
+        clock.set(750);
+        let frame = transitions.frame().unwrap();
+        assert_eq!(column(&frame, 0), [0, FULL, FULL, FULL, 0]);
+        assert_eq!(column(&frame, 1), [0; 5]);
+    }
+
+    #[test]
+    fn dissolve_switches_pixels_one_by_one() {
+        let clock = MockClock::new(0);
+        let mut transitions = start(&clock, TransitionEffect::Dissolve, &Expression::default());
+        let mut switched = 0;
+        for at_ms in (0..1000).step_by(50) {
+            clock.set(at_ms);
+            let frame = transitions.frame().unwrap();
+            let levels = frame.levels[..HEIGHT.into()]
+                .iter()
+                .flat_map(|row| &row[..WIDTH.into()]);
+            assert!(levels.clone().all(|&level| level == 0 || level == FULL));
+            let now_switched = levels.filter(|&&level| level == 0).count();
+            assert!(now_switched >= switched);
+            switched = now_switched;
+        }
+        assert!(switched > 0);
+        clock.set(1000);
+        assert_eq!(transitions.frame(), None);
+    }
+
+    #[test]
+    fn interrupted_transitions_carry_on_from_the_frame_shown() {
+        let clock = MockClock::new(0);
+        let mut transitions = start(&clock, TransitionEffect::CrossFade, &Expression::default());
+        clock.set(500);
+        let shown = transitions.frame().unwrap();
+        transitions.show(&lit(), TransitionKind::Mood);
+        assert_eq!(transitions.frame(), Some(shown));
+        clock.set(1500);
+        assert_eq!(transitions.frame(), None);
+    }
+
+    #[test]
+    fn threshold_keeps_pixels_at_least_half_lit() {
+        let clock = MockClock::new(0);
+        let mut transitions = start(&clock, TransitionEffect::CrossFade, &Expression::default());
+        clock.set(499);
+        assert_eq!(transitions.frame().unwrap().threshold(), lit());
+        clock.set(500);
+        assert_eq!(
+            transitions.frame().unwrap().threshold(),
+            Expression::default()
+        );
+
+        let mut levels = Levels::default();
+        levels.levels[0][..3].copy_from_slice(&[FULL / 2, FULL / 2 + 1, FULL]);
+        assert_eq!(levels.threshold(), Expression::from_rows(&[0b110]));
+    }
+}
@@ -0,0 +1,68 @@
+//! Ambient light sensing and automatic brightness.
+
+use blinkybot_core::ambient::AutoLevel;
+use blinkybot_rpc::{BrightnessMode, ScheduleEntry, ScheduledDisplay};
+use defmt::info;
+use embassy_futures::select::{select, select4, Either, Either4};
+
+use crate::webusb::Comms;
+
+/// Computes the display brightness from the brightness settings, the ambient
+/// light level and the active schedule entry and publishes it to
+/// `comms.display_brightness`.
+pub async fn brightness_control(comms: &Comms) -> ! {
+    let mut mode_receiver = comms.brightness_mode.dyn_receiver().unwrap();
+    let mut manual_receiver = comms.brightness_val.dyn_receiver().unwrap();
+    let mut auto_receiver = comms.auto_brightness.dyn_receiver().unwrap();
+    let mut ambient_receiver = comms.ambient_val.dyn_receiver().unwrap();
+    let mut schedule_receiver = comms.active_schedule.dyn_receiver().unwrap();
+    let sender = comms.display_brightness.dyn_sender();
+
+    let mut mode = mode_receiver.get().await;
+    let mut manual = manual_receiver.get().await;
+    let mut auto = auto_receiver.get().await;
+    let mut ambient = ambient_receiver.get().await;
+    let mut schedule = schedule_receiver.get().await;
+    let mut auto_level = AutoLevel::new();
+    let mut current = None;
+
+    loop {
+        let level = match schedule {
+            Some(ScheduleEntry {
+                display: ScheduledDisplay::Blank,
+                ..
+            }) => 0,
+            Some(ScheduleEntry {
+                brightness: Some(level),
+                ..
+            }) => level,
+            _ => match mode {
+                BrightnessMode::Manual => manual,
+                BrightnessMode::Auto => auto_level.update(&auto, ambient),
+            },
+        };
+        if current != Some(level) {
+            info!("display brightness {} ({})", level, mode);
+            sender.send(level);
+            current = Some(level);
+        }
+
+        match select(
+            select4(
+                mode_receiver.changed(),
+                manual_receiver.changed(),
+                auto_receiver.changed(),
+                ambient_receiver.changed(),
+            ),
+            schedule_receiver.changed(),
+        )
+        .await
+        {
+            Either::First(Either4::First(val)) => mode = val,
+            Either::First(Either4::Second(val)) => manual = val,
+            Either::First(Either4::Third(val)) => auto = val,
+            Either::First(Either4::Fourth(val)) => ambient = val,
+            Either::Second(val) => schedule = val,
+        }
+    }
+}
@@ -0,0 +1,830 @@
+//! What the bot shows and when.
+//!
+//! In `FaceMode::Expressions` the stored expressions are preloaded into the
+//! display and flipped between.  In `FaceMode::Procedural` a face drawn from
+//! `FaceParams` is animated a frame at a time.  Either way the face follows
+//! the bot's mood.  Friends in the friend table bring their own expressions,
+//! which are shown instead of the `Friend` and `FriendBlink` ones.  With
+//! `BlinkSync` enabled the bot blinks after the friend leading the blinking
+//! instead of when it likes.  Changes of expression are animated with the
+//! `TransitionStyle` configured for their `TransitionKind`, after which the
+//! preloaded frame is shown.
+//!
+//! In `FaceMode::Script` the uploaded `Script` picks the expressions and
+//! the brightness instead, restarting whenever it is changed or the mode is
+//! entered.  The bot still sleeps on schedule but only blinks when the
+//! script says so.
+//!
+//! In `FaceMode::Clock` the time is drawn, as dashes until the host has set
+//! it, in turn with the expressions as set in `ClockConfig`.  A button can
+//! toggle the clock in and out of any other mode, or switch to the next
+//! mode until the host sets one.  The clock isn't shown while the bot
+//! sleeps.
+//!
+//! What the bot does is published to the host as `BehaviorEvent`s.
+
+use blinkybot_core::clock::MonotonicClock;
+use blinkybot_core::clock_face::ClockFace;
+use blinkybot_core::face::{self, Animator, FaceParams};
+use blinkybot_core::gamma;
+use blinkybot_core::mood::{MoodMachine, Stimulus};
+use blinkybot_core::script::{Body, Status, Vm};
+use blinkybot_core::transition::Transitions;
+use blinkybot_rpc::{
+    BehaviorEvent, BehaviorEventKind, BlinkSync, BrightnessCurve, ButtonAction, ButtonBindings,
+    ButtonEvent, ClockConfig, ConfigChange, DisplayInfo, Expression, ExpressionIndex, FaceMode,
+    Friend, FriendId, Gaze, Mood, MoodStyle, PeerInfo, ScheduleEntry, ScheduledDisplay, Script,
+    TransitionKind, TransitionStyle, MAX_BUTTONS, MAX_FRIENDS,
+};
+use defmt::{error, info};
+use embassy_futures::select::{select4, Either4};
+use embassy_sync::channel::DynamicSender;
+use embassy_sync::pubsub::DynSubscriber;
+use embassy_sync::watch::DynReceiver;
+use embassy_time::{Duration, Instant, Timer};
+use oorandom::Rand32;
+
+use crate::clock::Clock;
+use crate::display::{Display, MatrixDisplay};
+use crate::webusb::Comms;
+
+/// How long the blink expression is shown.
+const BLINK_DURATION: Duration = Duration::from_millis(25);
+/// Time between frames of an animating procedural face or transition.
+const FRAME_INTERVAL: Duration = Duration::from_millis(face::FRAME_MS);
+/// Leader blinks this close after our last blink are taken to be the one
+/// already blinked, reported again.
+const SYNC_GUARD: Duration = Duration::from_millis(500);
+
+/// Embassy's time since boot.
+pub(crate) struct EmbassyClock;
+
+impl MonotonicClock for EmbassyClock {
+    fn now_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+}
+
+pub(crate) fn is_friend(val: u16) -> bool {
+    val < 0x100
+}
+
+fn is_sleeping(schedule: Option<ScheduleEntry>) -> bool {
+    matches!(
+        schedule,
+        Some(ScheduleEntry {
+            display: ScheduledDisplay::Sleep,
+            ..
+        })
+    )
+}
+
+/// Returns the kind of transition used to go from showing `from` to
+/// showing `to`.
+fn transition_kind(from: ExpressionIndex, to: ExpressionIndex) -> TransitionKind {
+    let is_friend = |index| {
+        matches!(
+            index,
+            ExpressionIndex::Friend | ExpressionIndex::FriendBlink
+        )
+    };
+    match (from, to) {
+        (ExpressionIndex::Sleep, _) | (_, ExpressionIndex::Sleep) => TransitionKind::Sleep,
+        (ExpressionIndex::Default, ExpressionIndex::Blink)
+        | (ExpressionIndex::Blink, ExpressionIndex::Default)
+        | (ExpressionIndex::Friend, ExpressionIndex::FriendBlink)
+        | (ExpressionIndex::FriendBlink, ExpressionIndex::Friend) => TransitionKind::Blink,
+        _ if is_friend(from) || is_friend(to) => TransitionKind::Friend,
+        _ => TransitionKind::Mood,
+    }
+}
+
+/// Keeps the display's preloaded expression frames in sync with `Comms`.
+struct Faces<'a> {
+    /// Ordered like `ExpressionIndex::ALL`.
+    receivers: [DynReceiver<'a, Expression>; ExpressionIndex::ALL.len()],
+    /// What was last preloaded for each `ExpressionIndex`.
+    loaded: [Option<Expression>; ExpressionIndex::ALL.len()],
+    /// Friend whose expressions replace the `Friend` and `FriendBlink` ones.
+    friend: Option<Friend>,
+    friend_changed: bool,
+    /// Expression last shown or transitioned to.
+    shown: Option<ExpressionIndex>,
+    transitions: Transitions<EmbassyClock>,
+}
+
+impl<'a> Faces<'a> {
+    fn new(
+        comms: &'a Comms,
+        info: DisplayInfo,
+        styles: [TransitionStyle; TransitionKind::ALL.len()],
+    ) -> Self {
+        Self {
+            receivers: [
+                comms.default_expression.dyn_receiver().unwrap(),
+                comms.blink_expression.dyn_receiver().unwrap(),
+                comms.friend_expression.dyn_receiver().unwrap(),
+                comms.friend_blink_expression.dyn_receiver().unwrap(),
+                comms.sleep_expression.dyn_receiver().unwrap(),
+            ],
+            loaded: Default::default(),
+            friend: None,
+            friend_changed: false,
+            shown: None,
+            transitions: Transitions::new(EmbassyClock, info.width, info.height, styles),
+        }
+    }
+
+    fn set_transitions(&mut self, styles: [TransitionStyle; TransitionKind::ALL.len()]) {
+        self.transitions.set_styles(styles);
+    }
+
+    /// Forgets what was shown so that the next expression is shown without
+    /// a transition, e.g. after the display showed procedural faces.
+    fn forget_shown(&mut self) {
+        self.shown = None;
+        self.transitions.reset();
+    }
+
+    fn is_animating(&self) -> bool {
+        self.transitions.is_animating()
+    }
+
+    /// Returns how long the transition from `from` to `to` takes.
+    fn transition_duration(&self, from: ExpressionIndex, to: ExpressionIndex) -> Duration {
+        let style = self.transitions.style(transition_kind(from, to));
+        Duration::from_millis(style.duration_ms.into())
+    }
+
+    fn set_friend(&mut self, friend: Option<&Friend>) {
+        if friend != self.friend.as_ref() {
+            self.friend = friend.cloned();
+            self.friend_changed = true;
+        }
+    }
+
+    /// Sets the brightness, preloads expressions that changed since the last
+    /// call, or all of them if `all` is set, and then shows `face`, or the
+    /// current frame of the transition to it.
+    async fn show<M: MatrixDisplay>(
+        &mut self,
+        display: &mut Display<M>,
+        face: ExpressionIndex,
+        brightness: gamma::Brightness,
+        all: bool,
+    ) -> Result<(), M::Error> {
+        display.set_brightness(brightness).await?;
+        for (index, receiver) in ExpressionIndex::ALL.iter().zip(self.receivers.iter_mut()) {
+            let friend = match (index, &self.friend) {
+                (ExpressionIndex::Friend, Some(friend)) => Some(&friend.expression),
+                (ExpressionIndex::FriendBlink, Some(friend)) => Some(&friend.blink_expression),
+                _ => None,
+            };
+            let is_friend = matches!(
+                index,
+                ExpressionIndex::Friend | ExpressionIndex::FriendBlink
+            );
+            let expression = if all || (self.friend_changed && is_friend) {
+                Some(receiver.get().await)
+            } else {
+                receiver.try_changed()
+            };
+            if let Some(expression) = expression {
+                let expression = friend.unwrap_or(&expression);
+                display.preload(*index, expression).await?;
+                self.loaded[*index as usize] = Some(expression.clone());
+            }
+        }
+        self.friend_changed = false;
+
+        if let Some(expression) = &self.loaded[face as usize] {
+            let kind = transition_kind(self.shown.unwrap_or(face), face);
+            self.transitions.show(expression, kind);
+            self.shown = Some(face);
+        }
+        match self.transitions.frame() {
+            Some(levels) => display.show_levels(&levels).await,
+            None => display.show_preloaded(face).await,
+        }
+    }
+}
+
+/// Something that the behavior reacts to.
+enum Event {
+    Timer,
+    Adc(u16),
+    Brightness(u8),
+    BrightnessCurve(BrightnessCurve),
+    Schedule(Option<ScheduleEntry>),
+    FaceMode(FaceMode),
+    Gaze(Option<Gaze>),
+    Transitions([TransitionStyle; TransitionKind::ALL.len()]),
+    ForcedMood(Option<Mood>),
+    MoodStyles([MoodStyle; Mood::ALL.len()]),
+    Button(ButtonEvent),
+    ButtonBindings([ButtonBindings; MAX_BUTTONS]),
+    Peer(Option<PeerInfo>),
+    Friends([Option<Friend>; MAX_FRIENDS]),
+    LeaderBlink(Instant),
+    BlinkSync(BlinkSync),
+    Script(Script),
+    Clock(Clock),
+    ClockConfig(ClockConfig),
+}
+
+struct Inputs<'a> {
+    adc: DynReceiver<'a, u16>,
+    brightness: DynReceiver<'a, u8>,
+    brightness_curve: DynReceiver<'a, BrightnessCurve>,
+    schedule: DynReceiver<'a, Option<ScheduleEntry>>,
+    face_mode: DynReceiver<'a, FaceMode>,
+    gaze: DynReceiver<'a, Option<Gaze>>,
+    transitions: DynReceiver<'a, [TransitionStyle; TransitionKind::ALL.len()]>,
+    forced_mood: DynReceiver<'a, Option<Mood>>,
+    mood_styles: DynReceiver<'a, [MoodStyle; Mood::ALL.len()]>,
+    button: DynSubscriber<'a, ButtonEvent>,
+    button_bindings: DynReceiver<'a, [ButtonBindings; MAX_BUTTONS]>,
+    peer: DynReceiver<'a, Option<PeerInfo>>,
+    friends: DynReceiver<'a, [Option<Friend>; MAX_FRIENDS]>,
+    leader_blink: DynReceiver<'a, Instant>,
+    blink_sync: DynReceiver<'a, BlinkSync>,
+    script: DynReceiver<'a, Script>,
+    clock: DynReceiver<'a, Clock>,
+    clock_config: DynReceiver<'a, ClockConfig>,
+}
+
+impl<'a> Inputs<'a> {
+    fn new(comms: &'a Comms) -> Self {
+        Self {
+            adc: comms.adc_val.dyn_receiver().unwrap(),
+            brightness: comms.display_brightness.dyn_receiver().unwrap(),
+            brightness_curve: comms.brightness_curve.dyn_receiver().unwrap(),
+            schedule: comms.active_schedule.dyn_receiver().unwrap(),
+            face_mode: comms.face_mode.dyn_receiver().unwrap(),
+            gaze: comms.gaze.dyn_receiver().unwrap(),
+            transitions: comms.transitions.dyn_receiver().unwrap(),
+            forced_mood: comms.forced_mood.dyn_receiver().unwrap(),
+            mood_styles: comms.mood_styles.dyn_receiver().unwrap(),
+            button: comms.button_event.dyn_subscriber().unwrap(),
+            button_bindings: comms.button_bindings.dyn_receiver().unwrap(),
+            peer: comms.peer.dyn_receiver().unwrap(),
+            friends: comms.friends.dyn_receiver().unwrap(),
+            leader_blink: comms.leader_blink.dyn_receiver().unwrap(),
+            blink_sync: comms.blink_sync.dyn_receiver().unwrap(),
+            script: comms.script.dyn_receiver().unwrap(),
+            clock: comms.clock.dyn_receiver().unwrap(),
+            clock_config: comms.clock_config.dyn_receiver().unwrap(),
+        }
+    }
+
+    /// Waits for the next input to change, or for `until`.
+    async fn next(&mut self, until: Instant) -> Event {
+        match select4(
+            select4(
+                Timer::at(until),
+                self.adc.changed(),
+                self.brightness.changed(),
+                self.brightness_curve.changed(),
+            ),
+            select4(
+                self.schedule.changed(),
+                self.face_mode.changed(),
+                self.gaze.changed(),
+                self.transitions.changed(),
+            ),
+            select4(
+                self.forced_mood.changed(),
+                self.mood_styles.changed(),
+                self.button.next_message_pure(),
+                self.button_bindings.changed(),
+            ),
+            select4(
+                self.peer.changed(),
+                self.friends.changed(),
+                self.leader_blink.changed(),
+                select4(
+                    self.blink_sync.changed(),
+                    self.script.changed(),
+                    self.clock.changed(),
+                    self.clock_config.changed(),
+                ),
+            ),
+        )
+        .await
+        {
+            Either4::First(Either4::First(_)) => Event::Timer,
+            Either4::First(Either4::Second(val)) => Event::Adc(val),
+            Either4::First(Either4::Third(val)) => Event::Brightness(val),
+            Either4::First(Either4::Fourth(val)) => Event::BrightnessCurve(val),
+            Either4::Second(Either4::First(val)) => Event::Schedule(val),
+            Either4::Second(Either4::Second(val)) => Event::FaceMode(val),
+            Either4::Second(Either4::Third(val)) => Event::Gaze(val),
+            Either4::Second(Either4::Fourth(val)) => Event::Transitions(val),
+            Either4::Third(Either4::First(val)) => Event::ForcedMood(val),
+            Either4::Third(Either4::Second(val)) => Event::MoodStyles(val),
+            Either4::Third(Either4::Third(val)) => Event::Button(val),
+            Either4::Third(Either4::Fourth(val)) => Event::ButtonBindings(val),
+            Either4::Fourth(Either4::First(val)) => Event::Peer(val),
+            Either4::Fourth(Either4::Second(val)) => Event::Friends(val),
+            Either4::Fourth(Either4::Third(val)) => Event::LeaderBlink(val),
+            Either4::Fourth(Either4::Fourth(Either4::First(val))) => Event::BlinkSync(val),
+            Either4::Fourth(Either4::Fourth(Either4::Second(val))) => Event::Script(val),
+            Either4::Fourth(Either4::Fourth(Either4::Third(val))) => Event::Clock(val),
+            Either4::Fourth(Either4::Fourth(Either4::Fourth(val))) => Event::ClockConfig(val),
+        }
+    }
+}
+
+struct State {
+    mood: Mood,
+    styles: [MoodStyle; Mood::ALL.len()],
+    bindings: [ButtonBindings; MAX_BUTTONS],
+    sleeping: bool,
+    brightness: u8,
+    /// Display turned off with a button.
+    display_off: bool,
+    curve: BrightnessCurve,
+    /// Mode shown, which a button can switch away from the stored one until
+    /// the host sets it again.
+    face_mode: FaceMode,
+    gaze: Option<Gaze>,
+    /// Last reading of the sense input.
+    adc: u16,
+    peer: Option<PeerInfo>,
+    friends: [Option<Friend>; MAX_FRIENDS],
+    blink_sync: BlinkSync,
+    /// Expression last shown by the script.
+    script_face: ExpressionIndex,
+    /// Brightness set by the script, replacing the configured one.
+    script_brightness: Option<u8>,
+    /// Clock toggled with a button, showing it in modes other than
+    /// `FaceMode::Clock` and hiding it in that mode.
+    clock_toggled: bool,
+}
+
+impl State {
+    fn brightness(&self) -> gamma::Brightness {
+        let brightness = match (self.face_mode, self.script_brightness) {
+            _ if self.display_off => 0,
+            (FaceMode::Script, Some(brightness)) => brightness,
+            _ => self.brightness,
+        };
+        gamma::Brightness::new(self.curve, brightness)
+    }
+
+    /// Whether the bot blinks by itself, rather than being asleep or left
+    /// to the script.
+    fn blinks(&self) -> bool {
+        !self.sleeping && self.face_mode != FaceMode::Script
+    }
+
+    /// Whether the clock is shown, in turn with the face.
+    fn shows_clock(&self) -> bool {
+        !self.sleeping && (self.face_mode == FaceMode::Clock) != self.clock_toggled
+    }
+
+    /// Returns the entry of the friend being seen.  Bots that said hello
+    /// are matched by serial before any ADC bands are tried.
+    fn friend(&self) -> Option<&Friend> {
+        let serial = self.peer.map(|peer| peer.serial);
+        let find = |by_serial: bool| {
+            self.friends.iter().flatten().find(|friend| {
+                matches!(friend.id, FriendId::Serial(_)) == by_serial
+                    && friend.id.matches(serial, self.adc)
+            })
+        };
+        find(true).or_else(|| find(false))
+    }
+
+    /// Whether a friend is seen, either a bot or something in the friend
+    /// table.
+    fn sees_friend(&self) -> bool {
+        is_friend(self.adc) || self.friend().is_some()
+    }
+
+    fn style(&self) -> &MoodStyle {
+        &self.styles[self.mood as usize]
+    }
+
+    fn expression(&self, blinking: bool) -> ExpressionIndex {
+        match (self.sleeping, blinking) {
+            (true, _) => ExpressionIndex::Sleep,
+            (false, false) => self.style().expression,
+            (false, true) => self.style().blink_expression,
+        }
+    }
+
+    /// Returns when to blink next, or `None` if the mood doesn't blink.
+    fn next_blink(&self, now: Instant, rng: &mut Rand32) -> Option<Instant> {
+        let style = self.style();
+        if style.blink_max_ms == 0 {
+            return None;
+        }
+        let min = style.blink_min_ms.min(style.blink_max_ms);
+        let ms = rng.rand_range(min..style.blink_max_ms.saturating_add(1));
+        Some(now + Duration::from_millis(ms.into()))
+    }
+
+    /// Returns the procedural face to animate towards, looking at `gaze`
+    /// unless the host has set where to look.
+    fn face_params(&self, gaze: (f32, f32)) -> FaceParams {
+        let mut params = FaceParams::for_mood(self.mood);
+        if self.sleeping {
+            params.openness = 0.0;
+            params.mouth_curve = 0.0;
+        }
+        (params.gaze_x, params.gaze_y) = match self.gaze {
+            Some(gaze) => (gaze.x as f32 / 100.0, gaze.y as f32 / 100.0),
+            None => gaze,
+        };
+        params
+    }
+}
+
+impl Body for State {
+    fn show(&mut self, expression: ExpressionIndex) {
+        self.script_face = expression;
+    }
+
+    fn set_brightness(&mut self, brightness: Option<u8>) {
+        self.script_brightness = brightness;
+    }
+
+    fn sees_friend(&self) -> bool {
+        State::sees_friend(self)
+    }
+
+    fn adc(&self) -> u16 {
+        self.adc
+    }
+}
+
+/// Sets `last` to `value`, returning whether it changed.
+fn changed<T: PartialEq>(last: &mut Option<T>, value: T) -> bool {
+    let changed = last.as_ref() != Some(&value);
+    *last = Some(value);
+    changed
+}
+
+/// Publishes `BehaviorEvent`s, keeping track of what was published so that
+/// only changes are, and the settings changed with buttons.
+struct Events<'a> {
+    sender: DynamicSender<'a, BehaviorEvent>,
+    config_changes: DynamicSender<'a, ConfigChange>,
+    face: Option<ExpressionIndex>,
+    face_mode: Option<FaceMode>,
+    mood: Option<Mood>,
+    friend: Option<bool>,
+    brightness: Option<u8>,
+    sleeping: Option<bool>,
+}
+
+impl<'a> Events<'a> {
+    fn new(comms: &'a Comms) -> Self {
+        Self {
+            sender: comms.behavior_events.dyn_sender(),
+            config_changes: comms.config_changes.dyn_sender(),
+            face: None,
+            face_mode: None,
+            mood: None,
+            friend: None,
+            brightness: None,
+            sleeping: None,
+        }
+    }
+
+    fn send(&self, kind: BehaviorEventKind) {
+        let event = BehaviorEvent {
+            uptime_ms: Instant::now().as_millis(),
+            kind,
+        };
+        // Drop events rather than hold up the behavior if the host falls
+        // behind.
+        let _ = self.sender.try_send(event);
+    }
+
+    /// Publishes a setting changed with a button.
+    fn changed(&self, change: ConfigChange) {
+        if self.config_changes.try_send(change).is_err() {
+            error!("Dropped config change, host is not keeping up");
+        }
+    }
+
+    /// Publishes whatever changed in `state` and the stored expression
+    /// shown, `None` for a procedural face or the clock.
+    fn update(&mut self, state: &State, face: Option<ExpressionIndex>) {
+        if self.face != face {
+            self.face = face;
+            if let Some(face) = face {
+                self.send(BehaviorEventKind::Expression(face));
+            }
+        }
+        if changed(&mut self.face_mode, state.face_mode) {
+            self.send(BehaviorEventKind::FaceMode(state.face_mode));
+        }
+        if changed(&mut self.mood, state.mood) {
+            self.send(BehaviorEventKind::Mood(state.mood));
+        }
+        let friend = state.sees_friend();
+        if changed(&mut self.friend, friend) {
+            self.send(BehaviorEventKind::Friend(friend));
+        }
+        let brightness = state.brightness().level;
+        if changed(&mut self.brightness, brightness) {
+            self.send(BehaviorEventKind::Brightness(brightness));
+        }
+        if changed(&mut self.sleeping, state.sleeping) {
+            self.send(BehaviorEventKind::Sleep(state.sleeping));
+        }
+    }
+}
+
+async fn show_face<M: MatrixDisplay>(
+    display: &mut Display<M>,
+    face: &Expression,
+    brightness: gamma::Brightness,
+) -> Result<(), M::Error> {
+    display.set_brightness(brightness).await?;
+    display.show(face).await
+}
+
+pub async fn behavior<M: MatrixDisplay>(mut display: Display<M>, comms: &Comms) -> ! {
+    let info = display.info();
+    let mut inputs = Inputs::new(comms);
+    let mut faces = Faces::new(comms, info, inputs.transitions.get().await);
+    let mut moods = MoodMachine::new(EmbassyClock);
+    moods.force(inputs.forced_mood.get().await);
+    let mut state = State {
+        mood: moods.mood(),
+        styles: inputs.mood_styles.get().await,
+        bindings: inputs.button_bindings.get().await,
+        sleeping: is_sleeping(inputs.schedule.get().await),
+        brightness: inputs.brightness.get().await,
+        display_off: false,
+        curve: inputs.brightness_curve.get().await,
+        face_mode: inputs.face_mode.get().await,
+        gaze: inputs.gaze.get().await,
+        adc: inputs.adc.get().await,
+        peer: inputs.peer.get().await,
+        friends: inputs.friends.get().await,
+        blink_sync: inputs.blink_sync.get().await,
+        script_face: ExpressionIndex::Default,
+        script_brightness: None,
+        clock_toggled: false,
+    };
+    moods.stimulate(Stimulus::Friend(state.sees_friend()));
+    state.mood = moods.mood();
+    let mood_sender = comms.mood.dyn_sender();
+    mood_sender.send(state.mood);
+    faces.set_friend(state.friend());
+    let mut animator = Animator::new(EmbassyClock, state.face_params((0.0, 0.0)));
+    // Last procedural face shown.
+    let mut shown: Option<Expression> = None;
+
+    let mut rng = Rand32::new(0);
+    let mut next_blink = state.next_blink(Instant::now(), &mut rng);
+    // End of the blink expression being shown.
+    let mut blink_end: Option<Instant> = None;
+    let mut last_blink: Option<Instant> = None;
+    let next_blink_sender = comms.next_blink.dyn_sender();
+    // Last `next_blink` sent, `None` while not blinking by itself.
+    let mut announced_blink: Option<Instant> = None;
+    let mut reload = true;
+    let mut script = inputs.script.get().await;
+    // Started on entering `FaceMode::Script`, dropped on leaving it.
+    let mut vm: Option<Vm<EmbassyClock>> = None;
+    let mut events = Events::new(comms);
+    // Only set once the host has set the time.
+    let mut clock = inputs.clock.try_get();
+    let mut clock_face = ClockFace::new(
+        EmbassyClock,
+        info.width,
+        info.height,
+        inputs.clock_config.get().await,
+    );
+    let mut clock_shown = false;
+
+    display.recover().await;
+
+    loop {
+        if state.face_mode == FaceMode::Script {
+            if vm.is_none() {
+                state.script_face = ExpressionIndex::Default;
+                state.script_brightness = None;
+                match Vm::new(EmbassyClock, script.clone(), rng.rand_u32()) {
+                    Ok(started) => vm = Some(started),
+                    Err(e) => error!("Failed to start script: {}", e),
+                }
+            }
+            if let Some(vm) = &mut vm {
+                let busy = vm.status() == Status::Busy;
+                if vm.run(&mut state) == Status::Busy && !busy {
+                    error!("Script stopped for running without waiting");
+                }
+            }
+        } else {
+            vm = None;
+        }
+
+        // The clock starts from the time whenever it comes back.
+        if state.shows_clock() && !clock_shown {
+            clock_face.restart();
+        }
+        clock_shown = state.shows_clock();
+        let time = clock.map(|clock| clock.now());
+        let clock_frame = if clock_shown {
+            clock_face.frame(time)
+        } else {
+            None
+        };
+
+        animator.update();
+        let brightness = state.brightness();
+        let face = match state.face_mode {
+            _ if clock_frame.is_some() => None,
+            FaceMode::Expressions | FaceMode::Clock => Some(state.expression(blink_end.is_some())),
+            FaceMode::Script if state.sleeping => Some(ExpressionIndex::Sleep),
+            FaceMode::Script => Some(state.script_face),
+            FaceMode::Procedural => None,
+        };
+        let result = match face {
+            Some(face) => {
+                shown = None;
+                faces.show(&mut display, face, brightness, reload).await
+            }
+            None => {
+                faces.forget_shown();
+                let face =
+                    clock_frame.unwrap_or_else(|| animator.frame().render(info.width, info.height));
+                if reload || shown.as_ref() != Some(&face) {
+                    let result = show_face(&mut display, &face, brightness).await;
+                    shown = Some(face);
+                    result
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to update display: {}", e);
+            display.recover().await;
+            shown = None;
+            reload = true;
+            continue;
+        }
+        reload = false;
+        events.update(&state, face);
+
+        let mut until = next_blink.unwrap_or(Instant::MAX);
+        if let Some(end) = blink_end {
+            until = until.min(end);
+        }
+        if let Some(deadline) = moods.deadline_ms() {
+            until = until.min(Instant::from_millis(deadline));
+        }
+        if let Some(Status::Waiting { until_ms }) = vm.as_ref().map(Vm::status) {
+            until = until.min(Instant::from_millis(until_ms));
+        }
+        if clock_shown {
+            until = until.min(Instant::from_millis(clock_face.deadline_ms(time)));
+        }
+        let animating = match state.face_mode {
+            FaceMode::Expressions | FaceMode::Script | FaceMode::Clock => faces.is_animating(),
+            FaceMode::Procedural => animator.is_animating(),
+        };
+        if animating {
+            until = until.min(Instant::now() + FRAME_INTERVAL);
+        }
+
+        match inputs.next(until).await {
+            Event::Timer => {
+                let now = Instant::now();
+                if blink_end.is_some_and(|end| now >= end) {
+                    blink_end = None;
+                }
+                if next_blink.is_some_and(|blink| now >= blink) {
+                    next_blink = state.next_blink(now, &mut rng);
+                    if state.blinks() {
+                        info!("blink");
+                        events.send(BehaviorEventKind::Blink);
+                        last_blink = Some(now);
+                        match state.face_mode {
+                            FaceMode::Expressions | FaceMode::Clock => {
+                                // Hold the blink for its full duration once
+                                // the transition into it is over.
+                                let into = faces.transition_duration(
+                                    state.expression(false),
+                                    state.expression(true),
+                                );
+                                blink_end = Some(now + into + BLINK_DURATION);
+                            }
+                            FaceMode::Procedural => {
+                                animator.blink();
+                                if rng.rand_range(0..2) == 0 {
+                                    animator.wander(rng.rand_u32());
+                                }
+                            }
+                            FaceMode::Script => {}
+                        }
+                    }
+                }
+            }
+            Event::Adc(val) => {
+                state.adc = val;
+                moods.stimulate(Stimulus::Friend(state.sees_friend()));
+            }
+            Event::Brightness(val) => {
+                state.brightness = val;
+                reload = true;
+            }
+            Event::BrightnessCurve(val) => {
+                state.curve = val;
+                reload = true;
+            }
+            Event::Schedule(val) => state.sleeping = is_sleeping(val),
+            Event::FaceMode(val) => {
+                state.face_mode = val;
+                state.clock_toggled = false;
+                reload = true;
+            }
+            Event::Gaze(val) => state.gaze = val,
+            Event::Transitions(val) => faces.set_transitions(val),
+            Event::ForcedMood(val) => moods.force(val),
+            Event::MoodStyles(val) => {
+                state.styles = val;
+                next_blink = state.next_blink(Instant::now(), &mut rng);
+            }
+            Event::Button(event) => {
+                let action = state
+                    .bindings
+                    .get(event.button as usize)
+                    .map_or(ButtonAction::Nothing, |bindings| {
+                        bindings.action(event.gesture)
+                    });
+                match action {
+                    ButtonAction::Nothing => {}
+                    ButtonAction::Touch => moods.stimulate(Stimulus::Touch),
+                    ButtonAction::CycleMood => {
+                        moods.cycle();
+                        events.changed(ConfigChange::ForcedMood(moods.forced()));
+                    }
+                    ButtonAction::ToggleBrightness => {
+                        state.display_off = !state.display_off;
+                        events.changed(ConfigChange::DisplayOff(state.display_off));
+                        reload = true;
+                    }
+                    ButtonAction::ToggleClock => {
+                        state.clock_toggled = !state.clock_toggled;
+                        events.changed(ConfigChange::ClockToggled(state.clock_toggled));
+                        reload = true;
+                    }
+                    ButtonAction::CycleFaceMode => {
+                        state.face_mode = state.face_mode.next();
+                        state.clock_toggled = false;
+                        events.changed(ConfigChange::FaceModeCycled(state.face_mode));
+                        reload = true;
+                    }
+                }
+            }
+            Event::ButtonBindings(val) => state.bindings = val,
+            Event::Peer(val) => state.peer = val,
+            Event::Friends(val) => {
+                state.friends = val;
+                moods.stimulate(Stimulus::Friend(state.sees_friend()));
+            }
+            Event::LeaderBlink(at) => {
+                let at = at + Duration::from_millis(state.blink_sync.cascade_ms.into());
+                let blinks = state.style().blink_max_ms != 0;
+                let seen = last_blink.is_some_and(|last| at < last + SYNC_GUARD);
+                if state.blink_sync.enabled && blinks && !seen && at > Instant::now() {
+                    next_blink = Some(at);
+                }
+            }
+            Event::BlinkSync(val) => state.blink_sync = val,
+            Event::Script(val) => {
+                script = val;
+                vm = None;
+            }
+            Event::Clock(val) => clock = Some(val),
+            Event::ClockConfig(val) => clock_face.set_config(val),
+        }
+        faces.set_friend(state.friend());
+
+        moods.update();
+        if moods.mood() != state.mood {
+            state.mood = moods.mood();
+            info!("mood: {}", state.mood);
+            mood_sender.send(state.mood);
+            next_blink = state.next_blink(Instant::now(), &mut rng);
+        }
+
+        let announce = next_blink.filter(|_| state.blinks());
+        if announce != announced_blink {
+            announced_blink = announce;
+            next_blink_sender.send(announce);
+        }
+
+        let gaze = (animator.target().gaze_x, animator.target().gaze_y);
+        animator.set_target(state.face_params(gaze));
+    }
+}
@@ -0,0 +1,61 @@
+//! Debounced GPIO buttons.
+//!
+//! Buttons are wired between their pin and ground, using the pin's pull up.
+//! Every gesture is sent to `Comms::button_event` for the behavior and the
+//! host.
+
+use blinkybot_core::button::{Button, ButtonTiming};
+use blinkybot_rpc::{ButtonEvent, MAX_BUTTONS};
+use defmt::info;
+use embassy_futures::join::join_array;
+use embassy_futures::select::select;
+use embassy_rp::gpio::Input;
+use embassy_time::{Instant, Timer};
+
+use crate::webusb::Comms;
+
+/// Inputs for the buttons fitted to the board, in button order, taken from
+/// the peripherals `$p`.  There can be up to `MAX_BUTTONS`.
+macro_rules! button_pins {
+    ($p:ident) => {
+        [
+            embassy_rp::gpio::Input::new($p.PIN_6, embassy_rp::gpio::Pull::Up),
+            embassy_rp::gpio::Input::new($p.PIN_7, embassy_rp::gpio::Pull::Up),
+        ]
+    };
+}
+
+/// Watches `pins`, which are numbered by their position.
+pub async fn buttons<const N: usize>(comms: &Comms, pins: [Input<'static>; N]) -> ! {
+    const { assert!(N > 0 && N <= MAX_BUTTONS) };
+    let mut index = 0;
+    let buttons = pins.map(|pin| {
+        index += 1;
+        button(comms, index - 1, pin)
+    });
+    let [first, ..] = join_array(buttons).await;
+    first
+}
+
+async fn button(comms: &Comms, index: u8, mut pin: Input<'static>) -> ! {
+    let publisher = comms.button_event.dyn_immediate_publisher();
+    let mut button = Button::new(ButtonTiming::default());
+    loop {
+        let now = Instant::now().as_millis();
+        button.set_level(now, pin.is_low());
+        while let Some(gesture) = button.update(now) {
+            let event = ButtonEvent {
+                button: index,
+                gesture,
+            };
+            info!("button: {}", event);
+            publisher.publish_immediate(event);
+        }
+
+        let until = button
+            .deadline_ms()
+            .map(Instant::from_millis)
+            .unwrap_or(Instant::MAX);
+        select(pin.wait_for_any_edge(), Timer::at(until)).await;
+    }
+}
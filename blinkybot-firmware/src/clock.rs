@@ -0,0 +1,86 @@
+//! Wall clock time and time of day schedules.
+//!
+//! The RP2350 has no battery backed clock on the Feather so the host sets the
+//! time when it connects.  The time is kept as an offset from the 64 bit
+//! system timer behind `Instant` which keeps counting for as long as the
+//! board is powered.
+
+use blinkybot_rpc::{ScheduleEntry, WallTime};
+use defmt::info;
+use embassy_futures::select::{select3, Either3};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::webusb::Comms;
+
+/// Wall clock time at boot.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    boot_unix_millis: u64,
+    utc_offset_minutes: i16,
+}
+
+impl Clock {
+    /// Creates a clock which reads `time` now.
+    pub fn new(time: WallTime) -> Self {
+        Self {
+            boot_unix_millis: time.unix_millis.saturating_sub(Instant::now().as_millis()),
+            utc_offset_minutes: time.utc_offset_minutes,
+        }
+    }
+
+    pub fn at(&self, instant: Instant) -> WallTime {
+        WallTime {
+            unix_millis: self.boot_unix_millis + instant.as_millis(),
+            utc_offset_minutes: self.utc_offset_minutes,
+        }
+    }
+
+    pub fn now(&self) -> WallTime {
+        self.at(Instant::now())
+    }
+
+    /// Returns the next instant a new local minute starts.
+    pub fn next_minute(&self) -> Instant {
+        let millis_into_minute = (self.now().local_millis_of_day() % (60 * 1000)) as u64;
+        Instant::now() + Duration::from_millis(60 * 1000 - millis_into_minute)
+    }
+}
+
+/// Tracks the active schedule entry and publishes it to
+/// `comms.active_schedule`.
+///
+/// No entry is active until the host has set the time.
+pub async fn scheduler(comms: &Comms) -> ! {
+    let mut clock_receiver = comms.clock.dyn_receiver().unwrap();
+    let mut schedule_receiver = comms.schedule.dyn_receiver().unwrap();
+    let sender = comms.active_schedule.dyn_sender();
+
+    let mut clock: Option<Clock> = clock_receiver.try_get();
+    let mut schedule = schedule_receiver.get().await;
+    let mut current: Option<Option<ScheduleEntry>> = None;
+
+    loop {
+        let active = clock.and_then(|clock| schedule.active(clock.now().local_minute_of_day()));
+        if current != Some(active) {
+            info!("schedule entry {}", active);
+            sender.send(active);
+            current = Some(active);
+        }
+
+        let next = match clock {
+            Some(clock) => clock.next_minute(),
+            None => Instant::MAX,
+        };
+        match select3(
+            Timer::at(next),
+            clock_receiver.changed(),
+            schedule_receiver.changed(),
+        )
+        .await
+        {
+            Either3::First(_) => (),
+            Either3::Second(val) => clock = Some(val),
+            Either3::Third(val) => schedule = val,
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use core::ops::Range;
 
-use defmt::error;
+use blinkybot_core::gamma;
+use defmt::{error, Format};
 use embedded_storage_async::nor_flash::NorFlash;
 use sequential_storage::{
     cache::NoCache,
@@ -9,14 +10,37 @@ use sequential_storage::{
 use serde::{Deserialize, Serialize};
 
 use crate::{Error, Result};
-use blinkybot_rpc::{Expression, ExpressionIndex};
+use blinkybot_rpc::{
+    AutoBrightness, BlinkSync, BrightnessCurve, BrightnessMode, ButtonAction, ButtonBindings,
+    ClockConfig, DeviceName, Expression, ExpressionIndex, FaceMode, Friend, Mood, MoodStyle,
+    Preset, Schedule, Script, TransitionEffect, TransitionKind, TransitionStyle, MAX_BRIGHTNESS,
+    MAX_FRIENDS, MAX_HEIGHT, MAX_NAME_LEN, MAX_SCHEDULE_ENTRIES, MAX_SCRIPT_LEN,
+};
 
 const POSTCARD_BYTES_PER_WORD: usize = 5;
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+// Variants are serialized by index so new keys must only be appended.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Format)]
 enum ConfigKey {
     ExpressionV0(ExpressionIndex),
+    // Raw PWM value.  Superseded by the perceptual `BrightnessV1`.
     BrightnessV0,
+    BrightnessV1,
+    BrightnessCurveV0,
+    BrightnessModeV0,
+    AutoBrightnessV0,
+    ScheduleV0,
+    ExpressionV1(ExpressionIndex),
+    DeviceNameV0,
+    FaceModeV0,
+    MoodStyleV0(Mood),
+    ButtonBindingsV0(u8),
+    /// Slot in the friend table.
+    FriendV0(u8),
+    BlinkSyncV0,
+    TransitionV0(TransitionKind),
+    ScriptV0,
+    ClockConfigV0,
 }
 
 impl ConfigKey {
@@ -46,15 +70,63 @@ impl Key for ConfigKey {
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 enum ConfigValue {
-    ExpressionV0(Expression),
+    // Only read to migrate to `ExpressionV1`.
+    #[allow(dead_code)]
+    ExpressionV0(ExpressionV0),
+    // Only read to migrate to `BrightnessV1`.
+    #[allow(dead_code)]
     BrightnessV0(u8),
+    BrightnessV1(u8),
+    BrightnessCurveV0(BrightnessCurve),
+    BrightnessModeV0(BrightnessMode),
+    AutoBrightnessV0(AutoBrightness),
+    ScheduleV0(Schedule),
+    ExpressionV1(Expression),
+    DeviceNameV0(DeviceName),
+    FaceModeV0(FaceMode),
+    MoodStyleV0(MoodStyle),
+    ButtonBindingsV0(ButtonBindings),
+    FriendV0(Option<Friend>),
+    BlinkSyncV0(BlinkSync),
+    TransitionV0(TransitionStyle),
+    ScriptV0(Script),
+    ClockConfigV0(ClockConfig),
+}
+
+/// Expressions from before the display size was configurable.
+#[allow(dead_code)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct ExpressionV0 {
+    pixels: [u16; 7],
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
 }
 
 impl ConfigValue {
-    const EXPRESSION_WORDS: usize = 7;
+    const EXPRESSION_WORDS: usize = MAX_HEIGHT as usize;
+    // Option tag, start, display and brightness of each entry.
+    const SCHEDULE_WORDS: usize = MAX_SCHEDULE_ENTRIES * 4;
+    // Length and bytes of the name.
+    const DEVICE_NAME_WORDS: usize = 1 + MAX_NAME_LEN;
+    // Option tag, ID tag, two words of serial and both expressions.
+    const FRIEND_WORDS: usize = 4 + 2 * Self::EXPRESSION_WORDS;
+    // Length and bytes of the bytecode.
+    const SCRIPT_WORDS: usize = 1 + MAX_SCRIPT_LEN;
     const PADDING_WORDS: usize = 0;
-    const BUFFER_SIZE: usize =
-        (Self::EXPRESSION_WORDS + Self::PADDING_WORDS) * POSTCARD_BYTES_PER_WORD;
+    const BUFFER_SIZE: usize = (max(
+        max(
+            max(Self::EXPRESSION_WORDS, Self::SCHEDULE_WORDS),
+            max(Self::DEVICE_NAME_WORDS, Self::FRIEND_WORDS),
+        ),
+        Self::SCRIPT_WORDS,
+    ) + Self::PADDING_WORDS)
+        * POSTCARD_BYTES_PER_WORD;
 }
 
 impl<'a> Value<'a> for ConfigValue {
@@ -81,158 +153,368 @@ impl<Flash: NorFlash> FlashConfigStore<Flash>
 where
     Flash::Error: defmt::Format,
 {
-    const DEFAULT_BRIGHTNESS: u8 = 0x2f;
+    const DEFAULT_BRIGHTNESS: u8 = 50;
+    const DEFAULT_BRIGHTNESS_CURVE: BrightnessCurve = BrightnessCurve::Cie1931;
+    const DEFAULT_BRIGHTNESS_MODE: BrightnessMode = BrightnessMode::Manual;
+    const DEFAULT_FACE_MODE: FaceMode = FaceMode::Expressions;
+    const DEFAULT_BLINK_SYNC: BlinkSync = BlinkSync {
+        enabled: false,
+        cascade_ms: 0,
+    };
+    const DEFAULT_CLOCK_CONFIG: ClockConfig = ClockConfig {
+        hours_24: true,
+        show_date: false,
+        clock_secs: 10,
+        face_secs: 0,
+    };
+    // Assumes an LDR to ground against the ADC pin's pull up, so darkness
+    // reads high.
+    const DEFAULT_AUTO_BRIGHTNESS: AutoBrightness = AutoBrightness {
+        dark: 0xe00,
+        bright: 0x200,
+        min: 5,
+        max: 80,
+    };
+
     pub fn new(flash: Flash, range: Range<u32>) -> Self {
         Self { flash, range }
     }
 
+    /// Drawn for the 15x7 display whatever the display, as the store is set
+    /// up before the display is.
     fn default_expression(index: ExpressionIndex) -> Expression {
         match index {
-            ExpressionIndex::Default => Expression {
-                pixels: [
-                    0b000_0000_0000_0000,
-                    0b001_1000_0000_1100,
-                    0b010_0100_0001_0010,
-                    0b010_0100_0001_0010,
-                    0b001_1001_0100_1100,
-                    0b000_0001_1100_0000,
-                    0b000_0000_0000_0000,
-                ],
-            },
-            ExpressionIndex::Blink => Expression {
-                pixels: [
-                    0b000_0000_0000_0000,
-                    0b000_0000_0000_0000,
-                    0b011_1100_0001_1110,
-                    0b000_0000_0000_0000,
-                    0b000_0001_0100_0000,
-                    0b000_0001_1100_0000,
-                    0b000_0000_0000_0000,
-                ],
-            },
-            ExpressionIndex::Friend => Expression {
-                pixels: [
-                    0b000_0000_0000_0000,
-                    0b001_1000_0000_1100,
-                    0b010_0100_0001_0010,
-                    0b010_0100_0001_0010,
-                    0b001_1001_0100_1100,
-                    0b000_0001_1100_0000,
-                    0b000_0000_0000_0000,
-                ],
-            },
-            ExpressionIndex::FriendBlink => Expression {
-                pixels: [
-                    0b000_0000_0000_0000,
-                    0b000_0000_0000_0000,
-                    0b011_1100_0001_1110,
-                    0b000_0000_0000_0000,
-                    0b000_0001_0100_0000,
-                    0b000_0001_1100_0000,
-                    0b000_0000_0000_0000,
-                ],
-            },
+            ExpressionIndex::Default => Preset::Neutral,
+            ExpressionIndex::Blink => Preset::Blink,
+            ExpressionIndex::Friend => Preset::Happy,
+            ExpressionIndex::FriendBlink => Preset::Content,
+            ExpressionIndex::Sleep => Preset::Sleepy,
         }
+        .expression()
     }
 
-    pub async fn get_expression(&mut self, index: ExpressionIndex) -> Expression {
+    fn default_mood_style(mood: Mood) -> MoodStyle {
+        match mood {
+            Mood::Idle => MoodStyle::new(
+                ExpressionIndex::Default,
+                ExpressionIndex::Blink,
+                2000,
+                10000,
+            ),
+            Mood::Happy => MoodStyle::new(
+                ExpressionIndex::Friend,
+                ExpressionIndex::FriendBlink,
+                2000,
+                10000,
+            ),
+            Mood::Sleepy => MoodStyle::new(ExpressionIndex::Sleep, ExpressionIndex::Sleep, 0, 0),
+            // Wide eyed.
+            Mood::Surprised => {
+                MoodStyle::new(ExpressionIndex::Default, ExpressionIndex::Blink, 0, 0)
+            }
+            Mood::Bored => {
+                MoodStyle::new(ExpressionIndex::Default, ExpressionIndex::Blink, 1000, 4000)
+            }
+        }
+    }
+
+    fn default_transition(kind: TransitionKind) -> TransitionStyle {
+        match kind {
+            // Blinks only last 25ms (`behavior::BLINK_DURATION`), less than
+            // a frame of any animation.
+            TransitionKind::Blink => TransitionStyle::new(TransitionEffect::Cut, 0),
+            TransitionKind::Friend => TransitionStyle::new(TransitionEffect::CrossFade, 300),
+            TransitionKind::Mood => TransitionStyle::new(TransitionEffect::Dissolve, 400),
+            TransitionKind::Sleep => TransitionStyle::new(TransitionEffect::Eyelid, 1000),
+        }
+    }
+
+    fn default_button_bindings(button: u8) -> ButtonBindings {
+        match button {
+            0 => ButtonBindings::new(
+                ButtonAction::Touch,
+                ButtonAction::CycleMood,
+                ButtonAction::ToggleBrightness,
+            ),
+            _ => ButtonBindings::new(
+                ButtonAction::CycleMood,
+                ButtonAction::ToggleBrightness,
+                ButtonAction::Nothing,
+            ),
+        }
+    }
+
+    async fn fetch(&mut self, key: &ConfigKey) -> Option<ConfigValue> {
         let mut buffer = [0u8; ConfigKey::BUFFER_SIZE + ConfigValue::BUFFER_SIZE];
-        let key = ConfigKey::ExpressionV0(index);
         match fetch_item(
             &mut self.flash,
             self.range.clone(),
             &mut NoCache::new(),
             &mut buffer,
-            &key,
+            key,
         )
         .await
         {
-            Ok(value) => {
-                let Some(value) = value else {
-                    return Self::default_expression(index);
-                };
-
-                // Protects against future additions to Config vaule.
-                #[allow(irrefutable_let_patterns)]
-                let ConfigValue::ExpressionV0(expression) = value
-                else {
-                    return Self::default_expression(index);
-                };
-
-                expression
-            }
+            Ok(value) => value,
             Err(e) => {
-                error!("Error fetching expression {}: {}", index, e);
-                Self::default_expression(index)
+                error!("Error fetching {}: {}", key, e);
+                None
             }
         }
     }
 
-    pub async fn set_expression(
-        &mut self,
-        index: ExpressionIndex,
-        expression: Expression,
-    ) -> Result<()> {
+    async fn store(&mut self, key: &ConfigKey, value: &ConfigValue) -> Result<()> {
         let mut buffer = [0u8; ConfigKey::BUFFER_SIZE + ConfigValue::BUFFER_SIZE];
-        let key = ConfigKey::ExpressionV0(index);
-        let value = ConfigValue::ExpressionV0(expression);
         store_item(
             &mut self.flash,
             self.range.clone(),
             &mut NoCache::new(),
             &mut buffer,
-            &key,
-            &value,
+            key,
+            value,
         )
         .await
         .map_err(|_| Error::Storage)
     }
 
-    pub async fn get_brightness(&mut self) -> u8 {
-        let mut buffer = [0u8; ConfigKey::BUFFER_SIZE + ConfigValue::BUFFER_SIZE];
-        let key = ConfigKey::BrightnessV0;
-        match fetch_item(
-            &mut self.flash,
-            self.range.clone(),
-            &mut NoCache::new(),
-            &mut buffer,
-            &key,
-        )
-        .await
+    pub async fn get_expression(&mut self, index: ExpressionIndex) -> Expression {
+        if let Some(ConfigValue::ExpressionV1(expression)) =
+            self.fetch(&ConfigKey::ExpressionV1(index)).await
         {
-            Ok(value) => {
-                let Some(value) = value else {
-                    return Self::DEFAULT_BRIGHTNESS;
-                };
-
-                // Protects against future additions to Config vaule.
-                #[allow(irrefutable_let_patterns)]
-                let ConfigValue::BrightnessV0(value) = value
-                else {
-                    return Self::DEFAULT_BRIGHTNESS;
-                };
-
-                value
+            return expression;
+        }
+        match self.fetch(&ConfigKey::ExpressionV0(index)).await {
+            Some(ConfigValue::ExpressionV0(expression)) => {
+                Expression::from_rows(&expression.pixels)
             }
-            Err(e) => {
-                error!("Error fetching brightness: {}", e);
-                Self::DEFAULT_BRIGHTNESS
+            _ => Self::default_expression(index),
+        }
+    }
+
+    pub async fn set_expression(
+        &mut self,
+        index: ExpressionIndex,
+        expression: Expression,
+    ) -> Result<()> {
+        self.store(
+            &ConfigKey::ExpressionV1(index),
+            &ConfigValue::ExpressionV1(expression),
+        )
+        .await
+    }
+
+    /// Returns the perceptual brightness level (0-100).  A raw PWM value
+    /// saved before levels existed is converted to the nearest level.
+    pub async fn get_brightness(&mut self) -> u8 {
+        if let Some(ConfigValue::BrightnessV1(value)) = self.fetch(&ConfigKey::BrightnessV1).await {
+            return value.min(MAX_BRIGHTNESS);
+        }
+        match self.fetch(&ConfigKey::BrightnessV0).await {
+            Some(ConfigValue::BrightnessV0(duty)) => {
+                gamma::level(self.get_brightness_curve().await, duty)
             }
+            _ => Self::DEFAULT_BRIGHTNESS,
         }
     }
+
     pub async fn set_brightness(&mut self, value: u8) -> Result<()> {
-        let mut buffer = [0u8; ConfigKey::BUFFER_SIZE + ConfigValue::BUFFER_SIZE];
-        let key = ConfigKey::BrightnessV0;
-        let value = ConfigValue::BrightnessV0(value);
-        store_item(
-            &mut self.flash,
-            self.range.clone(),
-            &mut NoCache::new(),
-            &mut buffer,
-            &key,
-            &value,
+        self.store(
+            &ConfigKey::BrightnessV1,
+            &ConfigValue::BrightnessV1(value.min(MAX_BRIGHTNESS)),
         )
         .await
-        .map_err(|_| Error::Storage)
+    }
+
+    pub async fn get_brightness_curve(&mut self) -> BrightnessCurve {
+        match self.fetch(&ConfigKey::BrightnessCurveV0).await {
+            Some(ConfigValue::BrightnessCurveV0(curve)) => curve,
+            _ => Self::DEFAULT_BRIGHTNESS_CURVE,
+        }
+    }
+
+    pub async fn set_brightness_curve(&mut self, curve: BrightnessCurve) -> Result<()> {
+        self.store(
+            &ConfigKey::BrightnessCurveV0,
+            &ConfigValue::BrightnessCurveV0(curve),
+        )
+        .await
+    }
+
+    pub async fn get_brightness_mode(&mut self) -> BrightnessMode {
+        match self.fetch(&ConfigKey::BrightnessModeV0).await {
+            Some(ConfigValue::BrightnessModeV0(mode)) => mode,
+            _ => Self::DEFAULT_BRIGHTNESS_MODE,
+        }
+    }
+
+    pub async fn set_brightness_mode(&mut self, mode: BrightnessMode) -> Result<()> {
+        self.store(
+            &ConfigKey::BrightnessModeV0,
+            &ConfigValue::BrightnessModeV0(mode),
+        )
+        .await
+    }
+
+    pub async fn get_auto_brightness(&mut self) -> AutoBrightness {
+        match self.fetch(&ConfigKey::AutoBrightnessV0).await {
+            Some(ConfigValue::AutoBrightnessV0(config)) => config,
+            _ => Self::DEFAULT_AUTO_BRIGHTNESS,
+        }
+    }
+
+    pub async fn set_auto_brightness(&mut self, config: AutoBrightness) -> Result<()> {
+        self.store(
+            &ConfigKey::AutoBrightnessV0,
+            &ConfigValue::AutoBrightnessV0(config),
+        )
+        .await
+    }
+
+    pub async fn get_schedule(&mut self) -> Schedule {
+        match self.fetch(&ConfigKey::ScheduleV0).await {
+            Some(ConfigValue::ScheduleV0(schedule)) => schedule,
+            _ => Schedule::default(),
+        }
+    }
+
+    pub async fn set_schedule(&mut self, schedule: Schedule) -> Result<()> {
+        self.store(&ConfigKey::ScheduleV0, &ConfigValue::ScheduleV0(schedule))
+            .await
+    }
+
+    pub async fn get_device_name(&mut self) -> DeviceName {
+        match self.fetch(&ConfigKey::DeviceNameV0).await {
+            Some(ConfigValue::DeviceNameV0(name)) => name,
+            _ => DeviceName::default(),
+        }
+    }
+
+    pub async fn set_device_name(&mut self, name: DeviceName) -> Result<()> {
+        self.store(&ConfigKey::DeviceNameV0, &ConfigValue::DeviceNameV0(name))
+            .await
+    }
+
+    pub async fn get_face_mode(&mut self) -> FaceMode {
+        match self.fetch(&ConfigKey::FaceModeV0).await {
+            Some(ConfigValue::FaceModeV0(mode)) => mode,
+            _ => Self::DEFAULT_FACE_MODE,
+        }
+    }
+
+    pub async fn set_face_mode(&mut self, mode: FaceMode) -> Result<()> {
+        self.store(&ConfigKey::FaceModeV0, &ConfigValue::FaceModeV0(mode))
+            .await
+    }
+
+    pub async fn get_mood_style(&mut self, mood: Mood) -> MoodStyle {
+        match self.fetch(&ConfigKey::MoodStyleV0(mood)).await {
+            Some(ConfigValue::MoodStyleV0(style)) => style,
+            _ => Self::default_mood_style(mood),
+        }
+    }
+
+    pub async fn set_mood_style(&mut self, mood: Mood, style: MoodStyle) -> Result<()> {
+        self.store(
+            &ConfigKey::MoodStyleV0(mood),
+            &ConfigValue::MoodStyleV0(style),
+        )
+        .await
+    }
+
+    pub async fn get_button_bindings(&mut self, button: u8) -> ButtonBindings {
+        match self.fetch(&ConfigKey::ButtonBindingsV0(button)).await {
+            Some(ConfigValue::ButtonBindingsV0(bindings)) => bindings,
+            _ => Self::default_button_bindings(button),
+        }
+    }
+
+    pub async fn set_button_bindings(
+        &mut self,
+        button: u8,
+        bindings: ButtonBindings,
+    ) -> Result<()> {
+        self.store(
+            &ConfigKey::ButtonBindingsV0(button),
+            &ConfigValue::ButtonBindingsV0(bindings),
+        )
+        .await
+    }
+
+    pub async fn get_friends(&mut self) -> [Option<Friend>; MAX_FRIENDS] {
+        let mut friends = [const { None }; MAX_FRIENDS];
+        for (slot, friend) in friends.iter_mut().enumerate() {
+            if let Some(ConfigValue::FriendV0(value)) =
+                self.fetch(&ConfigKey::FriendV0(slot as u8)).await
+            {
+                *friend = value;
+            }
+        }
+        friends
+    }
+
+    pub async fn get_blink_sync(&mut self) -> BlinkSync {
+        match self.fetch(&ConfigKey::BlinkSyncV0).await {
+            Some(ConfigValue::BlinkSyncV0(sync)) => sync,
+            _ => Self::DEFAULT_BLINK_SYNC,
+        }
+    }
+
+    pub async fn set_blink_sync(&mut self, sync: BlinkSync) -> Result<()> {
+        self.store(&ConfigKey::BlinkSyncV0, &ConfigValue::BlinkSyncV0(sync))
+            .await
+    }
+
+    pub async fn get_transition(&mut self, kind: TransitionKind) -> TransitionStyle {
+        match self.fetch(&ConfigKey::TransitionV0(kind)).await {
+            Some(ConfigValue::TransitionV0(style)) => style,
+            _ => Self::default_transition(kind),
+        }
+    }
+
+    pub async fn set_transition(
+        &mut self,
+        kind: TransitionKind,
+        style: TransitionStyle,
+    ) -> Result<()> {
+        self.store(
+            &ConfigKey::TransitionV0(kind),
+            &ConfigValue::TransitionV0(style),
+        )
+        .await
+    }
+
+    /// Returns the behavior script, empty if none was stored.
+    pub async fn get_script(&mut self) -> Script {
+        match self.fetch(&ConfigKey::ScriptV0).await {
+            Some(ConfigValue::ScriptV0(script)) => script,
+            _ => Script::default(),
+        }
+    }
+
+    pub async fn set_script(&mut self, script: Script) -> Result<()> {
+        self.store(&ConfigKey::ScriptV0, &ConfigValue::ScriptV0(script))
+            .await
+    }
+
+    pub async fn get_clock_config(&mut self) -> ClockConfig {
+        match self.fetch(&ConfigKey::ClockConfigV0).await {
+            Some(ConfigValue::ClockConfigV0(config)) => config,
+            _ => Self::DEFAULT_CLOCK_CONFIG,
+        }
+    }
+
+    pub async fn set_clock_config(&mut self, config: ClockConfig) -> Result<()> {
+        self.store(
+            &ConfigKey::ClockConfigV0,
+            &ConfigValue::ClockConfigV0(config),
+        )
+        .await
+    }
+
+    /// Stores `friend` in `slot` of the friend table, or empties it if
+    /// `None`.
+    pub async fn set_friend(&mut self, slot: u8, friend: Option<Friend>) -> Result<()> {
+        self.store(&ConfigKey::FriendV0(slot), &ConfigValue::FriendV0(friend))
+            .await
     }
 }
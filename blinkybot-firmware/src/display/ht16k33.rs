@@ -0,0 +1,131 @@
+//! HT16K33 LED matrix backpack driver.
+//!
+//! The HT16K33 drives up to 16 columns (its ROW outputs) by 8 rows (its COM
+//! outputs) from 16 bytes of display RAM: two bytes per row with column 0 in
+//! the least significant bit.  It only has the one frame and a global 16
+//! step dimming control.
+
+use blinkybot_core::gamma::Brightness;
+use blinkybot_rpc::Expression;
+use embedded_hal_async::i2c::I2c;
+
+use super::{BusCounters, Capabilities, MatrixDisplay};
+
+/// Default address of Adafruit's backpacks.
+pub const DEFAULT_ADDRESS: u8 = 0x70;
+
+const MAX_WIDTH: u8 = 16;
+const MAX_HEIGHT: u8 = 8;
+const RAM_BYTES: usize = 16;
+
+const DISPLAY_RAM: u8 = 0x00;
+const OSCILLATOR_ON: u8 = 0x21;
+const ROW_OUTPUT: u8 = 0xa0;
+const DISPLAY_OFF: u8 = 0x80;
+const DISPLAY_ON: u8 = 0x81;
+const DIMMING: u8 = 0xe0;
+
+pub struct Ht16k33<I2C> {
+    i2c: I2C,
+    address: u8,
+    width: u8,
+    height: u8,
+    /// Contents of the display RAM, `None` if unknown.
+    shadow: Option<[u8; RAM_BYTES]>,
+    counters: BusCounters,
+}
+
+impl<I2C: I2c> Ht16k33<I2C> {
+    /// Creates a driver for a `width` by `height` matrix, of at most 16 by
+    /// 8 pixels, wired to the HT16K33 at `address`.
+    pub fn new(i2c: I2C, address: u8, width: u8, height: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            width: width.min(MAX_WIDTH),
+            height: height.min(MAX_HEIGHT),
+            shadow: None,
+            counters: BusCounters::default(),
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), I2C::Error> {
+        self.counters
+            .write(&mut self.i2c, self.address, bytes)
+            .await
+    }
+
+    /// Returns the display RAM for `face`.
+    fn render(&self, face: &Expression) -> [u8; RAM_BYTES] {
+        let mut ram = [0u8; RAM_BYTES];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if face.get_pixel(x as u32, y as u32) {
+                    ram[y as usize * 2 + x as usize / 8] |= 1 << (x % 8);
+                }
+            }
+        }
+        ram
+    }
+}
+
+impl<I2C, I2cError> MatrixDisplay for Ht16k33<I2C>
+where
+    I2C: I2c<Error = I2cError>,
+    I2cError: defmt::Format,
+{
+    type Error = I2cError;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            width: self.width,
+            height: self.height,
+            frames: 1,
+        }
+    }
+
+    fn counters(&self) -> BusCounters {
+        self.counters
+    }
+
+    async fn init(&mut self) -> Result<(), I2cError> {
+        self.shadow = None;
+        self.write(&[OSCILLATOR_ON]).await?;
+        self.write(&[ROW_OUTPUT]).await?;
+        let mut buffer = [0u8; RAM_BYTES + 1];
+        buffer[0] = DISPLAY_RAM;
+        self.write(&buffer).await?;
+        self.shadow = Some([0; RAM_BYTES]);
+        self.write(&[DISPLAY_ON]).await
+    }
+
+    async fn set_brightness(&mut self, brightness: Brightness) -> Result<(), I2cError> {
+        let pwm = brightness.pwm();
+        // The lowest dimming step still lights the display.
+        if pwm == 0 {
+            return self.write(&[DISPLAY_OFF]).await;
+        }
+        self.write(&[DIMMING | (pwm >> 4)]).await?;
+        self.write(&[DISPLAY_ON]).await
+    }
+
+    async fn write_frame(&mut self, _frame: u8, face: &Expression) -> Result<bool, I2cError> {
+        let ram = self.render(face);
+        if self.shadow == Some(ram) {
+            return Ok(false);
+        }
+
+        let mut buffer = [0u8; RAM_BYTES + 1];
+        buffer[0] = DISPLAY_RAM;
+        buffer[1..].copy_from_slice(&ram);
+        // Forget the RAM contents until the write is known to succeed.
+        self.shadow = None;
+        self.write(&buffer).await?;
+        self.shadow = Some(ram);
+        Ok(true)
+    }
+
+    async fn show_frame(&mut self, _frame: u8) -> Result<(), I2cError> {
+        Ok(())
+    }
+}
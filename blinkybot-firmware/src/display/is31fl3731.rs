@@ -0,0 +1,195 @@
+//! IS31FL3731 charlieplexed LED matrix driver.
+//!
+//! The IS31FL3731 has 8 frames of PWM registers and can switch which one is
+//! displayed with a single register write.  It has no global brightness
+//! control so the brightness is the PWM value written for lit pixels, dimmed
+//! along the brightness curve for the partly lit pixels of transition
+//! frames.
+//!
+//! A shadow copy of every frame's PWM registers is kept so that only the
+//! span of registers that changed is written, using the chip's register
+//! auto-increment to send it as a single transfer.
+
+use blinkybot_core::gamma::Brightness;
+use blinkybot_core::transition::Levels;
+use blinkybot_rpc::{BrightnessCurve, Expression};
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+use is31fl3731_async::IS31FL3731;
+
+use super::{BusCounters, Capabilities, MatrixDisplay};
+
+const COMMAND_REGISTER: u8 = 0xfd;
+const FUNCTION_BANK: u8 = 0x0b;
+const CONFIG_REGISTER: u8 = 0x00;
+const PICTURE_MODE: u8 = 0x00;
+const PICTURE_DISPLAY_REGISTER: u8 = 0x01;
+const AUDIO_SYNC_REGISTER: u8 = 0x06;
+const SHUTDOWN_REGISTER: u8 = 0x0a;
+const LED_CONTROL_OFFSET: u8 = 0x00;
+const BLINK_CONTROL_OFFSET: u8 = 0x12;
+const CONTROL_REGISTERS: usize = 18;
+const PWM_OFFSET: u8 = 0x24;
+const PWM_REGISTERS: usize = 144;
+const FRAMES: usize = 8;
+
+pub struct Is31fl3731<I2C> {
+    matrix: IS31FL3731<I2C>,
+    /// Currently selected register bank, if known.
+    bank: Option<u8>,
+    /// Contents of each frame's PWM registers, `None` if unknown.
+    shadow: [Option<[u8; PWM_REGISTERS]>; FRAMES],
+    brightness: Brightness,
+    counters: BusCounters,
+}
+
+impl<I2C: I2c> Is31fl3731<I2C> {
+    /// Wraps a `matrix` configured for the board's LED layout.
+    pub fn new(matrix: IS31FL3731<I2C>) -> Self {
+        Self {
+            matrix,
+            bank: None,
+            shadow: [None; FRAMES],
+            brightness: Brightness::new(BrightnessCurve::Linear, 0),
+            counters: BusCounters::default(),
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), I2C::Error> {
+        self.counters
+            .write(&mut self.matrix.i2c, self.matrix.address, bytes)
+            .await
+    }
+
+    async fn select_bank(&mut self, bank: u8) -> Result<(), I2C::Error> {
+        if self.bank == Some(bank) {
+            return Ok(());
+        }
+        // The selected bank is unknown if the write fails part way.
+        self.bank = None;
+        self.write(&[COMMAND_REGISTER, bank]).await?;
+        self.bank = Some(bank);
+        Ok(())
+    }
+
+    /// Writes `values` to consecutive registers of the selected bank starting
+    /// at `register`.
+    async fn write_registers(&mut self, register: u8, values: &[u8]) -> Result<(), I2C::Error> {
+        let mut buffer = [0u8; PWM_REGISTERS + 1];
+        buffer[0] = register;
+        buffer[1..=values.len()].copy_from_slice(values);
+        self.write(&buffer[..=values.len()]).await
+    }
+
+    /// Returns the PWM registers for `levels`.
+    fn render(&self, levels: &Levels) -> [u8; PWM_REGISTERS] {
+        let mut registers = [0u8; PWM_REGISTERS];
+        for y in 0..self.matrix.height {
+            for x in 0..self.matrix.width {
+                let level = levels.get(x as u32, y as u32);
+                if level > 0 {
+                    let led = (self.matrix.calc_pixel)(x, y) as usize;
+                    registers[led] = self.brightness.scaled_pwm(level);
+                }
+            }
+        }
+        registers
+    }
+
+    /// Writes `registers` into `frame`, skipping registers the frame already
+    /// holds.  Returns false if nothing was written.
+    async fn write_pwm(
+        &mut self,
+        frame: u8,
+        registers: [u8; PWM_REGISTERS],
+    ) -> Result<bool, I2C::Error> {
+        let (first, last) = match &self.shadow[frame as usize] {
+            Some(shadow) => {
+                let Some(first) = (0..PWM_REGISTERS).find(|&i| registers[i] != shadow[i]) else {
+                    return Ok(false);
+                };
+                let last = (0..PWM_REGISTERS)
+                    .rev()
+                    .find(|&i| registers[i] != shadow[i])
+                    .unwrap_or(first);
+                (first, last)
+            }
+            None => (0, PWM_REGISTERS - 1),
+        };
+
+        self.select_bank(frame).await?;
+        // Forget the frame's contents until the write is known to succeed.
+        self.shadow[frame as usize] = None;
+        self.write_registers(PWM_OFFSET + first as u8, &registers[first..=last])
+            .await?;
+        self.shadow[frame as usize] = Some(registers);
+        Ok(true)
+    }
+}
+
+impl<I2C, I2cError> MatrixDisplay for Is31fl3731<I2C>
+where
+    I2C: I2c<Error = I2cError>,
+    I2cError: defmt::Format,
+{
+    type Error = I2cError;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            width: self.matrix.width,
+            height: self.matrix.height,
+            frames: FRAMES as u8,
+        }
+    }
+
+    fn counters(&self) -> BusCounters {
+        self.counters
+    }
+
+    async fn init(&mut self) -> Result<(), I2cError> {
+        self.bank = None;
+        self.shadow = [None; FRAMES];
+
+        self.select_bank(FUNCTION_BANK).await?;
+        self.write(&[SHUTDOWN_REGISTER, 0]).await?;
+        Timer::after_millis(10).await;
+        self.write(&[CONFIG_REGISTER, PICTURE_MODE]).await?;
+        self.write(&[PICTURE_DISPLAY_REGISTER, 0]).await?;
+        self.write(&[AUDIO_SYNC_REGISTER, 0]).await?;
+
+        // Enable every LED in every frame, with blinking off and PWM cleared.
+        for frame in 0..FRAMES as u8 {
+            self.select_bank(frame).await?;
+            self.write_registers(LED_CONTROL_OFFSET, &[0xff; CONTROL_REGISTERS])
+                .await?;
+            self.write_registers(BLINK_CONTROL_OFFSET, &[0; CONTROL_REGISTERS])
+                .await?;
+            self.write_registers(PWM_OFFSET, &[0; PWM_REGISTERS])
+                .await?;
+            self.shadow[frame as usize] = Some([0; PWM_REGISTERS]);
+        }
+
+        self.select_bank(FUNCTION_BANK).await?;
+        self.write(&[SHUTDOWN_REGISTER, 1]).await
+    }
+
+    async fn set_brightness(&mut self, brightness: Brightness) -> Result<(), I2cError> {
+        self.brightness = brightness;
+        Ok(())
+    }
+
+    async fn write_frame(&mut self, frame: u8, face: &Expression) -> Result<bool, I2cError> {
+        let registers = self.render(&Levels::from(face));
+        self.write_pwm(frame, registers).await
+    }
+
+    async fn write_levels(&mut self, frame: u8, levels: &Levels) -> Result<bool, I2cError> {
+        let registers = self.render(levels);
+        self.write_pwm(frame, registers).await
+    }
+
+    async fn show_frame(&mut self, frame: u8) -> Result<(), I2cError> {
+        self.select_bank(FUNCTION_BANK).await?;
+        self.write(&[PICTURE_DISPLAY_REGISTER, frame]).await
+    }
+}
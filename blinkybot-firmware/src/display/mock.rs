@@ -0,0 +1,88 @@
+//! Display that is not attached to anything.
+//!
+//! Keeps its frames in RAM and logs the displayed one whenever it changes,
+//! so the firmware can run on a bare board.
+
+use core::convert::Infallible;
+
+use blinkybot_core::gamma::Brightness;
+use blinkybot_rpc::Expression;
+use defmt::debug;
+
+use super::{BusCounters, Capabilities, MatrixDisplay};
+
+const FRAMES: usize = 8;
+
+pub struct MockDisplay {
+    width: u8,
+    height: u8,
+    frames: [Expression; FRAMES],
+    front: u8,
+    pwm: u8,
+}
+
+impl MockDisplay {
+    pub fn new(width: u8, height: u8) -> Self {
+        Self {
+            width,
+            height,
+            frames: Default::default(),
+            front: 0,
+            pwm: 0,
+        }
+    }
+
+    fn log(&self) {
+        let face = &self.frames[self.front as usize];
+        debug!(
+            "mock display frame {} at {}: {:04x}",
+            self.front,
+            self.pwm,
+            face.pixels[..self.height as usize]
+        );
+    }
+}
+
+impl MatrixDisplay for MockDisplay {
+    type Error = Infallible;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            width: self.width,
+            height: self.height,
+            frames: FRAMES as u8,
+        }
+    }
+
+    fn counters(&self) -> BusCounters {
+        BusCounters::default()
+    }
+
+    async fn init(&mut self) -> Result<(), Infallible> {
+        self.frames = Default::default();
+        self.front = 0;
+        Ok(())
+    }
+
+    async fn set_brightness(&mut self, brightness: Brightness) -> Result<(), Infallible> {
+        self.pwm = brightness.pwm();
+        Ok(())
+    }
+
+    async fn write_frame(&mut self, frame: u8, face: &Expression) -> Result<bool, Infallible> {
+        if self.frames[frame as usize] == *face {
+            return Ok(false);
+        }
+        self.frames[frame as usize] = face.clone();
+        if frame == self.front {
+            self.log();
+        }
+        Ok(true)
+    }
+
+    async fn show_frame(&mut self, frame: u8) -> Result<(), Infallible> {
+        self.front = frame;
+        self.log();
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+//! LED matrix display.
+//!
+//! `MatrixDisplay` is implemented for each supported LED matrix controller
+//! and `Display` builds everything the behavior engine needs on top of it.
+//!
+//! Controllers with enough frame memory get their frames used as a double
+//! buffer for arbitrary faces: the face is drawn into the frame that is not
+//! being displayed and then flipped to, so a half drawn face is never
+//! visible.  If there are enough frames left, they hold preloaded
+//! expressions so that switching between them, e.g. to blink, is instant.
+//! Controllers without spare frames draw straight into the displayed one.
+//!
+//! Transition frames give each pixel its own brightness.  Controllers with
+//! per-pixel PWM show them as they are, others show the pixels that are at
+//! least half lit.
+//!
+//! Failed transfers are retried a few times by the controller.  If the
+//! display still does not respond, the error is returned and the caller is
+//! expected to call `Display::recover()` which resets the controller and
+//! redoes its setup.
+
+use blinkybot_core::gamma::Brightness;
+use blinkybot_core::transition::Levels;
+use blinkybot_rpc::{
+    DisplayHealth, DisplayInfo, DisplayState, DisplayStats, Expression, ExpressionIndex,
+};
+use defmt::{info, warn, Format};
+use embassy_sync::watch::DynSender;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+
+#[cfg(not(any(
+    feature = "charlie-wing",
+    feature = "matrix",
+    feature = "ht16k33",
+    feature = "mock-display"
+)))]
+compile_error!(
+    "select a display with one of the charlie-wing, matrix, ht16k33 or mock-display features"
+);
+
+#[cfg(any(
+    all(
+        feature = "charlie-wing",
+        any(feature = "matrix", feature = "ht16k33", feature = "mock-display")
+    ),
+    all(feature = "matrix", any(feature = "ht16k33", feature = "mock-display")),
+    all(feature = "ht16k33", feature = "mock-display"),
+))]
+compile_error!(
+    "select only one display feature; use --no-default-features to replace charlie-wing"
+);
+
+#[cfg(feature = "ht16k33")]
+mod ht16k33;
+#[cfg(feature = "is31fl3731")]
+mod is31fl3731;
+#[cfg(feature = "mock-display")]
+mod mock;
+
+#[cfg(feature = "ht16k33")]
+pub use ht16k33::{Ht16k33, DEFAULT_ADDRESS as HT16K33_ADDRESS};
+#[cfg(feature = "is31fl3731")]
+pub use is31fl3731::Is31fl3731;
+#[cfg(feature = "mock-display")]
+pub use mock::MockDisplay;
+
+/// Frames used to double buffer `Display::show`.
+const SCRATCH_FRAMES: [u8; 2] = [0, 1];
+/// First frame holding preloaded expressions, one per `ExpressionIndex`.
+const PRELOAD_FRAME_BASE: u8 = 2;
+
+/// Attempts made for each transfer before giving up.
+const TRANSFER_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(1);
+const RESET_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RESET_BACKOFF: Duration = Duration::from_secs(10);
+
+/// What a `MatrixDisplay` can do.
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+    pub width: u8,
+    pub height: u8,
+    /// Number of frames that can be written with `write_frame()`.
+    pub frames: u8,
+}
+
+/// Bus traffic of a `MatrixDisplay` since it was created.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BusCounters {
+    pub bytes: u32,
+    pub errors: u32,
+    pub retries: u32,
+}
+
+impl BusCounters {
+    /// Writes `bytes` to the device at `address`, retrying failed transfers
+    /// with increasing backoff.
+    pub async fn write<I2C: I2c>(
+        &mut self,
+        i2c: &mut I2C,
+        address: u8,
+        bytes: &[u8],
+    ) -> Result<(), I2C::Error> {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            self.bytes = self.bytes.wrapping_add(bytes.len() as u32 + 1);
+            match i2c.write(address, bytes).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    self.errors = self.errors.wrapping_add(1);
+                    if attempt == TRANSFER_ATTEMPTS {
+                        return Err(e);
+                    }
+                    self.retries = self.retries.wrapping_add(1);
+                }
+            }
+            Timer::after(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// An LED matrix controller.
+pub trait MatrixDisplay {
+    type Error: Format;
+
+    fn capabilities(&self) -> Capabilities;
+
+    fn counters(&self) -> BusCounters;
+
+    /// Resets the controller and sets it up with every frame blank and frame
+    /// 0 displayed.
+    async fn init(&mut self) -> Result<(), Self::Error>;
+
+    /// Sets the brightness of lit pixels.
+    ///
+    /// Controllers without a global brightness control apply it to frames
+    /// written afterwards.
+    async fn set_brightness(&mut self, brightness: Brightness) -> Result<(), Self::Error>;
+
+    /// Draws `face` into `frame`.  Returns false if `frame` already showed
+    /// `face` and nothing was written.
+    async fn write_frame(&mut self, frame: u8, face: &Expression) -> Result<bool, Self::Error>;
+
+    /// Draws `levels` into `frame`, scaling the brightness of lit pixels by
+    /// each pixel's level.  Returns false if nothing was written.
+    ///
+    /// Controllers without per-pixel brightness draw the pixels that are at
+    /// least half lit.
+    async fn write_levels(&mut self, frame: u8, levels: &Levels) -> Result<bool, Self::Error> {
+        self.write_frame(frame, &levels.threshold()).await
+    }
+
+    /// Displays `frame`.
+    async fn show_frame(&mut self, frame: u8) -> Result<(), Self::Error>;
+}
+
+/// Something `Display` can draw into a frame.
+#[derive(Clone, Copy)]
+enum Face<'a> {
+    Expression(&'a Expression),
+    Levels(&'a Levels),
+}
+
+pub struct Display<M> {
+    matrix: M,
+    capabilities: Capabilities,
+    /// Frame currently being displayed.
+    front: u8,
+    /// Brightness last set on the matrix, `None` if unknown.
+    brightness: Option<Brightness>,
+    /// Expressions and brightness loaded by `preload()`.
+    preloaded: [Option<(Expression, Brightness)>; ExpressionIndex::ALL.len()],
+    stats: DisplayStats,
+    stats_sender: DynSender<'static, DisplayStats>,
+    health: DisplayHealth,
+    health_sender: DynSender<'static, DisplayHealth>,
+}
+
+impl<M: MatrixDisplay> Display<M> {
+    /// Wraps `matrix`.  `recover()` must be called to set it up before use.
+    pub fn new(
+        matrix: M,
+        stats_sender: DynSender<'static, DisplayStats>,
+        health_sender: DynSender<'static, DisplayHealth>,
+    ) -> Self {
+        let stats = DisplayStats::default();
+        stats_sender.send(stats);
+        let health = DisplayHealth {
+            state: DisplayState::Initializing,
+            i2c_errors: 0,
+            retries: 0,
+            resets: 0,
+        };
+        health_sender.send(health);
+        Self {
+            capabilities: matrix.capabilities(),
+            matrix,
+            front: SCRATCH_FRAMES[0],
+            brightness: None,
+            preloaded: Default::default(),
+            stats,
+            stats_sender,
+            health,
+            health_sender,
+        }
+    }
+
+    pub fn info(&self) -> DisplayInfo {
+        DisplayInfo {
+            width: self.capabilities.width,
+            height: self.capabilities.height,
+        }
+    }
+
+    /// Sets up the display, retrying with increasing backoff until it
+    /// responds.
+    ///
+    /// Setup clears every frame so all previously drawn and preloaded frames
+    /// need to be drawn again.
+    pub async fn recover(&mut self) {
+        let mut backoff = RESET_BACKOFF;
+        loop {
+            if self.health.state != DisplayState::Initializing {
+                self.set_state(DisplayState::Recovering);
+                self.health.resets = self.health.resets.wrapping_add(1);
+                Timer::after(backoff).await;
+                backoff = (backoff * 2).min(MAX_RESET_BACKOFF);
+            }
+
+            self.brightness = None;
+            self.preloaded = Default::default();
+            let result = self.matrix.init().await;
+            self.update_counters();
+            if result.is_ok() {
+                self.front = SCRATCH_FRAMES[0];
+                info!("display ready");
+                self.set_state(DisplayState::Ok);
+                return;
+            }
+
+            warn!("display setup failed");
+            self.set_state(DisplayState::Recovering);
+        }
+    }
+
+    fn set_state(&mut self, state: DisplayState) {
+        self.health.state = state;
+        self.health_sender.send(self.health);
+    }
+
+    /// Copies the matrix's bus counters into the published stats and health.
+    fn update_counters(&mut self) {
+        let counters = self.matrix.counters();
+        self.stats.i2c_bytes = counters.bytes;
+        self.stats_sender.send(self.stats);
+        if (self.health.i2c_errors, self.health.retries) != (counters.errors, counters.retries) {
+            self.health.i2c_errors = counters.errors;
+            self.health.retries = counters.retries;
+            self.health_sender.send(self.health);
+        }
+    }
+
+    /// Sets the brightness used for faces.
+    ///
+    /// Depending on the matrix, faces already drawn may need to be drawn
+    /// again for it to take effect.
+    pub async fn set_brightness(&mut self, brightness: Brightness) -> Result<(), M::Error> {
+        if self.brightness == Some(brightness) {
+            return Ok(());
+        }
+        self.brightness = None;
+        let result = self.matrix.set_brightness(brightness).await;
+        self.update_counters();
+        result?;
+        self.brightness = Some(brightness);
+        Ok(())
+    }
+
+    /// Draws `face` into the frame that is not displayed and then displays
+    /// it.  Without spare frames, `face` is drawn straight into the
+    /// displayed frame.
+    pub async fn show(&mut self, face: &Expression) -> Result<(), M::Error> {
+        self.show_face(Face::Expression(face)).await
+    }
+
+    /// Like `show()` but with a brightness for every pixel, for transition
+    /// frames.
+    pub async fn show_levels(&mut self, levels: &Levels) -> Result<(), M::Error> {
+        self.show_face(Face::Levels(levels)).await
+    }
+
+    async fn show_face(&mut self, face: Face<'_>) -> Result<(), M::Error> {
+        if self.capabilities.frames < SCRATCH_FRAMES.len() as u8 {
+            return self.draw(self.front, face).await;
+        }
+        let back = if self.front == SCRATCH_FRAMES[0] {
+            SCRATCH_FRAMES[1]
+        } else {
+            SCRATCH_FRAMES[0]
+        };
+        self.draw(back, face).await?;
+        self.display_frame(back).await
+    }
+
+    /// Draws `face` into the frame reserved for `index`, or just remembers
+    /// it if the matrix does not have enough frames.
+    pub async fn preload(
+        &mut self,
+        index: ExpressionIndex,
+        face: &Expression,
+    ) -> Result<(), M::Error> {
+        let loaded = self.brightness.map(|brightness| (face.clone(), brightness));
+        let changed = self.preloaded[index as usize] != loaded;
+        self.preloaded[index as usize] = loaded;
+        let Some(frame) = self.preload_frame(index) else {
+            return Ok(());
+        };
+        if frame == self.front && changed {
+            // Cover the frame with a scratch copy while it is redrawn.
+            self.show(face).await?;
+            self.draw(frame, Face::Expression(face)).await?;
+            return self.display_frame(frame).await;
+        }
+        self.draw(frame, Face::Expression(face)).await
+    }
+
+    /// Displays the expression previously loaded with `preload()`.
+    pub async fn show_preloaded(&mut self, index: ExpressionIndex) -> Result<(), M::Error> {
+        if let Some(frame) = self.preload_frame(index) {
+            return self.display_frame(frame).await;
+        }
+        match self.preloaded[index as usize].clone() {
+            Some((face, _)) => self.show(&face).await,
+            None => Ok(()),
+        }
+    }
+
+    fn preload_frame(&self, index: ExpressionIndex) -> Option<u8> {
+        let frame = PRELOAD_FRAME_BASE + index as u8;
+        (frame < self.capabilities.frames).then_some(frame)
+    }
+
+    async fn display_frame(&mut self, frame: u8) -> Result<(), M::Error> {
+        if frame == self.front {
+            return Ok(());
+        }
+        let result = self.matrix.show_frame(frame).await;
+        self.update_counters();
+        result?;
+        self.front = frame;
+        Ok(())
+    }
+
+    async fn draw(&mut self, frame: u8, face: Face<'_>) -> Result<(), M::Error> {
+        let start = Instant::now();
+        self.stats.frames = self.stats.frames.wrapping_add(1);
+        let result = match face {
+            Face::Expression(face) => self.matrix.write_frame(frame, face).await,
+            Face::Levels(levels) => self.matrix.write_levels(frame, levels).await,
+        };
+        match result {
+            Ok(false) => self.stats.unchanged_frames = self.stats.unchanged_frames.wrapping_add(1),
+            Ok(true) => {
+                let elapsed = start.elapsed().as_micros() as u32;
+                self.stats.last_frame_us = elapsed;
+                self.stats.max_frame_us = self.stats.max_frame_us.max(elapsed);
+            }
+            Err(_) => (),
+        }
+        self.update_counters();
+        result.map(|_| ())
+    }
+}
@@ -0,0 +1,207 @@
+//! Bot-to-bot link over the link line.
+//!
+//! Friends are seen on the analog sense line, which a friend holds low, so
+//! that line can't carry data.  Bots that meet also join their link pins,
+//! GPIO 27 on every board, which each bot pulls up.  The joined link line
+//! idles high with or without a friend attached, and bytes are sent UART
+//! style: a low start bit, eight data bits LSB first and a high stop bit.
+//! Bits are sent open drain, driving the line low for a 0 and letting go of
+//! it for a 1, so two bots talking at once can't fight.
+//!
+//! The link runs in its own task.  While a friend is seen it announces
+//! itself at random intervals and listens in between.  Bits are timed with
+//! the async timer, so a task holding up the executor for longer than a
+//! bit garbles the frame, which the CRC then drops.
+//!
+//! Hellos carry the time to the sender's next blink.  A friend with a lower
+//! serial number leads the blinking and its blinks are passed on to the
+//! behavior through `Comms::leader_blink`.
+
+use blinkybot_core::link::{Hello, Link, Wire};
+use blinkybot_rpc::{DeviceInfo, Mood, PeerInfo};
+use defmt::info;
+use embassy_rp::gpio::{Flex, Pull};
+use embassy_rp::peripherals::PIN_27;
+use embassy_sync::watch::{DynReceiver, DynSender};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use oorandom::Rand32;
+
+use crate::behavior::{self, EmbassyClock};
+use crate::webusb::Comms;
+
+/// 2400 baud.
+const BIT: Duration = Duration::from_micros(417);
+/// Start bit, data bits and stop bit.
+const BYTE: Duration = Duration::from_micros(417 * 10);
+/// Range of the random gaps between announcements, in milliseconds.
+const GAP_MS: core::ops::Range<u32> = 500..1500;
+
+struct LinkWire<'d> {
+    pin: Flex<'d>,
+}
+
+impl LinkWire<'static> {
+    fn new(pin: PIN_27) -> Self {
+        let mut pin = Flex::new(pin);
+        pin.set_pull(Pull::Up);
+        pin.set_low();
+        pin.set_as_input();
+        Self { pin }
+    }
+
+    fn set_bit(&mut self, bit: bool) {
+        if bit {
+            self.pin.set_as_input();
+        } else {
+            self.pin.set_as_output();
+        }
+    }
+
+    /// Reads the byte whose start bit began at `start`, or `None` if its
+    /// stop bit is missing.
+    async fn read_byte(&mut self, start: Instant) -> Option<u8> {
+        // Sample in the middle of each bit.
+        let mut at = start + BIT / 2;
+        let mut byte = 0;
+        for i in 0..8 {
+            at += BIT;
+            Timer::at(at).await;
+            if self.pin.is_high() {
+                byte |= 1 << i;
+            }
+        }
+        at += BIT;
+        Timer::at(at).await;
+        self.pin.is_high().then_some(byte)
+    }
+}
+
+impl Wire for LinkWire<'_> {
+    type Error = core::convert::Infallible;
+
+    async fn send(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        let mut at = Instant::now();
+        for byte in bytes {
+            let bits = core::iter::once(false)
+                .chain((0..8).map(|i| byte & (1 << i) != 0))
+                .chain(core::iter::once(true));
+            for bit in bits {
+                self.set_bit(bit);
+                at += BIT;
+                Timer::at(at).await;
+            }
+        }
+        self.set_bit(true);
+        Ok(())
+    }
+
+    async fn recv(&mut self, timeout_ms: u32) -> Result<Option<u8>, Self::Error> {
+        let until = Instant::now() + Duration::from_millis(timeout_ms.into());
+        loop {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if with_timeout(remaining, self.pin.wait_for_falling_edge())
+                .await
+                .is_err()
+            {
+                return Ok(None);
+            }
+            // Bytes with a bad stop bit are noise or collisions.
+            if let Some(byte) = self.read_byte(Instant::now()).await {
+                return Ok(Some(byte));
+            }
+        }
+    }
+}
+
+/// Keeps `Comms::peer` up to date with the friend being seen, talking to
+/// it over `pin`.
+#[embassy_executor::task]
+pub async fn link_task(comms: &'static Comms, pin: PIN_27) -> ! {
+    let mut adc = comms.adc_val.dyn_receiver().unwrap();
+    let mut friends = Friends::new(comms);
+    let mut link = Link::new(LinkWire::new(pin), EmbassyClock);
+    // Seeded from the serial so that bots pick different gaps.
+    let mut rng = None;
+    loop {
+        if !behavior::is_friend(adc.get().await) {
+            friends.set_peer(None);
+            adc.changed().await;
+            continue;
+        }
+        // Both bots announce at random intervals so that one is listening
+        // while the other talks.
+        let own = friends.announce(&mut link).await;
+        let rng = rng.get_or_insert_with(|| Rand32::new(own.serial));
+        let gap = Duration::from_millis(rng.rand_range(GAP_MS).into());
+        let until = (Instant::now() + gap).as_millis();
+        while let Ok(Some(hello)) = link.listen(own.serial, until).await {
+            friends.heard(&own, &hello);
+        }
+    }
+}
+
+struct Friends<'a> {
+    device_info: DynReceiver<'a, DeviceInfo>,
+    mood: DynReceiver<'a, Mood>,
+    next_blink: DynReceiver<'a, Option<Instant>>,
+    leader_blink: DynSender<'a, Instant>,
+    peer_sender: DynSender<'a, Option<PeerInfo>>,
+    peer: Option<PeerInfo>,
+}
+
+impl<'a> Friends<'a> {
+    fn new(comms: &'a Comms) -> Self {
+        let peer_sender = comms.peer.dyn_sender();
+        peer_sender.send(None);
+        Self {
+            device_info: comms.device_info.dyn_receiver().unwrap(),
+            mood: comms.mood.dyn_receiver().unwrap(),
+            next_blink: comms.next_blink.dyn_receiver().unwrap(),
+            leader_blink: comms.leader_blink.dyn_sender(),
+            peer_sender,
+            peer: None,
+        }
+    }
+
+    /// Says hello, returning what was said about this bot.
+    async fn announce(&mut self, link: &mut Link<LinkWire<'_>, EmbassyClock>) -> PeerInfo {
+        let device_info = self.device_info.get().await;
+        let own = PeerInfo {
+            serial: device_info.serial,
+            name: device_info.name,
+            mood: self.mood.try_get().unwrap_or(Mood::Idle),
+        };
+        let start = Instant::now();
+        let next_blink_ms = self
+            .next_blink
+            .try_get()
+            .flatten()
+            .and_then(|at| u16::try_from(at.saturating_duration_since(start).as_millis()).ok());
+        let Ok(()) = link
+            .announce(&Hello {
+                peer: own,
+                next_blink_ms,
+            })
+            .await;
+        own
+    }
+
+    /// Takes in the friend's `hello`, which has only just been received.
+    fn heard(&mut self, own: &PeerInfo, hello: &Hello) {
+        self.set_peer(Some(hello.peer));
+        let leads = hello.peer.serial < own.serial;
+        if let Some(ms) = hello.next_blink_ms.filter(|_| leads) {
+            let start = Instant::now() - BYTE * hello.frame_len() as u32;
+            self.leader_blink
+                .send(start + Duration::from_millis(ms.into()));
+        }
+    }
+
+    fn set_peer(&mut self, peer: Option<PeerInfo>) {
+        if peer != self.peer {
+            info!("peer: {}", peer);
+            self.peer = peer;
+            self.peer_sender.send(peer);
+        }
+    }
+}
@@ -6,11 +6,10 @@
 #![no_std]
 #![no_main]
 
-use blinkybot_rpc::ExpressionIndex;
 use defmt::*;
+use display::Display;
 use embassy_executor::Spawner;
 use embassy_futures::join;
-use embassy_futures::select::{select, select3, Either, Either3};
 use embassy_rp::adc::{self, Adc, Channel};
 use embassy_rp::bind_interrupts;
 use embassy_rp::block::ImageDef;
@@ -21,18 +20,24 @@ use embassy_rp::peripherals::{I2C1, USB};
 use embassy_rp::usb;
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::channel;
-use embassy_sync::watch::DynReceiver;
-use embassy_time::{Delay, Duration, Instant, Timer};
-use embedded_hal_async::i2c::I2c;
+use embassy_time::Timer;
+#[cfg(feature = "charlie-wing")]
 use is31fl3731_async::devices::CharlieWing;
-use is31fl3731_async::IS31FL3731;
-use oorandom::Rand32;
+#[cfg(feature = "matrix")]
+use is31fl3731_async::devices::Matrix;
 use postcard::fixint::be;
 use webusb::Comms;
 use {defmt_rtt as _, panic_probe as _};
 
+mod ambient;
+mod behavior;
+#[macro_use]
+mod buttons;
+mod clock;
 mod config_store;
+mod display;
 mod error;
+mod link;
 mod webusb;
 
 pub use error::Error;
@@ -89,6 +94,10 @@ async fn main_(spawner: Spawner) {
     info!("set up ADC");
     let mut adc = Adc::new(p.ADC, Irqs, adc::Config::default());
     let sense_adc = Channel::new_pin(p.PIN_28, Pull::Up);
+    let light_adc = Channel::new_pin(p.PIN_26, Pull::Up);
+
+    info!("set up buttons");
+    let button_pins = button_pins!(p);
 
     info!("set up comms");
     // Create the driver, from the HAL.
@@ -99,113 +108,59 @@ async fn main_(spawner: Spawner) {
     let i2c = i2c::I2c::new_async(p.I2C1, scl, sda, Irqs, Config::default());
 
     info!("Setting up display");
-    let mut matrix = CharlieWing::configure(i2c);
-    unwrap!(matrix.setup(&mut Delay {}).await, "Failed to setup display");
+    #[cfg(feature = "charlie-wing")]
+    let matrix = display::Is31fl3731::new(CharlieWing::configure(i2c));
+    #[cfg(feature = "matrix")]
+    let matrix = display::Is31fl3731::new(Matrix::configure(i2c));
+    #[cfg(feature = "ht16k33")]
+    let matrix = display::Ht16k33::new(i2c, display::HT16K33_ADDRESS, 16, 8);
+    #[cfg(feature = "mock-display")]
+    let matrix = {
+        let _ = i2c;
+        display::MockDisplay::new(15, 7)
+    };
+    let display = Display::new(
+        matrix,
+        comms.display_stats.dyn_sender(),
+        comms.display_health.dyn_sender(),
+    );
+    comms.display_info.dyn_sender().send(display.info());
 
     info!("starting coroutines");
-    let adc_fut = adc_sampler(comms, adc, sense_adc);
-    let behavior_fut = behavior(matrix, comms);
+    spawner.must_spawn(link::link_task(comms, p.PIN_27));
+    let adc_fut = adc_sampler(comms, adc, sense_adc, light_adc);
+    let brightness_fut = ambient::brightness_control(comms);
+    let scheduler_fut = clock::scheduler(comms);
+    let behavior_fut = behavior::behavior(display, comms);
+    let buttons_fut = buttons::buttons(comms, button_pins);
 
     info!("joining");
-    join::join(adc_fut, behavior_fut).await;
+    join::join5(
+        adc_fut,
+        brightness_fut,
+        scheduler_fut,
+        behavior_fut,
+        buttons_fut,
+    )
+    .await;
     //behavior_fut.await;
     error!("reached end of main");
 }
 
-async fn adc_sampler(comms: &Comms, mut adc: Adc<'_, adc::Async>, mut input: Channel<'_>) -> ! {
+async fn adc_sampler(
+    comms: &Comms,
+    mut adc: Adc<'_, adc::Async>,
+    mut sense: Channel<'_>,
+    mut light: Channel<'_>,
+) -> ! {
     let sender = comms.adc_val.dyn_sender();
+    let ambient_sender = comms.ambient_val.dyn_sender();
+    let mut ambient = blinkybot_core::ambient::Smoother::new();
     loop {
-        let val = adc.read(&mut input).await.unwrap();
+        let val = adc.read(&mut sense).await.unwrap();
         sender.send(val);
+        let val = adc.read(&mut light).await.unwrap();
+        ambient_sender.send(ambient.update(val));
         Timer::after_millis(100).await;
     }
 }
-
-fn is_friend(val: u16) -> bool {
-    val < 0x100
-}
-
-async fn behavior<I2C, I2cError>(mut matrix: IS31FL3731<I2C>, comms: &Comms) -> !
-where
-    I2C: I2c<Error = I2cError>,
-{
-    let mut default_expression = comms.default_expression.dyn_receiver().unwrap();
-    let mut blink_expression = comms.blink_expression.dyn_receiver().unwrap();
-    let mut friend_expression = comms.friend_expression.dyn_receiver().unwrap();
-    let mut friend_blink_expression = comms.friend_blink_expression.dyn_receiver().unwrap();
-    let mut adc_val_receiver = comms.adc_val.dyn_receiver().unwrap();
-    let mut brightness_val_receiver = comms.brightness_val.dyn_receiver().unwrap();
-
-    let mut rng = Rand32::new(0);
-    let mut seeing_friend = is_friend(adc_val_receiver.get().await);
-    let mut brightness = brightness_val_receiver.get().await;
-
-    loop {
-        let until = Instant::now() + Duration::from_millis(rng.rand_range(2000..10000).into());
-        while Instant::now() < until {
-            if seeing_friend {
-                set_face(
-                    &mut matrix,
-                    friend_expression.get().await.pixels,
-                    brightness,
-                )
-                .await;
-            } else {
-                set_face(
-                    &mut matrix,
-                    default_expression.get().await.pixels,
-                    brightness,
-                )
-                .await;
-            }
-
-            let fut = Timer::at(until);
-            match select3(
-                fut,
-                adc_val_receiver.changed(),
-                brightness_val_receiver.changed(),
-            )
-            .await
-            {
-                Either3::First(_) => break,
-                Either3::Second(val) => {
-                    seeing_friend = is_friend(val);
-                }
-                Either3::Third(val) => {
-                    brightness = val;
-                }
-            }
-        }
-        info!("blink");
-        if seeing_friend {
-            set_face(
-                &mut matrix,
-                friend_blink_expression.get().await.pixels,
-                brightness,
-            )
-            .await;
-        } else {
-            set_face(&mut matrix, blink_expression.get().await.pixels, brightness).await;
-        }
-        Timer::after_millis(25).await;
-    }
-}
-
-async fn set_face<I2C, I2cError>(matrix: &mut IS31FL3731<I2C>, face: [u16; 7], brightness: u8)
-where
-    I2C: I2c<Error = I2cError>,
-{
-    for (y, row) in face.iter().enumerate() {
-        for x in 0..15 {
-            let value = if (row & (1 << x)) != 0 {
-                brightness
-            } else {
-                0x00
-            };
-            unwrap!(
-                matrix.pixel(x, y as u8, value).await,
-                "Failed to set pixel light on"
-            );
-        }
-    }
-}
@@ -1,10 +1,13 @@
-use defmt::{error, info};
+use defmt::{error, info, unwrap};
 use embassy_executor::Spawner;
 use embassy_rp::flash::{Async, Flash};
 use embassy_rp::peripherals::{FLASH, USB};
 use embassy_rp::usb::{Driver as UsbDriver, Endpoint, Out};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::{Channel, DynamicReceiver, DynamicSender};
+use embassy_sync::pubsub::{DynSubscriber, PubSubChannel, WaitResult};
 use embassy_sync::watch::{DynReceiver, DynSender, Watch};
+use embassy_time::Instant;
 use embassy_usb::class::web_usb::{Config as WebUsbConfig, State, Url, WebUsb};
 use embassy_usb::driver::Driver;
 use embassy_usb::msos::{self, windows_version};
@@ -12,25 +15,96 @@ use embassy_usb::{Builder, Config, UsbDevice};
 
 use postcard_rpc::{
     define_dispatch,
-    target_server::{buffers::AllBuffers, rpc_dispatch, SpawnContext},
+    target_server::{buffers::AllBuffers, rpc_dispatch, Dispatch, Sender, SpawnContext},
     WireHeader,
 };
 
+use blinkybot_core::{pack, script};
 use blinkybot_rpc::{
-    Expression, ExpressionIndex, GetAdcEndpoint, GetBrightnessEndpoint, GetExpressionEndpoint,
-    PingEndpoint, SetBrightnessEndpoint, SetExpression, SetExpressionEndpoint,
+    serial_number, AddFriendEndpoint, ApplyPreset, ApplyPresetEndpoint, AutoBrightness,
+    BehaviorEvent, BehaviorTopic, BlinkSync, BrightnessCurve, BrightnessMode, ButtonAction,
+    ButtonBindings, ButtonEvent, ButtonTopic, ClockConfig, ConfigChange, ConfigChangedTopic,
+    DeviceInfo, DeviceName, DisplayHealth, DisplayInfo, DisplayStats, Expression, ExpressionIndex,
+    FaceMode, Friend, FriendId, Gaze, GetAdcEndpoint, GetAmbientLightEndpoint,
+    GetAutoBrightnessEndpoint, GetBlinkSyncEndpoint, GetBrightnessCurveEndpoint,
+    GetBrightnessEndpoint, GetBrightnessModeEndpoint, GetButtonBindingsEndpoint,
+    GetClockConfigEndpoint, GetDeviceInfoEndpoint, GetDisplayHealthEndpoint,
+    GetDisplayInfoEndpoint, GetDisplayStatsEndpoint, GetExpressionEndpoint, GetFaceModeEndpoint,
+    GetFriendEndpoint, GetMoodEndpoint, GetMoodStyleEndpoint, GetPeerEndpoint, GetScheduleEndpoint,
+    GetScriptEndpoint, GetTimeEndpoint, GetTransitionEndpoint, ListFriendsEndpoint,
+    ListPresetsEndpoint, Mood, MoodStyle, PeerInfo, PingEndpoint, Preset, RemoveFriendEndpoint,
+    Schedule, ScheduleEntry, Script, ScriptError, SetAutoBrightnessEndpoint, SetBlinkSyncEndpoint,
+    SetBrightnessCurveEndpoint, SetBrightnessEndpoint, SetBrightnessModeEndpoint,
+    SetButtonBindings, SetButtonBindingsEndpoint, SetClockConfigEndpoint, SetDeviceNameEndpoint,
+    SetExpression, SetExpressionEndpoint, SetFaceModeEndpoint, SetGazeEndpoint, SetMoodEndpoint,
+    SetMoodStyle, SetMoodStyleEndpoint, SetScheduleEndpoint, SetScriptEndpoint, SetTimeEndpoint,
+    SetTransition, SetTransitionEndpoint, TransitionEffect, TransitionKind, TransitionStyle,
+    WallTime, MAX_BRIGHTNESS, MAX_BUTTONS, MAX_FRIENDS, SERIAL_NUMBER_LEN, USB_PRODUCT_ID,
+    USB_VENDOR_ID,
 };
 use static_cell::{ConstStaticCell, StaticCell};
 
+use crate::clock::Clock;
 use crate::config_store::FlashConfigStore;
 
+/// Depth of `Comms::config_changes`, enough to hold every change from
+/// applying a pack, the largest burst, with room to spare.
+const CONFIG_CHANGES: usize = 32;
+const _: () = assert!(pack::MAX_APPLIED <= CONFIG_CHANGES);
+
 pub struct Comms {
     pub default_expression: Watch<ThreadModeRawMutex, Expression, 1>,
     pub blink_expression: Watch<ThreadModeRawMutex, Expression, 1>,
     pub friend_expression: Watch<ThreadModeRawMutex, Expression, 1>,
     pub friend_blink_expression: Watch<ThreadModeRawMutex, Expression, 1>,
-    pub adc_val: Watch<ThreadModeRawMutex, u16, 2>,
+    pub sleep_expression: Watch<ThreadModeRawMutex, Expression, 1>,
+    pub adc_val: Watch<ThreadModeRawMutex, u16, 3>,
     pub brightness_val: Watch<ThreadModeRawMutex, u8, 1>,
+    pub brightness_curve: Watch<ThreadModeRawMutex, BrightnessCurve, 1>,
+    pub brightness_mode: Watch<ThreadModeRawMutex, BrightnessMode, 1>,
+    pub auto_brightness: Watch<ThreadModeRawMutex, AutoBrightness, 1>,
+    pub ambient_val: Watch<ThreadModeRawMutex, u16, 2>,
+    /// Brightness level the display should use after applying the mode.
+    pub display_brightness: Watch<ThreadModeRawMutex, u8, 1>,
+    /// Only has a value once the host has set the time.
+    pub clock: Watch<ThreadModeRawMutex, Clock, 2>,
+    pub clock_config: Watch<ThreadModeRawMutex, ClockConfig, 1>,
+    pub schedule: Watch<ThreadModeRawMutex, Schedule, 1>,
+    pub active_schedule: Watch<ThreadModeRawMutex, Option<ScheduleEntry>, 2>,
+    pub display_stats: Watch<ThreadModeRawMutex, DisplayStats, 1>,
+    pub display_health: Watch<ThreadModeRawMutex, DisplayHealth, 1>,
+    pub display_info: Watch<ThreadModeRawMutex, DisplayInfo, 1>,
+    pub face_mode: Watch<ThreadModeRawMutex, FaceMode, 1>,
+    /// Where the procedural face looks, `None` to let it wander.
+    pub gaze: Watch<ThreadModeRawMutex, Option<Gaze>, 1>,
+    /// Current mood, published by the behavior.
+    pub mood: Watch<ThreadModeRawMutex, Mood, 2>,
+    /// Mood forced by the host, `None` to let the bot decide.
+    pub forced_mood: Watch<ThreadModeRawMutex, Option<Mood>, 1>,
+    /// Ordered like `Mood::ALL`.
+    pub mood_styles: Watch<ThreadModeRawMutex, [MoodStyle; Mood::ALL.len()], 1>,
+    /// Ordered like `TransitionKind::ALL`.
+    pub transitions: Watch<ThreadModeRawMutex, [TransitionStyle; TransitionKind::ALL.len()], 1>,
+    /// Every gesture, for the behavior and the host.  Published immediately
+    /// so a subscriber only loses events when it falls 8 behind.
+    pub button_event: PubSubChannel<ThreadModeRawMutex, ButtonEvent, 8, 2, 0>,
+    pub button_bindings: Watch<ThreadModeRawMutex, [ButtonBindings; MAX_BUTTONS], 1>,
+    pub device_info: Watch<ThreadModeRawMutex, DeviceInfo, 1>,
+    /// The friend on the other end of the link line, once it has said hello.
+    pub peer: Watch<ThreadModeRawMutex, Option<PeerInfo>, 2>,
+    pub friends: Watch<ThreadModeRawMutex, [Option<Friend>; MAX_FRIENDS], 1>,
+    pub blink_sync: Watch<ThreadModeRawMutex, BlinkSync, 1>,
+    /// Behavior script run in `FaceMode::Script`, already validated.
+    pub script: Watch<ThreadModeRawMutex, Script, 1>,
+    /// When the bot blinks next, published by the behavior for the link.
+    pub next_blink: Watch<ThreadModeRawMutex, Option<Instant>, 1>,
+    /// When the friend leading the blinking blinks next.
+    pub leader_blink: Watch<ThreadModeRawMutex, Instant, 1>,
+    /// What the behavior did, for the host.  Queued rather than watched as
+    /// several things can happen at once.
+    pub behavior_events: Channel<ThreadModeRawMutex, BehaviorEvent, 8>,
+    /// Settings changed by clients or buttons, for the clients.
+    pub config_changes: Channel<ThreadModeRawMutex, ConfigChange, CONFIG_CHANGES>,
 }
 
 impl Comms {
@@ -40,8 +114,38 @@ impl Comms {
             blink_expression: Watch::new(),
             friend_expression: Watch::new(),
             friend_blink_expression: Watch::new(),
+            sleep_expression: Watch::new(),
             adc_val: Watch::new(),
             brightness_val: Watch::new(),
+            brightness_curve: Watch::new(),
+            brightness_mode: Watch::new(),
+            auto_brightness: Watch::new(),
+            ambient_val: Watch::new(),
+            display_brightness: Watch::new(),
+            clock: Watch::new(),
+            clock_config: Watch::new(),
+            schedule: Watch::new(),
+            active_schedule: Watch::new(),
+            display_stats: Watch::new(),
+            display_health: Watch::new(),
+            display_info: Watch::new(),
+            face_mode: Watch::new(),
+            gaze: Watch::new(),
+            mood: Watch::new(),
+            forced_mood: Watch::new(),
+            mood_styles: Watch::new(),
+            transitions: Watch::new(),
+            button_event: PubSubChannel::new(),
+            button_bindings: Watch::new(),
+            device_info: Watch::new(),
+            peer: Watch::new(),
+            friends: Watch::new(),
+            blink_sync: Watch::new(),
+            script: Watch::new(),
+            next_blink: Watch::new(),
+            leader_blink: Watch::new(),
+            behavior_events: Channel::new(),
+            config_changes: Channel::new(),
         }
     }
 }
@@ -51,11 +155,50 @@ pub struct Context {
     blink_expression_sender: DynSender<'static, Expression>,
     friend_expression_sender: DynSender<'static, Expression>,
     friend_blink_expression_sender: DynSender<'static, Expression>,
+    sleep_expression_sender: DynSender<'static, Expression>,
     adc_val_receiver: DynReceiver<'static, u16>,
     brightness_val_sender: DynSender<'static, u8>,
+    brightness_curve_sender: DynSender<'static, BrightnessCurve>,
+    brightness_mode_sender: DynSender<'static, BrightnessMode>,
+    auto_brightness_sender: DynSender<'static, AutoBrightness>,
+    ambient_val_receiver: DynReceiver<'static, u16>,
+    clock: Option<Clock>,
+    clock_sender: DynSender<'static, Clock>,
+    clock_config_sender: DynSender<'static, ClockConfig>,
+    schedule_sender: DynSender<'static, Schedule>,
+    display_stats_receiver: DynReceiver<'static, DisplayStats>,
+    display_health_receiver: DynReceiver<'static, DisplayHealth>,
+    display_info_receiver: DynReceiver<'static, DisplayInfo>,
+    face_mode_sender: DynSender<'static, FaceMode>,
+    gaze_sender: DynSender<'static, Option<Gaze>>,
+    mood_receiver: DynReceiver<'static, Mood>,
+    forced_mood_sender: DynSender<'static, Option<Mood>>,
+    mood_styles: [MoodStyle; Mood::ALL.len()],
+    mood_styles_sender: DynSender<'static, [MoodStyle; Mood::ALL.len()]>,
+    transitions: [TransitionStyle; TransitionKind::ALL.len()],
+    transitions_sender: DynSender<'static, [TransitionStyle; TransitionKind::ALL.len()]>,
+    button_bindings: [ButtonBindings; MAX_BUTTONS],
+    button_bindings_sender: DynSender<'static, [ButtonBindings; MAX_BUTTONS]>,
+    friends: [Option<Friend>; MAX_FRIENDS],
+    friends_sender: DynSender<'static, [Option<Friend>; MAX_FRIENDS]>,
+    blink_sync_sender: DynSender<'static, BlinkSync>,
+    script_sender: DynSender<'static, Script>,
+    device_info: DeviceInfo,
+    device_info_sender: DynSender<'static, DeviceInfo>,
+    peer_receiver: DynReceiver<'static, Option<PeerInfo>>,
+    config_changes: DynamicSender<'static, ConfigChange>,
     config_store: FlashConfigStore<Flash<'static, FLASH, Async, { crate::FLASH_SIZE }>>,
 }
 
+impl Context {
+    /// Publishes a setting changed by a client.
+    fn changed(&self, change: ConfigChange) {
+        if self.config_changes.try_send(change).is_err() {
+            error!("Dropped config change, host is not keeping up");
+        }
+    }
+}
+
 pub struct SpawnCtx {}
 
 impl SpawnContext for Context {
@@ -71,11 +214,51 @@ define_dispatch! {
         Context = Context,
     >;
     PingEndpoint => blocking ping_handler,
+    GetDeviceInfoEndpoint => blocking get_device_info_handler,
+    SetDeviceNameEndpoint => async set_device_name_handler,
     SetExpressionEndpoint => async set_expression_handler,
     GetExpressionEndpoint => async get_expression_handler,
+    ListPresetsEndpoint => blocking list_presets_handler,
+    ApplyPresetEndpoint => async apply_preset_handler,
     GetAdcEndpoint => async get_adc_handler,
     GetBrightnessEndpoint => async get_brightness_handler,
     SetBrightnessEndpoint => async set_brightness_handler,
+    GetBrightnessCurveEndpoint => async get_brightness_curve_handler,
+    SetBrightnessCurveEndpoint => async set_brightness_curve_handler,
+    GetBrightnessModeEndpoint => async get_brightness_mode_handler,
+    SetBrightnessModeEndpoint => async set_brightness_mode_handler,
+    GetAutoBrightnessEndpoint => async get_auto_brightness_handler,
+    SetAutoBrightnessEndpoint => async set_auto_brightness_handler,
+    GetAmbientLightEndpoint => async get_ambient_light_handler,
+    SetTimeEndpoint => blocking set_time_handler,
+    GetTimeEndpoint => blocking get_time_handler,
+    GetClockConfigEndpoint => async get_clock_config_handler,
+    SetClockConfigEndpoint => async set_clock_config_handler,
+    GetScheduleEndpoint => async get_schedule_handler,
+    SetScheduleEndpoint => async set_schedule_handler,
+    GetDisplayStatsEndpoint => async get_display_stats_handler,
+    GetDisplayHealthEndpoint => async get_display_health_handler,
+    GetDisplayInfoEndpoint => async get_display_info_handler,
+    GetFaceModeEndpoint => async get_face_mode_handler,
+    SetFaceModeEndpoint => async set_face_mode_handler,
+    SetGazeEndpoint => blocking set_gaze_handler,
+    GetMoodEndpoint => async get_mood_handler,
+    SetMoodEndpoint => blocking set_mood_handler,
+    GetMoodStyleEndpoint => blocking get_mood_style_handler,
+    SetMoodStyleEndpoint => async set_mood_style_handler,
+    GetTransitionEndpoint => blocking get_transition_handler,
+    SetTransitionEndpoint => async set_transition_handler,
+    GetButtonBindingsEndpoint => blocking get_button_bindings_handler,
+    SetButtonBindingsEndpoint => async set_button_bindings_handler,
+    GetPeerEndpoint => blocking get_peer_handler,
+    GetBlinkSyncEndpoint => async get_blink_sync_handler,
+    SetBlinkSyncEndpoint => async set_blink_sync_handler,
+    ListFriendsEndpoint => blocking list_friends_handler,
+    GetFriendEndpoint => blocking get_friend_handler,
+    AddFriendEndpoint => async add_friend_handler,
+    RemoveFriendEndpoint => async remove_friend_handler,
+    GetScriptEndpoint => async get_script_handler,
+    SetScriptEndpoint => async set_script_handler,
 }
 
 static ALL_BUFFERS: ConstStaticCell<AllBuffers<256, 256, 256>> =
@@ -89,11 +272,15 @@ pub async fn setup(
     driver: UsbDriver<'static, USB>,
     config_store: FlashConfigStore<Flash<'static, FLASH, Async, { crate::FLASH_SIZE }>>,
 ) -> &'static Comms {
+    let serial = chip_id();
+    static SERIAL_NUMBER: StaticCell<[u8; SERIAL_NUMBER_LEN]> = StaticCell::new();
+    let serial_digits = SERIAL_NUMBER.init(serial_number(serial));
+
     // Create embassy-usb Config
-    let mut config = Config::new(0xf569, 0x0001);
+    let mut config = Config::new(USB_VENDOR_ID, USB_PRODUCT_ID);
     config.manufacturer = Some("Konkers");
     config.product = Some("BlinkyBot");
-    config.serial_number = Some("12345678");
+    config.serial_number = Some(unwrap!(core::str::from_utf8(serial_digits)));
     config.max_power = 500;
     config.max_packet_size_0 = 64;
 
@@ -148,15 +335,59 @@ pub async fn setup(
     // Build the builder.
     let usb = builder.build();
 
+    // Placeholders until loaded from the config store.
+    let unloaded_style = MoodStyle::new(ExpressionIndex::Default, ExpressionIndex::Blink, 0, 0);
+    let unloaded_transition = TransitionStyle::new(TransitionEffect::Cut, 0);
+    let unloaded_bindings = ButtonBindings::new(
+        ButtonAction::Nothing,
+        ButtonAction::Nothing,
+        ButtonAction::Nothing,
+    );
     let mut context = Context {
         default_expression_sender: comms.default_expression.dyn_sender(),
         blink_expression_sender: comms.blink_expression.dyn_sender(),
         friend_expression_sender: comms.friend_expression.dyn_sender(),
         friend_blink_expression_sender: comms.friend_blink_expression.dyn_sender(),
+        sleep_expression_sender: comms.sleep_expression.dyn_sender(),
         adc_val_receiver: comms.adc_val.dyn_receiver().unwrap(),
         brightness_val_sender: comms.brightness_val.dyn_sender(),
+        brightness_curve_sender: comms.brightness_curve.dyn_sender(),
+        brightness_mode_sender: comms.brightness_mode.dyn_sender(),
+        auto_brightness_sender: comms.auto_brightness.dyn_sender(),
+        ambient_val_receiver: comms.ambient_val.dyn_receiver().unwrap(),
+        clock: None,
+        clock_sender: comms.clock.dyn_sender(),
+        clock_config_sender: comms.clock_config.dyn_sender(),
+        schedule_sender: comms.schedule.dyn_sender(),
+        display_stats_receiver: comms.display_stats.dyn_receiver().unwrap(),
+        display_health_receiver: comms.display_health.dyn_receiver().unwrap(),
+        display_info_receiver: comms.display_info.dyn_receiver().unwrap(),
+        face_mode_sender: comms.face_mode.dyn_sender(),
+        gaze_sender: comms.gaze.dyn_sender(),
+        mood_receiver: comms.mood.dyn_receiver().unwrap(),
+        forced_mood_sender: comms.forced_mood.dyn_sender(),
+        mood_styles: [unloaded_style; Mood::ALL.len()],
+        mood_styles_sender: comms.mood_styles.dyn_sender(),
+        transitions: [unloaded_transition; TransitionKind::ALL.len()],
+        transitions_sender: comms.transitions.dyn_sender(),
+        button_bindings: [unloaded_bindings; MAX_BUTTONS],
+        button_bindings_sender: comms.button_bindings.dyn_sender(),
+        friends: [const { None }; MAX_FRIENDS],
+        friends_sender: comms.friends.dyn_sender(),
+        blink_sync_sender: comms.blink_sync.dyn_sender(),
+        script_sender: comms.script.dyn_sender(),
+        device_info: DeviceInfo {
+            serial,
+            name: DeviceName::default(),
+        },
+        device_info_sender: comms.device_info.dyn_sender(),
+        peer_receiver: comms.peer.dyn_receiver().unwrap(),
+        config_changes: comms.config_changes.dyn_sender(),
         config_store,
     };
+    context.device_info.name = context.config_store.get_device_name().await;
+    info!("device {}", context.device_info);
+    context.device_info_sender.send(context.device_info);
     context.default_expression_sender.send(
         context
             .config_store
@@ -181,11 +412,75 @@ pub async fn setup(
             .get_expression(ExpressionIndex::FriendBlink)
             .await,
     );
+    context.sleep_expression_sender.send(
+        context
+            .config_store
+            .get_expression(ExpressionIndex::Sleep)
+            .await,
+    );
     context
         .brightness_val_sender
         .send(context.config_store.get_brightness().await);
+    context
+        .brightness_curve_sender
+        .send(context.config_store.get_brightness_curve().await);
+    context
+        .brightness_mode_sender
+        .send(context.config_store.get_brightness_mode().await);
+    context
+        .auto_brightness_sender
+        .send(context.config_store.get_auto_brightness().await);
+    context
+        .schedule_sender
+        .send(context.config_store.get_schedule().await);
+    context
+        .clock_config_sender
+        .send(context.config_store.get_clock_config().await);
+    context
+        .face_mode_sender
+        .send(context.config_store.get_face_mode().await);
+    context.gaze_sender.send(None);
+    for mood in Mood::ALL {
+        context.mood_styles[mood as usize] = context.config_store.get_mood_style(mood).await;
+    }
+    context.mood_styles_sender.send(context.mood_styles);
+    for kind in TransitionKind::ALL {
+        context.transitions[kind as usize] = context.config_store.get_transition(kind).await;
+    }
+    context.transitions_sender.send(context.transitions);
+    context.forced_mood_sender.send(None);
+    for (button, bindings) in context.button_bindings.iter_mut().enumerate() {
+        *bindings = context.config_store.get_button_bindings(button as u8).await;
+    }
+    context.button_bindings_sender.send(context.button_bindings);
+    context.friends = context.config_store.get_friends().await;
+    context.friends_sender.send(context.friends.clone());
+    context
+        .blink_sync_sender
+        .send(context.config_store.get_blink_sync().await);
+    let script = context.config_store.get_script().await;
+    match script::validate_script(&script) {
+        Ok(()) => context.script_sender.send(script),
+        Err(e) => {
+            error!("Stored script is invalid: {}", e);
+            context.script_sender.send(Script::default());
+        }
+    }
     let dispatch = Dispatcher::new(&mut buffers.tx_buf, endpoints.write_ep, context);
 
+    spawner.must_spawn(button_topic_task(
+        dispatch.sender(),
+        comms.button_event.dyn_subscriber().unwrap(),
+    ));
+    spawner.must_spawn(behavior_topic_task(
+        dispatch.sender(),
+        comms.behavior_events.dyn_receiver(),
+    ));
+    spawner.must_spawn(config_topic_task(
+        dispatch.sender(),
+        comms.config_changes.dyn_receiver(),
+    ));
+
     spawner.must_spawn(dispatch_task(
         endpoints.read_ep,
         dispatch,
@@ -196,6 +491,17 @@ pub async fn setup(
     comms
 }
 
+/// Returns the RP2350's unique chip ID.
+///
+/// Panics if it can not be read: every bot would get the same serial number
+/// otherwise, and hosts could no longer tell them apart.
+fn chip_id() -> u64 {
+    match embassy_rp::otp::get_chipid() {
+        Ok(id) => id,
+        Err(e) => defmt::panic!("Failed to read chip ID for the serial number: {}", e),
+    }
+}
+
 struct WebEndpoints<'d, D: Driver<'d>> {
     write_ep: D::EndpointIn,
     read_ep: D::EndpointOut,
@@ -224,6 +530,57 @@ async fn dispatch_task(
     rpc_dispatch(ep_out, dispatch, rx_buf).await;
 }
 
+/// Publishes button events to the host.
+#[embassy_executor::task]
+async fn button_topic_task(
+    sender: Sender<ThreadModeRawMutex, UsbDriver<'static, USB>>,
+    mut subscriber: DynSubscriber<'static, ButtonEvent>,
+) {
+    let mut seq_no = 0u32;
+    loop {
+        let event = match subscriber.next_message().await {
+            WaitResult::Message(event) => event,
+            WaitResult::Lagged(missed) => {
+                error!("Dropped {} button events, host is not keeping up", missed);
+                continue;
+            }
+        };
+        // Nothing is listening while the host is disconnected.
+        let _ = sender.publish::<ButtonTopic>(seq_no, &event).await;
+        seq_no = seq_no.wrapping_add(1);
+    }
+}
+
+/// Publishes behavior events to the host.
+#[embassy_executor::task]
+async fn behavior_topic_task(
+    sender: Sender<ThreadModeRawMutex, UsbDriver<'static, USB>>,
+    receiver: DynamicReceiver<'static, BehaviorEvent>,
+) {
+    let mut seq_no = 0u32;
+    loop {
+        let event = receiver.receive().await;
+        // Nothing is listening while the host is disconnected.
+        let _ = sender.publish::<BehaviorTopic>(seq_no, &event).await;
+        seq_no = seq_no.wrapping_add(1);
+    }
+}
+
+/// Publishes config changes to the host.
+#[embassy_executor::task]
+async fn config_topic_task(
+    sender: Sender<ThreadModeRawMutex, UsbDriver<'static, USB>>,
+    receiver: DynamicReceiver<'static, ConfigChange>,
+) {
+    let mut seq_no = 0u32;
+    loop {
+        let change = receiver.receive().await;
+        // Nothing is listening while the host is disconnected.
+        let _ = sender.publish::<ConfigChangedTopic>(seq_no, &change).await;
+        seq_no = seq_no.wrapping_add(1);
+    }
+}
+
 /// This handles the low level USB management
 #[embassy_executor::task]
 pub async fn usb_task(mut usb: UsbDevice<'static, UsbDriver<'static, USB>>) {
@@ -235,8 +592,27 @@ fn ping_handler(_context: &mut Context, header: WireHeader, rqst: u32) -> u32 {
     rqst
 }
 
+fn get_device_info_handler(context: &mut Context, header: WireHeader, _request: ()) -> DeviceInfo {
+    info!("get device info: seq - {=u32}", header.seq_no);
+    context.device_info
+}
+
+async fn set_device_name_handler(context: &mut Context, header: WireHeader, request: DeviceName) {
+    info!("set device name: seq - {=u32} {}", header.seq_no, request);
+    if let Err(e) = context.config_store.set_device_name(request).await {
+        error!("Failed to save device name to flash: {}", e);
+    }
+    context.device_info.name = request;
+    context.device_info_sender.send(context.device_info);
+    context.changed(ConfigChange::DeviceName(request));
+}
+
 async fn set_expression_handler(context: &mut Context, header: WireHeader, request: SetExpression) {
     info!("set expression: seq - {=u32} {}", header.seq_no, request);
+    set_expression(context, request).await;
+}
+
+async fn set_expression(context: &mut Context, request: SetExpression) {
     if let Err(e) = context
         .config_store
         .set_expression(request.index.clone(), request.expression.clone())
@@ -247,6 +623,7 @@ async fn set_expression_handler(context: &mut Context, header: WireHeader, reque
             request.index, e
         );
     }
+    context.changed(ConfigChange::Expression(request.clone()));
     match &request.index {
         blinkybot_rpc::ExpressionIndex::Default => {
             context.default_expression_sender.send(request.expression)
@@ -260,6 +637,9 @@ async fn set_expression_handler(context: &mut Context, header: WireHeader, reque
         blinkybot_rpc::ExpressionIndex::FriendBlink => context
             .friend_blink_expression_sender
             .send(request.expression),
+        blinkybot_rpc::ExpressionIndex::Sleep => {
+            context.sleep_expression_sender.send(request.expression)
+        }
     }
 }
 
@@ -272,6 +652,28 @@ async fn get_expression_handler(
     context.config_store.get_expression(request).await
 }
 
+fn list_presets_handler(
+    _context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> [Preset; Preset::ALL.len()] {
+    info!("list presets: seq - {=u32}", header.seq_no);
+    Preset::ALL
+}
+
+async fn apply_preset_handler(context: &mut Context, header: WireHeader, request: ApplyPreset) {
+    info!("apply preset: seq - {=u32} {}", header.seq_no, request);
+    let display_info = context.display_info_receiver.get().await;
+    set_expression(
+        context,
+        SetExpression {
+            index: request.index,
+            expression: request.preset.expression_for(display_info),
+        },
+    )
+    .await;
+}
+
 async fn get_adc_handler(context: &mut Context, header: WireHeader, _request: ()) -> u16 {
     info!("get adc: seq - {=u32}", header.seq_no);
 
@@ -286,9 +688,427 @@ async fn get_brightness_handler(context: &mut Context, header: WireHeader, _requ
 
 async fn set_brightness_handler(context: &mut Context, header: WireHeader, request: u8) {
     info!("set brightness: seq - {=u32} {}", header.seq_no, request);
+    let request = request.min(MAX_BRIGHTNESS);
 
     if let Err(e) = context.config_store.set_brightness(request).await {
         error!("Failed to save brightness to flash: {}", e);
     }
     context.brightness_val_sender.send(request);
+    context.changed(ConfigChange::Brightness(request));
+}
+
+async fn get_brightness_curve_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> BrightnessCurve {
+    let val = context.config_store.get_brightness_curve().await;
+    info!("get brightness curve: seq - {=u32} {}", header.seq_no, val);
+    val
+}
+
+async fn set_brightness_curve_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: BrightnessCurve,
+) {
+    info!(
+        "set brightness curve: seq - {=u32} {}",
+        header.seq_no, request
+    );
+
+    if let Err(e) = context.config_store.set_brightness_curve(request).await {
+        error!("Failed to save brightness curve to flash: {}", e);
+    }
+    context.brightness_curve_sender.send(request);
+    context.changed(ConfigChange::BrightnessCurve(request));
+}
+
+async fn get_brightness_mode_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> BrightnessMode {
+    let val = context.config_store.get_brightness_mode().await;
+    info!("get brightness mode: seq - {=u32} {}", header.seq_no, val);
+    val
+}
+
+async fn set_brightness_mode_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: BrightnessMode,
+) {
+    info!(
+        "set brightness mode: seq - {=u32} {}",
+        header.seq_no, request
+    );
+
+    if let Err(e) = context.config_store.set_brightness_mode(request).await {
+        error!("Failed to save brightness mode to flash: {}", e);
+    }
+    context.brightness_mode_sender.send(request);
+    context.changed(ConfigChange::BrightnessMode(request));
+}
+
+async fn get_auto_brightness_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> AutoBrightness {
+    let val = context.config_store.get_auto_brightness().await;
+    info!("get auto brightness: seq - {=u32} {}", header.seq_no, val);
+    val
+}
+
+async fn set_auto_brightness_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: AutoBrightness,
+) {
+    info!(
+        "set auto brightness: seq - {=u32} {}",
+        header.seq_no, request
+    );
+
+    if let Err(e) = context.config_store.set_auto_brightness(request).await {
+        error!("Failed to save auto brightness to flash: {}", e);
+    }
+    context.auto_brightness_sender.send(request);
+    context.changed(ConfigChange::AutoBrightness(request));
+}
+
+async fn get_ambient_light_handler(context: &mut Context, header: WireHeader, _request: ()) -> u16 {
+    info!("get ambient light: seq - {=u32}", header.seq_no);
+
+    context.ambient_val_receiver.get().await
+}
+
+fn set_time_handler(context: &mut Context, header: WireHeader, request: WallTime) {
+    info!("set time: seq - {=u32} {}", header.seq_no, request);
+
+    let clock = Clock::new(request);
+    context.clock = Some(clock);
+    context.clock_sender.send(clock);
+}
+
+fn get_time_handler(context: &mut Context, header: WireHeader, _request: ()) -> Option<WallTime> {
+    info!("get time: seq - {=u32}", header.seq_no);
+
+    context.clock.map(|clock| clock.now())
+}
+
+async fn get_clock_config_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> ClockConfig {
+    let val = context.config_store.get_clock_config().await;
+    info!("get clock config: seq - {=u32} {}", header.seq_no, val);
+    val
+}
+
+async fn set_clock_config_handler(context: &mut Context, header: WireHeader, request: ClockConfig) {
+    info!("set clock config: seq - {=u32} {}", header.seq_no, request);
+
+    if let Err(e) = context.config_store.set_clock_config(request).await {
+        error!("Failed to save clock config to flash: {}", e);
+    }
+    context.clock_config_sender.send(request);
+    context.changed(ConfigChange::ClockConfig(request));
+}
+
+async fn get_schedule_handler(context: &mut Context, header: WireHeader, _request: ()) -> Schedule {
+    info!("get schedule: seq - {=u32}", header.seq_no);
+
+    context.config_store.get_schedule().await
+}
+
+async fn set_schedule_handler(context: &mut Context, header: WireHeader, request: Schedule) {
+    info!("set schedule: seq - {=u32} {}", header.seq_no, request);
+
+    if let Err(e) = context.config_store.set_schedule(request.clone()).await {
+        error!("Failed to save schedule to flash: {}", e);
+    }
+    context.schedule_sender.send(request.clone());
+    context.changed(ConfigChange::Schedule(request));
+}
+
+async fn get_display_stats_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> DisplayStats {
+    info!("get display stats: seq - {=u32}", header.seq_no);
+
+    context.display_stats_receiver.get().await
+}
+
+async fn get_display_health_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> DisplayHealth {
+    info!("get display health: seq - {=u32}", header.seq_no);
+
+    context.display_health_receiver.get().await
+}
+
+async fn get_display_info_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> DisplayInfo {
+    info!("get display info: seq - {=u32}", header.seq_no);
+
+    context.display_info_receiver.get().await
+}
+
+async fn get_face_mode_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> FaceMode {
+    let val = context.config_store.get_face_mode().await;
+    info!("get face mode: seq - {=u32} {}", header.seq_no, val);
+    val
+}
+
+async fn set_face_mode_handler(context: &mut Context, header: WireHeader, request: FaceMode) {
+    info!("set face mode: seq - {=u32} {}", header.seq_no, request);
+
+    if let Err(e) = context.config_store.set_face_mode(request).await {
+        error!("Failed to save face mode to flash: {}", e);
+    }
+    context.face_mode_sender.send(request);
+    context.changed(ConfigChange::FaceMode(request));
+}
+
+fn set_gaze_handler(context: &mut Context, header: WireHeader, request: Option<Gaze>) {
+    info!("set gaze: seq - {=u32} {}", header.seq_no, request);
+    context.gaze_sender.send(request);
+    context.changed(ConfigChange::Gaze(request));
+}
+
+async fn get_mood_handler(context: &mut Context, header: WireHeader, _request: ()) -> Mood {
+    info!("get mood: seq - {=u32}", header.seq_no);
+
+    context.mood_receiver.get().await
+}
+
+fn set_mood_handler(context: &mut Context, header: WireHeader, request: Option<Mood>) {
+    info!("set mood: seq - {=u32} {}", header.seq_no, request);
+    context.forced_mood_sender.send(request);
+    context.changed(ConfigChange::ForcedMood(request));
+}
+
+fn get_mood_style_handler(context: &mut Context, header: WireHeader, request: Mood) -> MoodStyle {
+    info!("get mood style: seq - {=u32} {}", header.seq_no, request);
+    context.mood_styles[request as usize]
+}
+
+async fn set_mood_style_handler(context: &mut Context, header: WireHeader, request: SetMoodStyle) {
+    info!("set mood style: seq - {=u32} {}", header.seq_no, request);
+
+    if let Err(e) = context
+        .config_store
+        .set_mood_style(request.mood, request.style)
+        .await
+    {
+        error!("Failed to save mood style to flash: {}", e);
+    }
+    context.mood_styles[request.mood as usize] = request.style;
+    context.mood_styles_sender.send(context.mood_styles);
+    context.changed(ConfigChange::MoodStyle(request));
+}
+
+fn get_transition_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: TransitionKind,
+) -> TransitionStyle {
+    info!("get transition: seq - {=u32} {}", header.seq_no, request);
+    context.transitions[request as usize]
+}
+
+async fn set_transition_handler(context: &mut Context, header: WireHeader, request: SetTransition) {
+    info!("set transition: seq - {=u32} {}", header.seq_no, request);
+
+    if let Err(e) = context
+        .config_store
+        .set_transition(request.kind, request.style)
+        .await
+    {
+        error!("Failed to save transition to flash: {}", e);
+    }
+    context.transitions[request.kind as usize] = request.style;
+    context.transitions_sender.send(context.transitions);
+    context.changed(ConfigChange::Transition(request));
+}
+
+fn get_button_bindings_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: u8,
+) -> Option<ButtonBindings> {
+    info!(
+        "get button bindings: seq - {=u32} {}",
+        header.seq_no, request
+    );
+    context.button_bindings.get(request as usize).copied()
+}
+
+async fn set_button_bindings_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: SetButtonBindings,
+) {
+    info!(
+        "set button bindings: seq - {=u32} {}",
+        header.seq_no, request
+    );
+
+    let Some(bindings) = context.button_bindings.get_mut(request.button as usize) else {
+        error!("No button {}", request.button);
+        return;
+    };
+    *bindings = request.bindings;
+    if let Err(e) = context
+        .config_store
+        .set_button_bindings(request.button, request.bindings)
+        .await
+    {
+        error!("Failed to save button bindings to flash: {}", e);
+    }
+    context.button_bindings_sender.send(context.button_bindings);
+    context.changed(ConfigChange::ButtonBindings(request));
+}
+
+fn get_peer_handler(context: &mut Context, header: WireHeader, _request: ()) -> Option<PeerInfo> {
+    info!("get peer: seq - {=u32}", header.seq_no);
+
+    context.peer_receiver.try_get().flatten()
+}
+
+async fn get_blink_sync_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> BlinkSync {
+    let val = context.config_store.get_blink_sync().await;
+    info!("get blink sync: seq - {=u32} {}", header.seq_no, val);
+    val
+}
+
+async fn set_blink_sync_handler(context: &mut Context, header: WireHeader, request: BlinkSync) {
+    info!("set blink sync: seq - {=u32} {}", header.seq_no, request);
+
+    if let Err(e) = context.config_store.set_blink_sync(request).await {
+        error!("Failed to save blink sync to flash: {}", e);
+    }
+    context.blink_sync_sender.send(request);
+    context.changed(ConfigChange::BlinkSync(request));
+}
+
+fn list_friends_handler(
+    context: &mut Context,
+    header: WireHeader,
+    _request: (),
+) -> [Option<FriendId>; MAX_FRIENDS] {
+    info!("list friends: seq - {=u32}", header.seq_no);
+
+    context
+        .friends
+        .each_ref()
+        .map(|friend| friend.as_ref().map(|friend| friend.id))
+}
+
+fn get_friend_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: FriendId,
+) -> Option<Friend> {
+    info!("get friend: seq - {=u32} {}", header.seq_no, request);
+
+    context
+        .friends
+        .iter()
+        .flatten()
+        .find(|friend| friend.id == request)
+        .cloned()
+}
+
+async fn add_friend_handler(context: &mut Context, header: WireHeader, request: Friend) -> bool {
+    info!("add friend: seq - {=u32} {}", header.seq_no, request.id);
+
+    let slot = context
+        .friends
+        .iter()
+        .position(|friend| {
+            friend
+                .as_ref()
+                .is_some_and(|friend| friend.id == request.id)
+        })
+        .or_else(|| context.friends.iter().position(Option::is_none));
+    let Some(slot) = slot else {
+        error!("Friend table is full");
+        return false;
+    };
+    if let Err(e) = context
+        .config_store
+        .set_friend(slot as u8, Some(request.clone()))
+        .await
+    {
+        error!("Failed to save friend to flash: {}", e);
+    }
+    context.friends[slot] = Some(request.clone());
+    context.friends_sender.send(context.friends.clone());
+    context.changed(ConfigChange::FriendAdded(request));
+    true
+}
+
+async fn remove_friend_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: FriendId,
+) -> bool {
+    info!("remove friend: seq - {=u32} {}", header.seq_no, request);
+
+    let Some(slot) = context
+        .friends
+        .iter()
+        .position(|friend| friend.as_ref().is_some_and(|friend| friend.id == request))
+    else {
+        error!("No friend {}", request);
+        return false;
+    };
+    if let Err(e) = context.config_store.set_friend(slot as u8, None).await {
+        error!("Failed to remove friend from flash: {}", e);
+    }
+    context.friends[slot] = None;
+    context.friends_sender.send(context.friends.clone());
+    context.changed(ConfigChange::FriendRemoved(request));
+    true
+}
+
+async fn get_script_handler(context: &mut Context, header: WireHeader, _request: ()) -> Script {
+    let val = context.config_store.get_script().await;
+    info!("get script: seq - {=u32} {}", header.seq_no, val);
+    val
+}
+
+async fn set_script_handler(
+    context: &mut Context,
+    header: WireHeader,
+    request: Script,
+) -> Result<(), ScriptError> {
+    info!("set script: seq - {=u32} {}", header.seq_no, request);
+
+    script::validate_script(&request)?;
+    if let Err(e) = context.config_store.set_script(request.clone()).await {
+        error!("Failed to save script to flash: {}", e);
+    }
+    context.script_sender.send(request.clone());
+    context.changed(ConfigChange::Script(request));
+    Ok(())
 }
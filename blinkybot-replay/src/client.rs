@@ -0,0 +1,156 @@
+//! Recording exchanges and messages as they happen.
+
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use postcard::experimental::schema::Schema;
+use postcard_rpc::host_client::{HostClient, HostErr, IoClosed, Subscription};
+use postcard_rpc::standard_icd::WireError;
+use postcard_rpc::{Endpoint, Topic};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{Exchange, Failure, Message, Session};
+
+/// The session being recorded, shared with the subscriptions.
+#[derive(Default)]
+struct Recorder {
+    /// `None` while not recording.
+    session: Mutex<Option<Session>>,
+}
+
+impl Recorder {
+    fn record_exchange(&self, exchange: impl FnOnce(u32) -> Exchange) {
+        if let Some(session) = self.session.lock().unwrap().as_mut() {
+            let exchange = exchange(session.next_seq_no());
+            session.exchanges.push(exchange);
+        }
+    }
+
+    fn record_message(&self, message: impl FnOnce(u32) -> Message) {
+        if let Some(session) = self.session.lock().unwrap().as_mut() {
+            let message = message(session.next_seq_no());
+            session.messages.push(message);
+        }
+    }
+}
+
+impl From<&HostErr<WireError>> for Failure {
+    fn from(e: &HostErr<WireError>) -> Self {
+        match e {
+            HostErr::Wire(error) => Failure::Wire(postcard::to_allocvec(error).unwrap_or_default()),
+            HostErr::Closed => Failure::Closed,
+            _ => Failure::Garbled,
+        }
+    }
+}
+
+/// A `HostClient` that can record the requests sent through `send_resp()`
+/// and the messages received on topics subscribed to with `subscribe()`.
+/// Everything else is passed through to the client unrecorded.
+pub struct RecordingClient {
+    client: HostClient<WireError>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingClient {
+    pub fn new(client: HostClient<WireError>) -> Self {
+        Self {
+            client,
+            recorder: Arc::default(),
+        }
+    }
+
+    /// Starts recording a new session, dropping any being recorded.
+    pub fn start_recording(&self) {
+        *self.recorder.session.lock().unwrap() = Some(Session::default());
+    }
+
+    /// Stops recording and returns the session, or `None` if nothing was
+    /// being recorded.
+    pub fn stop_recording(&self) -> Option<Session> {
+        self.recorder.session.lock().unwrap().take()
+    }
+
+    /// Continues recording `session`, e.g. one stopped on another client
+    /// when reconnecting to the bot.
+    pub fn resume_recording(&self, session: Session) {
+        *self.recorder.session.lock().unwrap() = Some(session);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.session.lock().unwrap().is_some()
+    }
+
+    /// Sends a request like `HostClient::send_resp()`, recording it and
+    /// its response, or how it failed, if recording.
+    pub async fn send_resp<E: Endpoint>(
+        &self,
+        request: &E::Request,
+    ) -> Result<E::Response, HostErr<WireError>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: Serialize + DeserializeOwned + Schema,
+    {
+        let response = self.client.send_resp::<E>(request).await;
+        self.recorder.record_exchange(|seq_no| Exchange {
+            seq_no,
+            path: E::PATH.to_string(),
+            key: E::REQ_KEY.to_bytes(),
+            request: postcard::to_allocvec(request).unwrap_or_default(),
+            response: match &response {
+                Ok(response) => Ok(postcard::to_allocvec(response).unwrap_or_default()),
+                Err(e) => Err(e.into()),
+            },
+        });
+        response
+    }
+
+    /// Subscribes like `HostClient::subscribe()`, recording the messages
+    /// received while recording.
+    pub async fn subscribe<T: Topic>(
+        &self,
+        depth: usize,
+    ) -> Result<RecordingSubscription<T::Message>, IoClosed>
+    where
+        T::Message: Serialize + DeserializeOwned,
+    {
+        Ok(RecordingSubscription {
+            inner: self.client.subscribe::<T>(depth).await?,
+            recorder: self.recorder.clone(),
+            path: T::PATH,
+            key: T::TOPIC_KEY.to_bytes(),
+        })
+    }
+}
+
+/// A `Subscription` that records its messages in the session of the
+/// `RecordingClient` it came from.
+pub struct RecordingSubscription<M> {
+    inner: Subscription<M>,
+    recorder: Arc<Recorder>,
+    path: &'static str,
+    key: [u8; 8],
+}
+
+impl<M: Serialize> RecordingSubscription<M> {
+    /// Waits for the next message like `Subscription::recv()`.
+    pub async fn recv(&mut self) -> Option<M> {
+        let message = self.inner.recv().await?;
+        self.recorder.record_message(|seq_no| Message {
+            seq_no,
+            path: self.path.to_string(),
+            key: self.key,
+            message: postcard::to_allocvec(&message).unwrap_or_default(),
+        });
+        Some(message)
+    }
+}
+
+impl Deref for RecordingClient {
+    type Target = HostClient<WireError>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
@@ -0,0 +1,378 @@
+//! Recording and replaying RPC sessions with a bot.
+//!
+//! A `RecordingClient` wraps a `HostClient` and logs every request it sends
+//! along with the bot's response, or how it failed, as an `Exchange`.
+//! Messages received on topics it subscribed to, such as button events, are
+//! logged as `Message`s.  A recorded `Session` can be saved as text and
+//! later sent again with `replay()`, which reports every response that
+//! differs from the recorded one.  That turns real sessions, e.g. captured
+//! in the web UI, into regression tests for the firmware's handlers.
+//!
+//! Sessions are saved one exchange or message per line:
+//!
+//! ```text
+//! 3 brightness/set 5f3a0c1e9b2d4a77 32 -
+//! 4 script/set 0c5e7a3b9d214f68 0101 !wire:04
+//! 5 > behavior/events 91d3b27c04ae5f86 e8070501
+//! ```
+//!
+//! An exchange gives the sequence number, the endpoint's path and request
+//! key, then the postcard encoded request and response in hex, `-` when
+//! empty.  A failed exchange has its `Failure` in place of the response:
+//! `!closed`, `!garbled`, or `!wire:` and the postcard encoded `WireError`.
+//! A message gives the sequence number, `>`, the topic's path and key, then
+//! the postcard encoded message.  Lines starting with `#` are comments.
+//!
+//! Sequence numbers count the exchanges and messages of the recording
+//! rather than being the ones in the wire headers, which `HostClient` picks
+//! without exposing them.  Messages are kept to show what the bot did
+//! between the exchanges, `replay()` only sends the requests.
+
+use std::fmt;
+
+use postcard_rpc::standard_icd::WireError;
+
+mod client;
+mod replay;
+
+pub use client::{RecordingClient, RecordingSubscription};
+pub use replay::{replay, Difference, Mismatch};
+
+/// A request sent to the bot and its response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exchange {
+    /// Order of the exchange in the session, counting from 0 when
+    /// recording started.  Not the wire header's sequence number.
+    pub seq_no: u32,
+    /// Path of the endpoint.
+    pub path: String,
+    /// Key of the endpoint's request when it was recorded, which changes
+    /// with the request and response types.
+    pub key: [u8; 8],
+    /// Postcard encoded request.
+    pub request: Vec<u8>,
+    /// Postcard encoded response, or how the request failed.
+    pub response: Result<Vec<u8>, Failure>,
+}
+
+/// How a request failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The bot couldn't handle the request, with the postcard encoded
+    /// `WireError` it sent back.
+    Wire(Vec<u8>),
+    /// The bot was disconnected.
+    Closed,
+    /// The response got garbled on the way.
+    Garbled,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Wire(error) => match postcard::from_bytes::<WireError>(error) {
+                Ok(error) => write!(f, "{error:?}"),
+                Err(_) => write!(f, "undecodable wire error {error:02x?}"),
+            },
+            Failure::Closed => write!(f, "disconnected"),
+            Failure::Garbled => write!(f, "garbled response"),
+        }
+    }
+}
+
+/// A message the bot published on a topic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// Order of the message in the session, shared with the exchanges.
+    pub seq_no: u32,
+    /// Path of the topic.
+    pub path: String,
+    /// Key of the topic's message when it was recorded.
+    pub key: [u8; 8],
+    /// Postcard encoded message.
+    pub message: Vec<u8>,
+}
+
+/// Recorded exchanges and messages, each in the order they happened.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    pub exchanges: Vec<Exchange>,
+    pub messages: Vec<Message>,
+}
+
+impl Session {
+    /// Sequence number for whatever is recorded next.
+    fn next_seq_no(&self) -> u32 {
+        let exchanges = self.exchanges.iter().map(|exchange| exchange.seq_no);
+        let messages = self.messages.iter().map(|message| message.seq_no);
+        exchanges.chain(messages).max().map_or(0, |last| last + 1)
+    }
+}
+
+/// Why a saved session could not be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line of the text, counting from 1.
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn to_hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex == "-" {
+        return Some(Vec::new());
+    }
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn response_to_text(response: &Result<Vec<u8>, Failure>) -> String {
+    match response {
+        Ok(response) => to_hex(response),
+        Err(Failure::Wire(error)) => format!("!wire:{}", to_hex(error)),
+        Err(Failure::Closed) => "!closed".to_string(),
+        Err(Failure::Garbled) => "!garbled".to_string(),
+    }
+}
+
+fn response_from_text(text: &str) -> Option<Result<Vec<u8>, Failure>> {
+    let Some(failure) = text.strip_prefix('!') else {
+        return from_hex(text).map(Ok);
+    };
+    let failure = match failure {
+        "closed" => Failure::Closed,
+        "garbled" => Failure::Garbled,
+        _ => Failure::Wire(from_hex(failure.strip_prefix("wire:")?)?),
+    };
+    Some(Err(failure))
+}
+
+impl Session {
+    /// Returns the session as text, as described in the crate docs.
+    pub fn to_text(&self) -> String {
+        let mut lines = Vec::new();
+        for exchange in &self.exchanges {
+            let line = format!(
+                "{} {} {} {} {}\n",
+                exchange.seq_no,
+                exchange.path,
+                to_hex(&exchange.key),
+                to_hex(&exchange.request),
+                response_to_text(&exchange.response)
+            );
+            lines.push((exchange.seq_no, line));
+        }
+        for message in &self.messages {
+            let line = format!(
+                "{} > {} {} {}\n",
+                message.seq_no,
+                message.path,
+                to_hex(&message.key),
+                to_hex(&message.message)
+            );
+            lines.push((message.seq_no, line));
+        }
+        lines.sort_by_key(|&(seq_no, _)| seq_no);
+        lines.into_iter().map(|(_, line)| line).collect()
+    }
+
+    /// Reads a session saved with `to_text()`.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut session = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let error = |reason| ParseError {
+                line: index + 1,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let parse_seq_no =
+                |seq_no: &str| seq_no.parse().map_err(|_| error("bad sequence number"));
+            let parse_key = |key: &str| from_hex(key).and_then(|key| key.try_into().ok());
+            match fields[..] {
+                [seq_no, ">", path, key, message] => session.messages.push(Message {
+                    seq_no: parse_seq_no(seq_no)?,
+                    path: path.to_string(),
+                    key: parse_key(key).ok_or(error("bad key"))?,
+                    message: from_hex(message).ok_or(error("bad message"))?,
+                }),
+                [seq_no, path, key, request, response] => session.exchanges.push(Exchange {
+                    seq_no: parse_seq_no(seq_no)?,
+                    path: path.to_string(),
+                    key: parse_key(key).ok_or(error("bad key"))?,
+                    request: from_hex(request).ok_or(error("bad request"))?,
+                    response: response_from_text(response).ok_or(error("bad response"))?,
+                }),
+                _ => return Err(error("expected five fields")),
+            }
+        }
+        Ok(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            exchanges: vec![
+                Exchange {
+                    seq_no: 0,
+                    path: "ping".to_string(),
+                    key: [0x5f, 0x3a, 0x0c, 0x1e, 0x9b, 0x2d, 0x4a, 0x77],
+                    request: vec![0x2a],
+                    response: Ok(vec![0x2a]),
+                },
+                Exchange {
+                    seq_no: 1,
+                    path: "brightness/set".to_string(),
+                    key: [0; 8],
+                    request: vec![0x32],
+                    response: Ok(Vec::new()),
+                },
+                Exchange {
+                    seq_no: 7,
+                    path: "expression/get".to_string(),
+                    key: [0xff; 8],
+                    request: Vec::new(),
+                    response: Ok((0..=255).collect()),
+                },
+                Exchange {
+                    seq_no: 8,
+                    path: "script/set".to_string(),
+                    key: [1; 8],
+                    request: vec![0x01, 0x01],
+                    response: Err(Failure::Wire(vec![0x04])),
+                },
+                Exchange {
+                    seq_no: 9,
+                    path: "ping".to_string(),
+                    key: [2; 8],
+                    request: vec![0x2a],
+                    response: Err(Failure::Closed),
+                },
+                Exchange {
+                    seq_no: 10,
+                    path: "ping".to_string(),
+                    key: [2; 8],
+                    request: vec![0x2b],
+                    response: Err(Failure::Garbled),
+                },
+            ],
+            messages: vec![
+                Message {
+                    seq_no: 2,
+                    path: "button/events".to_string(),
+                    key: [3; 8],
+                    message: vec![0x00, 0x01],
+                },
+                Message {
+                    seq_no: 3,
+                    path: "behavior/events".to_string(),
+                    key: [4; 8],
+                    message: vec![0xe8, 0x07, 0x00],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = session().to_text();
+        let parsed = Session::parse(&text).unwrap();
+        assert_eq!(parsed, session());
+        assert_eq!(parsed.to_text(), text);
+    }
+
+    #[test]
+    fn text_is_one_line_per_exchange() {
+        let text = session().to_text();
+        assert_eq!(
+            text.lines().nth(1),
+            Some("1 brightness/set 0000000000000000 32 -")
+        );
+    }
+
+    #[test]
+    fn messages_are_interleaved_by_sequence_number() {
+        let text = session().to_text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[2], "2 > button/events 0303030303030303 0001");
+        assert_eq!(lines[3], "3 > behavior/events 0404040404040404 e80700");
+        assert!(lines[4].starts_with("7 expression/get "));
+    }
+
+    #[test]
+    fn failures_replace_the_response() {
+        let text = session().to_text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[5], "8 script/set 0101010101010101 0101 !wire:04");
+        assert_eq!(lines[6], "9 ping 0202020202020202 2a !closed");
+        assert_eq!(lines[7], "10 ping 0202020202020202 2b !garbled");
+    }
+
+    #[test]
+    fn next_seq_no_follows_exchanges_and_messages() {
+        assert_eq!(Session::default().next_seq_no(), 0);
+        assert_eq!(session().next_seq_no(), 11);
+        let mut session = session();
+        session.messages.push(Message {
+            seq_no: 12,
+            path: "config/changed".to_string(),
+            key: [5; 8],
+            message: Vec::new(),
+        });
+        assert_eq!(session.next_seq_no(), 13);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let text = format!("# recorded by hand\n\n{}  \n", session().to_text());
+        assert_eq!(Session::parse(&text), Ok(session()));
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        for (text, reason) in [
+            ("0 ping 5f3a0c1e9b2d4a77 2a", "expected five fields"),
+            ("x ping 5f3a0c1e9b2d4a77 2a 2a", "bad sequence number"),
+            ("0 ping 5f3a0c1e9b2d4a 2a 2a", "bad key"),
+            ("0 ping 5f3a0c1e9b2d4a77 2 2a", "bad request"),
+            ("0 ping 5f3a0c1e9b2d4a77 2a zz", "bad response"),
+            ("0 ping 5f3a0c1e9b2d4a77 2a !lost", "bad response"),
+            ("0 ping 5f3a0c1e9b2d4a77 2a !wire:z", "bad response"),
+            (
+                "x > button/events 5f3a0c1e9b2d4a77 00",
+                "bad sequence number",
+            ),
+            ("0 > button/events 5f3a 00", "bad key"),
+            ("0 > button/events 5f3a0c1e9b2d4a77 0", "bad message"),
+        ] {
+            let text = format!("# header\n{text}\n");
+            assert_eq!(Session::parse(&text), Err(ParseError { line: 2, reason }));
+        }
+    }
+}
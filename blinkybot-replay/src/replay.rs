@@ -0,0 +1,201 @@
+//! Sending recorded requests again.
+
+use std::fmt;
+
+use blinkybot_rpc::{
+    AddFriendEndpoint, ApplyPresetEndpoint, GetAdcEndpoint, GetAmbientLightEndpoint,
+    GetAutoBrightnessEndpoint, GetBlinkSyncEndpoint, GetBrightnessCurveEndpoint,
+    GetBrightnessEndpoint, GetBrightnessModeEndpoint, GetButtonBindingsEndpoint,
+    GetClockConfigEndpoint, GetDeviceInfoEndpoint, GetDisplayHealthEndpoint,
+    GetDisplayInfoEndpoint, GetDisplayStatsEndpoint, GetExpressionEndpoint, GetFaceModeEndpoint,
+    GetFriendEndpoint, GetMoodEndpoint, GetMoodStyleEndpoint, GetPeerEndpoint, GetScheduleEndpoint,
+    GetScriptEndpoint, GetTimeEndpoint, GetTransitionEndpoint, ListFriendsEndpoint,
+    ListPresetsEndpoint, PingEndpoint, RemoveFriendEndpoint, SetAutoBrightnessEndpoint,
+    SetBlinkSyncEndpoint, SetBrightnessCurveEndpoint, SetBrightnessEndpoint,
+    SetBrightnessModeEndpoint, SetButtonBindingsEndpoint, SetClockConfigEndpoint,
+    SetDeviceNameEndpoint, SetExpressionEndpoint, SetFaceModeEndpoint, SetGazeEndpoint,
+    SetMoodEndpoint, SetMoodStyleEndpoint, SetScheduleEndpoint, SetScriptEndpoint, SetTimeEndpoint,
+    SetTransitionEndpoint,
+};
+use postcard::experimental::schema::Schema;
+use postcard_rpc::host_client::HostClient;
+use postcard_rpc::standard_icd::WireError;
+use postcard_rpc::Endpoint;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{Exchange, Failure, Session};
+
+/// How a replayed exchange went differently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    /// The bot has no endpoint with the recorded path.
+    UnknownEndpoint,
+    /// The endpoint's key changed, so its request or response types did.
+    KeyChanged,
+    /// The recorded request doesn't decode as the endpoint's request.
+    BadRequest,
+    /// The request failed where it had succeeded.
+    Failed(String),
+    /// The bot responded differently, both shown with `Debug`, or with the
+    /// `Failure` for a request that failed.
+    Response { expected: String, actual: String },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::UnknownEndpoint => write!(f, "unknown endpoint"),
+            Difference::KeyChanged => write!(f, "endpoint key changed"),
+            Difference::BadRequest => write!(f, "recorded request doesn't decode"),
+            Difference::Failed(e) => write!(f, "request failed: {e}"),
+            Difference::Response { expected, actual } => {
+                write!(f, "expected {expected}, got {actual}")
+            }
+        }
+    }
+}
+
+/// A replayed exchange that went differently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub seq_no: u32,
+    pub path: String,
+    pub difference: Difference,
+}
+
+/// Sends the recorded request of `exchange` to `E` and compares the
+/// response.
+async fn send<E: Endpoint>(
+    client: &HostClient<WireError>,
+    exchange: &Exchange,
+) -> Result<(), Difference>
+where
+    E::Request: Serialize + DeserializeOwned + Schema,
+    E::Response: Serialize + DeserializeOwned + Schema + fmt::Debug,
+{
+    if exchange.key != E::REQ_KEY.to_bytes() {
+        return Err(Difference::KeyChanged);
+    }
+    let request: E::Request =
+        postcard::from_bytes(&exchange.request).map_err(|_| Difference::BadRequest)?;
+    let actual = client.send_resp::<E>(&request).await;
+    let (expected, actual) = match (&exchange.response, actual) {
+        (Ok(expected), Ok(actual)) => {
+            if postcard::to_allocvec(&actual).ok().as_ref() == Some(expected) {
+                return Ok(());
+            }
+            (describe::<E::Response>(expected), format!("{actual:?}"))
+        }
+        (Ok(_), Err(e)) => return Err(Difference::Failed(format!("{e:?}"))),
+        (Err(expected), Ok(actual)) => (expected.to_string(), format!("{actual:?}")),
+        (Err(expected), Err(e)) => {
+            let actual = Failure::from(&e);
+            if actual == *expected {
+                return Ok(());
+            }
+            (expected.to_string(), actual.to_string())
+        }
+    };
+    Err(Difference::Response { expected, actual })
+}
+
+/// Shows the postcard encoded `response` with `Debug`.
+fn describe<R: DeserializeOwned + fmt::Debug>(response: &[u8]) -> String {
+    match postcard::from_bytes::<R>(response) {
+        Ok(response) => format!("{response:?}"),
+        Err(_) => format!("undecodable {response:02x?}"),
+    }
+}
+
+/// Sends `exchange` to the endpoint with its path.
+macro_rules! dispatch {
+    ($client:expr, $exchange:expr, [$($endpoint:ty),* $(,)?]) => {{
+        $(
+            if $exchange.path == <$endpoint as Endpoint>::PATH {
+                return send::<$endpoint>($client, $exchange).await;
+            }
+        )*
+        Err(Difference::UnknownEndpoint)
+    }};
+}
+
+async fn replay_exchange(
+    client: &HostClient<WireError>,
+    exchange: &Exchange,
+) -> Result<(), Difference> {
+    dispatch!(
+        client,
+        exchange,
+        [
+            PingEndpoint,
+            GetDeviceInfoEndpoint,
+            SetDeviceNameEndpoint,
+            SetExpressionEndpoint,
+            GetExpressionEndpoint,
+            ListPresetsEndpoint,
+            ApplyPresetEndpoint,
+            GetAdcEndpoint,
+            GetBrightnessEndpoint,
+            SetBrightnessEndpoint,
+            GetBrightnessCurveEndpoint,
+            SetBrightnessCurveEndpoint,
+            GetBrightnessModeEndpoint,
+            SetBrightnessModeEndpoint,
+            GetAutoBrightnessEndpoint,
+            SetAutoBrightnessEndpoint,
+            GetAmbientLightEndpoint,
+            SetTimeEndpoint,
+            GetTimeEndpoint,
+            GetClockConfigEndpoint,
+            SetClockConfigEndpoint,
+            GetDisplayInfoEndpoint,
+            GetDisplayStatsEndpoint,
+            GetDisplayHealthEndpoint,
+            GetFaceModeEndpoint,
+            SetFaceModeEndpoint,
+            SetGazeEndpoint,
+            GetMoodEndpoint,
+            SetMoodEndpoint,
+            GetMoodStyleEndpoint,
+            SetMoodStyleEndpoint,
+            GetTransitionEndpoint,
+            SetTransitionEndpoint,
+            GetButtonBindingsEndpoint,
+            SetButtonBindingsEndpoint,
+            GetPeerEndpoint,
+            GetBlinkSyncEndpoint,
+            SetBlinkSyncEndpoint,
+            ListFriendsEndpoint,
+            GetFriendEndpoint,
+            AddFriendEndpoint,
+            RemoveFriendEndpoint,
+            GetScriptEndpoint,
+            SetScriptEndpoint,
+            GetScheduleEndpoint,
+            SetScheduleEndpoint,
+        ]
+    )
+}
+
+/// Sends the requests of `session` to the bot in order, returning the
+/// exchanges whose responses differ from the recorded ones.  A request that
+/// failed when recorded is expected to fail the same way.  The recorded
+/// topic messages are not compared.
+///
+/// Responses that depend on the bot's surroundings or the time, like
+/// `GetAdcEndpoint` or `GetTimeEndpoint`, are compared like any other, so
+/// sessions meant as tests should avoid them or expect them to differ.
+pub async fn replay(client: &HostClient<WireError>, session: &Session) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for exchange in &session.exchanges {
+        if let Err(difference) = replay_exchange(client, exchange).await {
+            mismatches.push(Mismatch {
+                seq_no: exchange.seq_no,
+                path: exchange.path.clone(),
+                difference,
+            });
+        }
+    }
+    mismatches
+}
@@ -1,13 +1,15 @@
 #![no_std]
 
 use postcard::experimental::schema::Schema;
-use postcard_rpc::endpoint;
+use postcard_rpc::{endpoint, topic};
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "wasm-bindgen")]
 use wasm_bindgen::prelude::*;
 
 endpoint!(PingEndpoint, u32, u32, "ping");
+endpoint!(GetDeviceInfoEndpoint, (), DeviceInfo, "device/info");
+endpoint!(SetDeviceNameEndpoint, DeviceName, (), "device/name/set");
 endpoint!(SetExpressionEndpoint, SetExpression, (), "expression/set");
 endpoint!(
     GetExpressionEndpoint,
@@ -15,11 +17,247 @@ endpoint!(
     Expression,
     "expression/get"
 );
+endpoint!(
+    ListPresetsEndpoint,
+    (),
+    [Preset; Preset::ALL.len()],
+    "expression/preset/list"
+);
+// Sets the slot to the preset's expression centred on the display, like
+// `SetExpressionEndpoint`.
+endpoint!(
+    ApplyPresetEndpoint,
+    ApplyPreset,
+    (),
+    "expression/preset/apply"
+);
 
 endpoint!(GetAdcEndpoint, (), u16, "adc/get");
 
 endpoint!(GetBrightnessEndpoint, (), u8, "brightness/get");
-endpoint!(SetBrightnessEndpoint, u8, (), "brightness/get");
+endpoint!(SetBrightnessEndpoint, u8, (), "brightness/set");
+endpoint!(
+    GetBrightnessCurveEndpoint,
+    (),
+    BrightnessCurve,
+    "brightness/curve/get"
+);
+endpoint!(
+    SetBrightnessCurveEndpoint,
+    BrightnessCurve,
+    (),
+    "brightness/curve/set"
+);
+endpoint!(
+    GetBrightnessModeEndpoint,
+    (),
+    BrightnessMode,
+    "brightness/mode/get"
+);
+endpoint!(
+    SetBrightnessModeEndpoint,
+    BrightnessMode,
+    (),
+    "brightness/mode/set"
+);
+endpoint!(
+    GetAutoBrightnessEndpoint,
+    (),
+    AutoBrightness,
+    "brightness/auto/get"
+);
+endpoint!(
+    SetAutoBrightnessEndpoint,
+    AutoBrightness,
+    (),
+    "brightness/auto/set"
+);
+
+endpoint!(GetAmbientLightEndpoint, (), u16, "ambient/get");
+
+endpoint!(SetTimeEndpoint, WallTime, (), "time/set");
+endpoint!(GetTimeEndpoint, (), Option<WallTime>, "time/get");
+endpoint!(GetClockConfigEndpoint, (), ClockConfig, "time/clock/get");
+endpoint!(SetClockConfigEndpoint, ClockConfig, (), "time/clock/set");
+
+endpoint!(GetDisplayInfoEndpoint, (), DisplayInfo, "display/info");
+endpoint!(GetDisplayStatsEndpoint, (), DisplayStats, "display/stats");
+endpoint!(
+    GetDisplayHealthEndpoint,
+    (),
+    DisplayHealth,
+    "display/health"
+);
+
+endpoint!(GetFaceModeEndpoint, (), FaceMode, "face/mode/get");
+endpoint!(SetFaceModeEndpoint, FaceMode, (), "face/mode/set");
+endpoint!(SetGazeEndpoint, Option<Gaze>, (), "face/gaze/set");
+
+endpoint!(GetMoodEndpoint, (), Mood, "mood/get");
+endpoint!(SetMoodEndpoint, Option<Mood>, (), "mood/set");
+endpoint!(GetMoodStyleEndpoint, Mood, MoodStyle, "mood/style/get");
+endpoint!(SetMoodStyleEndpoint, SetMoodStyle, (), "mood/style/set");
+
+endpoint!(
+    GetTransitionEndpoint,
+    TransitionKind,
+    TransitionStyle,
+    "transition/get"
+);
+endpoint!(SetTransitionEndpoint, SetTransition, (), "transition/set");
+
+endpoint!(
+    GetButtonBindingsEndpoint,
+    u8,
+    Option<ButtonBindings>,
+    "button/bindings/get"
+);
+endpoint!(
+    SetButtonBindingsEndpoint,
+    SetButtonBindings,
+    (),
+    "button/bindings/set"
+);
+topic!(ButtonTopic, ButtonEvent, "button/event");
+
+endpoint!(GetPeerEndpoint, (), Option<PeerInfo>, "link/peer");
+endpoint!(GetBlinkSyncEndpoint, (), BlinkSync, "link/blink_sync/get");
+endpoint!(SetBlinkSyncEndpoint, BlinkSync, (), "link/blink_sync/set");
+
+endpoint!(
+    ListFriendsEndpoint,
+    (),
+    [Option<FriendId>; MAX_FRIENDS],
+    "friend/list"
+);
+endpoint!(GetFriendEndpoint, FriendId, Option<Friend>, "friend/get");
+// Replaces any entry with the same ID.  Fails if the table is full.
+endpoint!(AddFriendEndpoint, Friend, bool, "friend/add");
+// Fails if there is no entry with the ID.
+endpoint!(RemoveFriendEndpoint, FriendId, bool, "friend/remove");
+
+endpoint!(GetScriptEndpoint, (), Script, "script/get");
+// Fails if the script does not pass validation, in which case the stored
+// script is kept.
+endpoint!(
+    SetScriptEndpoint,
+    Script,
+    Result<(), ScriptError>,
+    "script/set"
+);
+
+topic!(BehaviorTopic, BehaviorEvent, "behavior/event");
+topic!(ConfigChangedTopic, ConfigChange, "config/changed");
+
+endpoint!(GetScheduleEndpoint, (), Schedule, "schedule/get");
+endpoint!(SetScheduleEndpoint, Schedule, (), "schedule/set");
+
+/// USB vendor ID of BlinkyBots.
+pub const USB_VENDOR_ID: u16 = 0xf569;
+/// USB product ID of BlinkyBots.
+pub const USB_PRODUCT_ID: u16 = 0x0001;
+
+/// Length of the USB serial number string.
+pub const SERIAL_NUMBER_LEN: usize = 16;
+
+/// Returns the USB serial number string for the chip ID `serial`: 16 upper
+/// case hex digits.
+pub fn serial_number(serial: u64) -> [u8; SERIAL_NUMBER_LEN] {
+    let mut digits = [0u8; SERIAL_NUMBER_LEN];
+    for (i, digit) in digits.iter_mut().enumerate() {
+        let nibble = (serial >> ((SERIAL_NUMBER_LEN - 1 - i) * 4)) as u8 & 0xf;
+        *digit = match nibble {
+            0..=9 => b'0' + nibble,
+            _ => b'A' + nibble - 10,
+        };
+    }
+    digits
+}
+
+pub const MAX_NAME_LEN: usize = 32;
+
+/// User settable name of a bot: up to `MAX_NAME_LEN` bytes of UTF-8.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceName {
+    len: u8,
+    bytes: [u8; MAX_NAME_LEN],
+}
+
+impl DeviceName {
+    /// Returns `None` if `name` is longer than `MAX_NAME_LEN` bytes.
+    pub fn new(name: &str) -> Option<Self> {
+        if name.len() > MAX_NAME_LEN {
+            return None;
+        }
+        let mut bytes = [0u8; MAX_NAME_LEN];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Some(Self {
+            len: name.len() as u8,
+            bytes,
+        })
+    }
+
+    /// Returns the name, or an empty string if it was received corrupted.
+    pub fn as_str(&self) -> &str {
+        self.bytes
+            .get(..self.len as usize)
+            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+            .unwrap_or("")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for DeviceName {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.as_str())
+    }
+}
+
+/// Identity of a bot.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceInfo {
+    /// Unique chip ID, reported as the USB serial number by
+    /// `serial_number()`.
+    pub serial: u64,
+    /// Empty until set by the user.
+    pub name: DeviceName,
+}
+
+/// What bots tell each other over the link line.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PeerInfo {
+    pub serial: u64,
+    pub name: DeviceName,
+    pub mood: Mood,
+}
+
+/// Blinking in time with a friend.  Of two bots the one with the lower
+/// serial number leads, so a row of bots blinks one after the other.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct BlinkSync {
+    /// Follow the leader's blinks instead of picking our own.
+    pub enabled: bool,
+    /// How long after the leader to blink, 0 to blink in unison.
+    pub cascade_ms: u32,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl BlinkSync {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(enabled: bool, cascade_ms: u32) -> Self {
+        Self {
+            enabled,
+            cascade_ms,
+        }
+    }
+}
+
+/// Brightness levels are expressed as a perceptual percentage.
+pub const MAX_BRIGHTNESS: u8 = 100;
 
 #[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -29,17 +267,719 @@ pub enum ExpressionIndex {
     Blink = 1,
     Friend = 2,
     FriendBlink = 3,
+    Sleep = 4,
+}
+
+impl ExpressionIndex {
+    pub const ALL: [ExpressionIndex; 5] = [
+        ExpressionIndex::Default,
+        ExpressionIndex::Blink,
+        ExpressionIndex::Friend,
+        ExpressionIndex::FriendBlink,
+        ExpressionIndex::Sleep,
+    ];
+}
+
+/// Mapping used to convert a perceptual brightness level into LED PWM duty.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum BrightnessCurve {
+    Linear = 0,
+    Gamma22 = 1,
+    Cie1931 = 2,
+}
+
+/// Source of the display brightness.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum BrightnessMode {
+    /// Use the level set with `SetBrightnessEndpoint`.
+    Manual = 0,
+    /// Follow the ambient light sensor using the `AutoBrightness` settings.
+    Auto = 1,
+}
+
+/// Mapping from the smoothed ambient light reading to a brightness level.
+///
+/// Readings between `dark` and `bright` are interpolated between `min` and
+/// `max`.  Readings outside that range are clamped so `min` and `max` also
+/// act as the limits of automatic brightness.  `dark` may be above or below
+/// `bright` depending on how the light sensor is wired.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct AutoBrightness {
+    /// ADC reading in a dark room.
+    pub dark: u16,
+    /// ADC reading in bright light.
+    pub bright: u16,
+    /// Brightness level used in the dark.
+    pub min: u8,
+    /// Brightness level used in bright light.
+    pub max: u8,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl AutoBrightness {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(dark: u16, bright: u16, min: u8, max: u8) -> Self {
+        Self {
+            dark,
+            bright,
+            min,
+            max,
+        }
+    }
+
+    /// Returns the brightness level for the ambient light reading `ambient`.
+    pub fn level(&self, ambient: u16) -> u8 {
+        let span = self.bright as i32 - self.dark as i32;
+        if span == 0 {
+            return self.max.min(MAX_BRIGHTNESS);
+        }
+        let t = ((ambient as i32 - self.dark as i32) * 256 / span).clamp(0, 256);
+        let level = self.min as i32 + (self.max as i32 - self.min as i32) * t / 256;
+        (level as u8).min(MAX_BRIGHTNESS)
+    }
+}
+
+/// Wall clock time as set by the host.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct WallTime {
+    /// Milliseconds since the Unix epoch in UTC.
+    pub unix_millis: u64,
+    /// Offset of local time from UTC in minutes.
+    pub utc_offset_minutes: i16,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl WallTime {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(unix_millis: u64, utc_offset_minutes: i16) -> Self {
+        Self {
+            unix_millis,
+            utc_offset_minutes,
+        }
+    }
+
+    /// Milliseconds since local midnight.
+    pub fn local_millis_of_day(&self) -> u32 {
+        let local = self.unix_millis as i64 + self.utc_offset_minutes as i64 * 60 * 1000;
+        local.rem_euclid(MILLIS_PER_DAY as i64) as u32
+    }
+
+    /// Minutes since local midnight.
+    pub fn local_minute_of_day(&self) -> u16 {
+        (self.local_millis_of_day() / (60 * 1000)) as u16
+    }
+}
+
+pub const MILLIS_PER_DAY: u32 = 24 * 60 * 60 * 1000;
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// How `FaceMode::Clock` shows the time.  The clock shows `--:--` until the
+/// host has set the time.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct ClockConfig {
+    /// Show 13:00 rather than 1:00.
+    pub hours_24: bool,
+    /// Scroll the date across after showing the time.
+    pub show_date: bool,
+    /// Seconds the time is shown for before the date or the face.
+    pub clock_secs: u16,
+    /// Seconds the face is shown for between showing the time, 0 to only
+    /// show the clock.
+    pub face_secs: u16,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl ClockConfig {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(hours_24: bool, show_date: bool, clock_secs: u16, face_secs: u16) -> Self {
+        Self {
+            hours_24,
+            show_date,
+            clock_secs,
+            face_secs,
+        }
+    }
+}
+
+/// What the display shows while a schedule entry is active.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum ScheduledDisplay {
+    /// Regular expressions and blinking.
+    Normal = 0,
+    /// The `Sleep` expression without blinking.
+    Sleep = 1,
+    /// Display off.
+    Blank = 2,
+}
+
+/// A schedule entry stays active from `start` until the start of the next
+/// entry, wrapping around midnight.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct ScheduleEntry {
+    /// Local minute of the day the entry takes effect.
+    pub start: u16,
+    pub display: ScheduledDisplay,
+    /// Brightness level override.  `None` keeps the configured brightness.
+    pub brightness: Option<u8>,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl ScheduleEntry {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(start: u16, display: ScheduledDisplay, brightness: Option<u8>) -> Self {
+        Self {
+            start,
+            display,
+            brightness,
+        }
+    }
+}
+
+/// How faces are drawn.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum FaceMode {
+    /// Switch between the stored expressions.
+    Expressions = 0,
+    /// Animate a face drawn from parameters.
+    Procedural = 1,
+    /// Run the uploaded behavior `Script`.
+    Script = 2,
+    /// Show the time, alternating with the expressions as set in
+    /// `ClockConfig`.
+    Clock = 3,
+}
+
+impl FaceMode {
+    pub const ALL: [FaceMode; 4] = [
+        FaceMode::Expressions,
+        FaceMode::Procedural,
+        FaceMode::Script,
+        FaceMode::Clock,
+    ];
+
+    /// The mode after this one in `ALL`, wrapping around.
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Where a procedural face looks, from -100 (left, up) to 100 (right, down).
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct Gaze {
+    pub x: i8,
+    pub y: i8,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl Gaze {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(x: i8, y: i8) -> Self {
+        Self { x, y }
+    }
+}
+
+/// How the bot feels.  Moods change by themselves over time and in response
+/// to friends and touch unless forced with `SetMoodEndpoint`.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum Mood {
+    Idle = 0,
+    Happy = 1,
+    Sleepy = 2,
+    Surprised = 3,
+    Bored = 4,
+}
+
+impl Mood {
+    pub const ALL: [Mood; 5] = [
+        Mood::Idle,
+        Mood::Happy,
+        Mood::Sleepy,
+        Mood::Surprised,
+        Mood::Bored,
+    ];
+}
+
+/// How a mood is shown in `FaceMode::Expressions`.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct MoodStyle {
+    pub expression: ExpressionIndex,
+    /// Expression briefly shown to blink.
+    pub blink_expression: ExpressionIndex,
+    /// Shortest time between blinks in milliseconds.
+    pub blink_min_ms: u32,
+    /// Longest time between blinks in milliseconds, 0 to never blink.
+    pub blink_max_ms: u32,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl MoodStyle {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(
+        expression: ExpressionIndex,
+        blink_expression: ExpressionIndex,
+        blink_min_ms: u32,
+        blink_max_ms: u32,
+    ) -> Self {
+        Self {
+            expression,
+            blink_expression,
+            blink_min_ms,
+            blink_max_ms,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SetMoodStyle {
+    pub mood: Mood,
+    pub style: MoodStyle,
+}
+
+/// How one expression turns into another in `FaceMode::Expressions`.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum TransitionEffect {
+    /// Switch straight away.
+    Cut = 0,
+    /// Fade every pixel at once.
+    CrossFade = 1,
+    /// Sweep from left to right.
+    Wipe = 2,
+    /// Switch pixels in a scattered order.
+    Dissolve = 3,
+    /// Close the eyelids over the old expression and open them on the new
+    /// one.
+    Eyelid = 4,
+}
+
+/// Changes of expression that can have their own transition.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum TransitionKind {
+    /// Into and out of a blink.
+    Blink = 0,
+    /// To and from a friend's expressions.
+    Friend = 1,
+    /// Any other change of mood.
+    Mood = 2,
+    /// Falling asleep and waking up.
+    Sleep = 3,
+}
+
+impl TransitionKind {
+    pub const ALL: [TransitionKind; 4] = [
+        TransitionKind::Blink,
+        TransitionKind::Friend,
+        TransitionKind::Mood,
+        TransitionKind::Sleep,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct TransitionStyle {
+    pub effect: TransitionEffect,
+    pub duration_ms: u16,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl TransitionStyle {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(effect: TransitionEffect, duration_ms: u16) -> Self {
+        Self {
+            effect,
+            duration_ms,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SetTransition {
+    pub kind: TransitionKind,
+    pub style: TransitionStyle,
+}
+
+/// Longest behavior script, in bytes of bytecode.
+pub const MAX_SCRIPT_LEN: usize = 192;
+// Serde only handles arrays of up to 32 elements.
+const SCRIPT_CHUNK_LEN: usize = 32;
+
+/// Bytecode of a behavior script, as run by `blinkybot_core::script`.
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script {
+    len: u8,
+    chunks: [[u8; SCRIPT_CHUNK_LEN]; MAX_SCRIPT_LEN / SCRIPT_CHUNK_LEN],
+}
+
+impl Script {
+    /// Returns `None` if `code` is longer than `MAX_SCRIPT_LEN` bytes.
+    pub fn new(code: &[u8]) -> Option<Self> {
+        if code.len() > MAX_SCRIPT_LEN {
+            return None;
+        }
+        let mut script = Self {
+            len: code.len() as u8,
+            ..Default::default()
+        };
+        script.chunks.as_flattened_mut()[..code.len()].copy_from_slice(code);
+        Some(script)
+    }
+
+    /// Returns the bytecode, or `None` if it was received corrupted with a
+    /// length over `MAX_SCRIPT_LEN`.
+    pub fn try_code(&self) -> Option<&[u8]> {
+        self.chunks.as_flattened().get(..self.len as usize)
+    }
+
+    /// Returns the bytecode, which is empty if it was received corrupted.
+    /// Anything that stores or runs a script should check `try_code()`.
+    pub fn code(&self) -> &[u8] {
+        self.try_code().unwrap_or(&[])
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Script {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=usize} byte script", self.code().len())
+    }
+}
+
+/// Why a script was rejected.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScriptError {
+    /// Offset of the offending instruction in the bytecode.
+    pub offset: u16,
+    pub kind: ScriptErrorKind,
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScriptErrorKind {
+    UnknownOpcode,
+    /// The instruction's operands run past the end of the script.
+    Truncated,
+    /// A jump to somewhere other than the start of an instruction.
+    BadTarget,
+    BadExpression,
+    BadCounter,
+    /// A percentage above 100.
+    BadPercent,
+    /// Longer than `MAX_SCRIPT_LEN`.
+    TooLong,
+    /// A brightness above `MAX_BRIGHTNESS` that doesn't select the
+    /// configured one.
+    BadBrightness,
+}
+
+/// Something the bot did, published on `BehaviorTopic`.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BehaviorEvent {
+    /// Milliseconds since the bot started.
+    pub uptime_ms: u64,
+    pub kind: BehaviorEventKind,
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BehaviorEventKind {
+    /// The bot blinked by itself.
+    Blink,
+    /// A stored expression started showing.  Not published for procedural
+    /// faces.
+    Expression(ExpressionIndex),
+    FaceMode(FaceMode),
+    Mood(Mood),
+    /// Started or stopped seeing a friend.
+    Friend(bool),
+    /// The display's brightness changed, from 0 to `MAX_BRIGHTNESS` on the
+    /// perceptual scale and 0 when it is off.
+    Brightness(u8),
+    /// Fell asleep or woke up on schedule.
+    Sleep(bool),
+}
+
+/// A setting changed by a client, published on `ConfigChangedTopic` so
+/// that other clients can follow.  Holds what was set, after clamping.
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigChange {
+    DeviceName(DeviceName),
+    Expression(SetExpression),
+    Brightness(u8),
+    BrightnessCurve(BrightnessCurve),
+    BrightnessMode(BrightnessMode),
+    AutoBrightness(AutoBrightness),
+    Schedule(Schedule),
+    FaceMode(FaceMode),
+    Gaze(Option<Gaze>),
+    ForcedMood(Option<Mood>),
+    MoodStyle(SetMoodStyle),
+    Transition(SetTransition),
+    ButtonBindings(SetButtonBindings),
+    /// A friend was added or replaced.
+    FriendAdded(Friend),
+    FriendRemoved(FriendId),
+    BlinkSync(BlinkSync),
+    Script(Script),
+    ClockConfig(ClockConfig),
+    /// The display was turned off, or back on, with a button.
+    DisplayOff(bool),
+    /// The clock was toggled with a button, see `ButtonAction::ToggleClock`.
+    ClockToggled(bool),
+    /// The face mode was switched with a button, see
+    /// `ButtonAction::CycleFaceMode`.  The stored `FaceMode` is unchanged.
+    FaceModeCycled(FaceMode),
+}
+
+/// Number of buttons a bot can have.
+pub const MAX_BUTTONS: usize = 2;
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum Gesture {
+    Press = 0,
+    /// The button was held down.
+    LongPress = 1,
+    /// The button was pressed twice in quick succession.
+    DoublePress = 2,
+}
+
+/// Published on `ButtonTopic` for every gesture.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct ButtonEvent {
+    /// Index of the button, below `MAX_BUTTONS`.
+    pub button: u8,
+    pub gesture: Gesture,
+}
+
+/// What the bot does on a button gesture.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum ButtonAction {
+    /// Only publish the event.
+    Nothing = 0,
+    /// Touch the bot, which affects its mood.
+    Touch = 1,
+    /// Force the next mood, going back to automatic after the last one.
+    CycleMood = 2,
+    /// Turn the display off or back on.
+    ToggleBrightness = 3,
+    /// Show the clock instead of the face, or the face instead of the
+    /// clock in `FaceMode::Clock`.
+    ToggleClock = 4,
+    /// Switch to the next face mode, such as from the stored expressions to
+    /// the procedural face.  The stored mode comes back on restart.
+    CycleFaceMode = 5,
+}
+
+/// Actions for each gesture of a button.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct ButtonBindings {
+    pub press: ButtonAction,
+    pub long_press: ButtonAction,
+    pub double_press: ButtonAction,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl ButtonBindings {
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(press: ButtonAction, long_press: ButtonAction, double_press: ButtonAction) -> Self {
+        Self {
+            press,
+            long_press,
+            double_press,
+        }
+    }
+
+    pub fn action(&self, gesture: Gesture) -> ButtonAction {
+        match gesture {
+            Gesture::Press => self.press,
+            Gesture::LongPress => self.long_press,
+            Gesture::DoublePress => self.double_press,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SetButtonBindings {
+    pub button: u8,
+    pub bindings: ButtonBindings,
+}
+
+/// Number of entries in the friend table.
+pub const MAX_FRIENDS: usize = 8;
+
+/// How a friend is recognised.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FriendId {
+    /// A bot that said hello over the link line with this serial number.
+    Serial(u64),
+    /// Anything reading from `min` to `max` inclusive on the sense input,
+    /// such as a toy with a resistor across its contacts.
+    AdcBand { min: u16, max: u16 },
+}
+
+impl FriendId {
+    /// Whether this is the friend with `serial`, if one said hello, reading
+    /// `adc` on the sense input.
+    pub fn matches(&self, serial: Option<u64>, adc: u16) -> bool {
+        match *self {
+            Self::Serial(id) => serial == Some(id),
+            Self::AdcBand { min, max } => (min..=max).contains(&adc),
+        }
+    }
 }
 
+/// Entry in the friend table.  While the friend is seen its expressions are
+/// shown instead of the `Friend` and `FriendBlink` ones.
 #[derive(Serialize, Deserialize, Schema, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Friend {
+    pub id: FriendId,
+    pub expression: Expression,
+    pub blink_expression: Expression,
+}
+
+pub const MAX_SCHEDULE_ENTRIES: usize = 8;
+
+/// Time of day schedule.  An empty schedule leaves the display alone.
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Schedule {
+    pub entries: [Option<ScheduleEntry>; MAX_SCHEDULE_ENTRIES],
+}
+
+impl Schedule {
+    /// Returns the entry active at `minute_of_day`.
+    pub fn active(&self, minute_of_day: u16) -> Option<ScheduleEntry> {
+        let entries = self.entries.iter().flatten();
+        // The latest entry that has started today, otherwise the last entry
+        // from yesterday is still active.
+        entries
+            .clone()
+            .filter(|entry| entry.start <= minute_of_day)
+            .max_by_key(|entry| entry.start)
+            .or_else(|| entries.max_by_key(|entry| entry.start))
+            .copied()
+    }
+}
+
+/// Display update counters since boot.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct DisplayStats {
+    /// Frames drawn, including ones that were already up to date.
+    pub frames: u32,
+    /// Frames that matched what the display already had and were skipped.
+    pub unchanged_frames: u32,
+    /// Bytes written to the display, including the address byte of each
+    /// transfer.
+    pub i2c_bytes: u32,
+    /// Time taken by the last frame that needed writing in microseconds.
+    pub last_frame_us: u32,
+    /// Slowest frame in microseconds.
+    pub max_frame_us: u32,
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum DisplayState {
+    /// The display has not been set up yet.
+    Initializing = 0,
+    Ok = 1,
+    /// The display stopped responding and is being reset.
+    Recovering = 2,
+}
+
+/// Display state and I2C error counters since boot.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct DisplayHealth {
+    pub state: DisplayState,
+    /// Failed I2C transfers.
+    pub i2c_errors: u32,
+    /// Transfers retried after an error.
+    pub retries: u32,
+    /// Times the display was reset and set up again.
+    pub resets: u32,
+}
+
+/// Largest display supported by `Expression`.
+pub const MAX_WIDTH: u32 = 16;
+pub const MAX_HEIGHT: u32 = 16;
+
+/// Geometry of the bot's display.  Expressions are drawn from their top left
+/// corner and pixels outside the display are ignored.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub struct DisplayInfo {
+    pub width: u8,
+    pub height: u8,
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Expression {
-    pub pixels: [u16; 7],
+    /// One bit per pixel, row by row with bit `x` of row `y` being pixel
+    /// `(x, y)`.
+    pub pixels: [u16; MAX_HEIGHT as usize],
 }
 
 impl Expression {
+    /// Creates an expression from the top rows of the bitmap.  Any rows not
+    /// in `rows` are blank.
+    pub fn from_rows(rows: &[u16]) -> Self {
+        let mut expression = Self::default();
+        for (pixels, row) in expression.pixels.iter_mut().zip(rows) {
+            *pixels = *row;
+        }
+        expression
+    }
+
     pub fn set_pixel(&mut self, x: u32, y: u32, state: bool) {
-        if x < 15 && y < 7 {
+        if x < MAX_WIDTH && y < MAX_HEIGHT {
             if state {
                 self.pixels[y as usize] |= 1 << x;
             } else {
@@ -49,7 +989,7 @@ impl Expression {
     }
 
     pub fn get_pixel(&self, x: u32, y: u32) -> bool {
-        if x < 15 && y < 7 {
+        if x < MAX_WIDTH && y < MAX_HEIGHT {
             (self.pixels[y as usize] & (1 << x)) != 0
         } else {
             false
@@ -62,3 +1002,332 @@ pub struct SetExpression {
     pub index: ExpressionIndex,
     pub expression: Expression,
 }
+
+/// Built-in expressions, to quickly give a slot a face with
+/// `ApplyPresetEndpoint`.  Drawn for a 15x7 display and centred on others.
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub enum Preset {
+    /// Wide open eyes and a small smile.
+    Neutral = 0,
+    /// `Neutral` with its eyes shut.
+    Blink = 1,
+    /// Smiling eyes and a wide grin.
+    Happy = 2,
+    /// `Happy` with its eyes shut.
+    Content = 3,
+    /// Drooping eyes and a frown.
+    Sad = 4,
+    /// Lowered brows and a tight mouth.
+    Angry = 5,
+    /// Hearts for eyes.
+    HeartEyes = 6,
+    /// Shut eyes and a slack mouth.
+    Sleepy = 7,
+    /// `Neutral` with one eye shut.
+    Wink = 8,
+    /// Round eyes and an open mouth.
+    Surprised = 9,
+    /// Half shut eyes and a flat mouth.
+    Bored = 10,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 11] = [
+        Preset::Neutral,
+        Preset::Blink,
+        Preset::Happy,
+        Preset::Content,
+        Preset::Sad,
+        Preset::Angry,
+        Preset::HeartEyes,
+        Preset::Sleepy,
+        Preset::Wink,
+        Preset::Surprised,
+        Preset::Bored,
+    ];
+
+    /// Short name, as used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Neutral => "neutral",
+            Preset::Blink => "blink",
+            Preset::Happy => "happy",
+            Preset::Content => "content",
+            Preset::Sad => "sad",
+            Preset::Angry => "angry",
+            Preset::HeartEyes => "heart-eyes",
+            Preset::Sleepy => "sleepy",
+            Preset::Wink => "wink",
+            Preset::Surprised => "surprised",
+            Preset::Bored => "bored",
+        }
+    }
+
+    /// Size of the display the presets are drawn for.
+    pub const DRAWN_FOR: DisplayInfo = DisplayInfo {
+        width: 15,
+        height: 7,
+    };
+
+    /// Drawn for the 15x7 display, see `expression_for()` for others.
+    pub fn expression(self) -> Expression {
+        let rows = match self {
+            Preset::Neutral => [
+                0b000_0000_0000_0000,
+                0b001_1000_0000_1100,
+                0b010_0100_0001_0010,
+                0b010_0100_0001_0010,
+                0b001_1001_0100_1100,
+                0b000_0001_1100_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::Blink => [
+                0b000_0000_0000_0000,
+                0b000_0000_0000_0000,
+                0b011_1100_0001_1110,
+                0b000_0000_0000_0000,
+                0b000_0001_0100_0000,
+                0b000_0001_1100_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::Happy => [
+                0b000_0000_0000_0000,
+                0b001_1000_0000_1100,
+                0b010_0100_0001_0010,
+                0b000_0000_0000_0000,
+                0b000_0100_0001_0000,
+                0b000_0011_1110_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::Content => [
+                0b000_0000_0000_0000,
+                0b000_0000_0000_0000,
+                0b011_1100_0001_1110,
+                0b000_0000_0000_0000,
+                0b000_0100_0001_0000,
+                0b000_0011_1110_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::Sad => [
+                0b000_0000_0000_0000,
+                0b000_0000_0000_0000,
+                0b011_1100_0001_1110,
+                0b001_1000_0000_1100,
+                0b000_0000_0000_0000,
+                0b000_0001_1100_0000,
+                0b000_0010_0010_0000,
+            ],
+            Preset::Angry => [
+                0b011_0000_0000_0110,
+                0b000_1100_0001_1000,
+                0b001_1000_0000_1100,
+                0b001_1000_0000_1100,
+                0b000_0000_0000_0000,
+                0b000_0011_1110_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::HeartEyes => [
+                0b000_0000_0000_0000,
+                0b011_0110_0011_0110,
+                0b011_1110_0011_1110,
+                0b001_1100_0001_1100,
+                0b000_1000_0000_1000,
+                0b000_0001_1100_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::Sleepy => [
+                0b000_0000_0000_0000,
+                0b000_0000_0000_0000,
+                0b000_0000_0000_0000,
+                0b011_1100_0001_1110,
+                0b000_0000_0000_0000,
+                0b000_0001_1100_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::Wink => [
+                0b000_0000_0000_0000,
+                0b001_1000_0000_0000,
+                0b010_0100_0000_0000,
+                0b010_0100_0001_1110,
+                0b001_1001_0100_0000,
+                0b000_0001_1100_0000,
+                0b000_0000_0000_0000,
+            ],
+            Preset::Surprised => [
+                0b001_1100_0001_1100,
+                0b010_0010_0010_0010,
+                0b010_0010_0010_0010,
+                0b001_1100_0001_1100,
+                0b000_0001_1100_0000,
+                0b000_0010_0010_0000,
+                0b000_0001_1100_0000,
+            ],
+            Preset::Bored => [
+                0b000_0000_0000_0000,
+                0b000_0000_0000_0000,
+                0b011_1100_0001_1110,
+                0b001_1000_0000_1100,
+                0b000_0000_0000_0000,
+                0b000_0011_1110_0000,
+                0b000_0000_0000_0000,
+            ],
+        };
+        Expression::from_rows(&rows)
+    }
+
+    /// Centred on `display`, rounding towards the top left.  Displays
+    /// smaller than `DRAWN_FOR` lose the edges of the drawing.
+    pub fn expression_for(self, display: DisplayInfo) -> Expression {
+        let offset = |size: u8, drawn: u8| (i32::from(size) - i32::from(drawn)) / 2;
+        let dx = offset(display.width, Self::DRAWN_FOR.width);
+        let dy = offset(display.height, Self::DRAWN_FOR.height);
+        let drawn = self.expression();
+        let mut expression = Expression::default();
+        for y in 0..u32::from(Self::DRAWN_FOR.height) {
+            for x in 0..u32::from(Self::DRAWN_FOR.width) {
+                let (Ok(to_x), Ok(to_y)) =
+                    (u32::try_from(x as i32 + dx), u32::try_from(y as i32 + dy))
+                else {
+                    continue;
+                };
+                if to_x < u32::from(display.width) && to_y < u32::from(display.height) {
+                    expression.set_pixel(to_x, to_y, drawn.get_pixel(x, y));
+                }
+            }
+        }
+        expression
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ApplyPreset {
+    pub preset: Preset,
+    pub index: ExpressionIndex,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(start: u16, display: ScheduledDisplay) -> ScheduleEntry {
+        ScheduleEntry::new(start, display, None)
+    }
+
+    /// Wakes at 07:00 and sleeps at 22:30, with the entries out of order and
+    /// a gap between them.
+    fn schedule() -> Schedule {
+        let mut schedule = Schedule::default();
+        schedule.entries[1] = Some(entry(22 * 60 + 30, ScheduledDisplay::Sleep));
+        schedule.entries[4] = Some(entry(7 * 60, ScheduledDisplay::Normal));
+        schedule
+    }
+
+    #[test]
+    fn empty_schedule() {
+        let schedule = Schedule::default();
+        assert_eq!(schedule.active(0), None);
+        assert_eq!(schedule.active(12 * 60), None);
+        assert_eq!(schedule.active(MINUTES_PER_DAY - 1), None);
+    }
+
+    #[test]
+    fn before_first_entry_is_yesterdays_last() {
+        let schedule = schedule();
+        let sleep = Some(entry(22 * 60 + 30, ScheduledDisplay::Sleep));
+        assert_eq!(schedule.active(0), sleep);
+        assert_eq!(schedule.active(7 * 60 - 1), sleep);
+    }
+
+    #[test]
+    fn entry_starts_on_its_minute() {
+        let schedule = schedule();
+        assert_eq!(
+            schedule.active(7 * 60),
+            Some(entry(7 * 60, ScheduledDisplay::Normal))
+        );
+        assert_eq!(
+            schedule.active(22 * 60 + 30),
+            Some(entry(22 * 60 + 30, ScheduledDisplay::Sleep))
+        );
+    }
+
+    #[test]
+    fn after_last_entry() {
+        let schedule = schedule();
+        let sleep = Some(entry(22 * 60 + 30, ScheduledDisplay::Sleep));
+        assert_eq!(
+            schedule.active(22 * 60 + 29),
+            Some(entry(7 * 60, ScheduledDisplay::Normal))
+        );
+        assert_eq!(schedule.active(23 * 60), sleep);
+        assert_eq!(schedule.active(MINUTES_PER_DAY - 1), sleep);
+    }
+
+    #[test]
+    fn script_longer_than_max_has_no_code() {
+        let code = [1; MAX_SCRIPT_LEN];
+        assert_eq!(Script::new(&code).unwrap().try_code(), Some(&code[..]));
+        assert_eq!(Script::new(&[0; MAX_SCRIPT_LEN + 1]), None);
+
+        let corrupted = Script {
+            len: MAX_SCRIPT_LEN as u8 + 1,
+            ..Default::default()
+        };
+        assert_eq!(corrupted.try_code(), None);
+        assert_eq!(corrupted.code(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn preset_is_unchanged_on_the_display_it_was_drawn_for() {
+        for preset in Preset::ALL {
+            assert_eq!(
+                preset.expression_for(Preset::DRAWN_FOR),
+                preset.expression()
+            );
+        }
+    }
+
+    #[test]
+    fn preset_is_centred_on_larger_displays() {
+        let drawn = Preset::Neutral.expression();
+        let centred = Preset::Neutral.expression_for(DisplayInfo {
+            width: 16,
+            height: 16,
+        });
+        // One spare column rounds to the left, nine spare rows put four
+        // above the drawing.
+        let mut expected = Expression::default();
+        for (row, pixels) in drawn.pixels.iter().take(7).enumerate() {
+            expected.pixels[row + 4] = *pixels;
+        }
+        assert_eq!(centred, expected);
+    }
+
+    #[test]
+    fn preset_is_cropped_on_smaller_displays() {
+        let cropped = Preset::Neutral.expression_for(DisplayInfo {
+            width: 11,
+            height: 5,
+        });
+        // Two columns and one row are lost on each side.
+        let drawn = Preset::Neutral.expression();
+        let mut expected = Expression::default();
+        for (row, pixels) in drawn.pixels[1..6].iter().enumerate() {
+            expected.pixels[row] = (pixels >> 2) & 0b111_1111_1111;
+        }
+        assert_eq!(cropped, expected);
+    }
+
+    #[test]
+    fn single_entry_is_always_active() {
+        let mut schedule = Schedule::default();
+        let blank = ScheduleEntry::new(12 * 60, ScheduledDisplay::Blank, Some(0));
+        schedule.entries[MAX_SCHEDULE_ENTRIES - 1] = Some(blank);
+        for minute in [0, 12 * 60 - 1, 12 * 60, MINUTES_PER_DAY - 1] {
+            assert_eq!(schedule.active(minute), Some(blank));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+
+use postcard_rpc::{host_client::HostErr, standard_icd::WireError};
+use wasm_bindgen::prelude::*;
+
+/// Kinds of `BlinkyBotError`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// No bot was picked, or the picked one is not the one asked for.
+    Connect,
+    /// The bot was disconnected.
+    Disconnected,
+    /// The request or its response got garbled on the way.
+    Comms,
+    /// An argument can't be sent to the bot, e.g. a name that's too long.
+    InvalidArgument,
+    /// The bot refused the request, e.g. an invalid script.
+    Rejected,
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const BLINKY_BOT_ERROR: &str = r#"
+/**
+ * Error thrown by every fallible function of the client.  Its `name` is
+ * the `kind` followed by `Error`, e.g. `RejectedError`.
+ */
+export interface BlinkyBotError extends Error {
+    kind: ErrorKind;
+}
+"#;
+
+/// Error thrown by every fallible function of the client, as a JS `Error`
+/// described by the `BlinkyBotError` interface.  Check `kind` to tell them
+/// apart, `message` says what went wrong.
+#[derive(Clone, Debug)]
+pub struct BlinkyBotError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl BlinkyBotError {
+    pub(crate) fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn invalid(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidArgument, message)
+    }
+}
+
+impl fmt::Display for BlinkyBotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+impl From<BlinkyBotError> for JsValue {
+    fn from(e: BlinkyBotError) -> Self {
+        let error = js_sys::Error::new(&e.message);
+        error.set_name(&format!("{:?}Error", e.kind));
+        // Only fails on frozen objects, which a new error isn't.
+        let _ = js_sys::Reflect::set(&error, &"kind".into(), &e.kind.into());
+        error.into()
+    }
+}
+
+impl From<HostErr<WireError>> for BlinkyBotError {
+    fn from(e: HostErr<WireError>) -> Self {
+        match e {
+            HostErr::Closed => Self::new(ErrorKind::Disconnected, "bot disconnected"),
+            e => Self::new(ErrorKind::Comms, format!("{e:?}")),
+        }
+    }
+}
@@ -1,43 +1,193 @@
-use std::convert::Infallible;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
+use blinkybot_replay::{RecordingClient, RecordingSubscription};
 use blinkybot_rpc::{
-    self, ExpressionIndex, GetAdcEndpoint, GetBrightnessEndpoint, GetExpressionEndpoint,
-    PingEndpoint, SetBrightnessEndpoint, SetExpression, SetExpressionEndpoint,
+    self, serial_number, AddFriendEndpoint, ApplyPreset, ApplyPresetEndpoint, AutoBrightness,
+    BehaviorTopic, BlinkSync, BrightnessCurve, BrightnessMode, ButtonBindings, ButtonEvent,
+    ButtonTopic, ClockConfig, ConfigChangedTopic, DeviceName, DisplayHealth, DisplayInfo,
+    DisplayStats, ExpressionIndex, FaceMode, Gaze, GetAdcEndpoint, GetAmbientLightEndpoint,
+    GetAutoBrightnessEndpoint, GetBlinkSyncEndpoint, GetBrightnessCurveEndpoint,
+    GetBrightnessEndpoint, GetBrightnessModeEndpoint, GetButtonBindingsEndpoint,
+    GetClockConfigEndpoint, GetDeviceInfoEndpoint, GetDisplayHealthEndpoint,
+    GetDisplayInfoEndpoint, GetDisplayStatsEndpoint, GetExpressionEndpoint, GetFaceModeEndpoint,
+    GetFriendEndpoint, GetMoodEndpoint, GetMoodStyleEndpoint, GetPeerEndpoint, GetScheduleEndpoint,
+    GetScriptEndpoint, GetTimeEndpoint, GetTransitionEndpoint, ListFriendsEndpoint,
+    ListPresetsEndpoint, Mood, MoodStyle, PingEndpoint, Preset, RemoveFriendEndpoint,
+    ScheduleEntry, Script, SetAutoBrightnessEndpoint, SetBlinkSyncEndpoint,
+    SetBrightnessCurveEndpoint, SetBrightnessEndpoint, SetBrightnessModeEndpoint,
+    SetButtonBindings, SetButtonBindingsEndpoint, SetClockConfigEndpoint, SetDeviceNameEndpoint,
+    SetExpression, SetExpressionEndpoint, SetFaceModeEndpoint, SetGazeEndpoint, SetMoodEndpoint,
+    SetMoodStyle, SetMoodStyleEndpoint, SetScheduleEndpoint, SetScriptEndpoint, SetTimeEndpoint,
+    SetTransition, SetTransitionEndpoint, TransitionKind, TransitionStyle, WallTime, MAX_HEIGHT,
+    MAX_SCHEDULE_ENTRIES, MAX_WIDTH, SERIAL_NUMBER_LEN,
 };
 use postcard_rpc::{
-    host_client::{HostClient, HostErr},
+    host_client::HostClient,
     standard_icd::{WireError, ERROR_PATH},
 };
-use wasm_bindgen::prelude::*;
+use wasm_bindgen::{prelude::*, Clamped};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{ImageData, UsbConnectionEvent, UsbDevice};
+use webusb::{WebUsbSpawn, WebUsbWire};
 
+mod error;
+mod pack;
 mod utils;
+mod webusb;
+
+pub use error::{BlinkyBotError, ErrorKind};
+pub use pack::{pack_from_text, pack_info, pack_to_text, PackInfo};
+
+#[wasm_bindgen]
+pub struct BlinkyBotClient {
+    connection: Rc<Connection>,
+    on_connect: Closure<dyn FnMut(UsbConnectionEvent)>,
+    on_disconnect: Closure<dyn FnMut(UsbConnectionEvent)>,
+}
+
+/// Whether a `BlinkyBotClient` can talk to its bot.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The bot was unplugged or reset.  Requests fail with
+    /// `ErrorKind::Disconnected` until it is plugged back in, when the
+    /// client reconnects by itself.
+    Disconnected,
+    /// Closed with `close()`.
+    Closed,
+}
+
+/// The client's connection, shared with the USB event listeners.
+struct Connection {
+    /// Replaced on every reconnect.
+    client: RefCell<Rc<RecordingClient>>,
+    /// USB serial number, to recognise the bot when it comes back.
+    serial: Option<String>,
+    state: Cell<ConnectionState>,
+    listener: RefCell<Option<js_sys::Function>>,
+}
+
+impl Connection {
+    fn client(&self) -> Rc<RecordingClient> {
+        self.client.borrow().clone()
+    }
+
+    fn is_bot(&self, device: &UsbDevice) -> bool {
+        self.serial.is_some() && device.serial_number() == self.serial
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.state.set(state);
+        // Cloned so that the listener can replace itself.
+        let listener = self.listener.borrow().clone();
+        if let Some(listener) = listener {
+            if let Err(e) = listener.call1(&JsValue::NULL, &state.into()) {
+                web_sys::console::error_2(&"connection listener failed:".into(), &e);
+            }
+        }
+    }
+
+    fn disconnected(&self) {
+        if self.state.get() == ConnectionState::Connected {
+            // Fails requests still waiting for a response.
+            self.client().close();
+            self.set_state(ConnectionState::Disconnected);
+        }
+    }
+
+    async fn reconnect(self: Rc<Self>, device: UsbDevice) {
+        if self.state.get() != ConnectionState::Disconnected {
+            return;
+        }
+        let client = match open(device).await {
+            Ok(client) => RecordingClient::new(client),
+            Err(e) => {
+                let message = format!("failed to reconnect: {e}");
+                web_sys::console::warn_1(&message.into());
+                return;
+            }
+        };
+        // Closed while reconnecting.
+        if self.state.get() != ConnectionState::Disconnected {
+            client.close();
+            return;
+        }
+        if let Some(session) = self.client().stop_recording() {
+            client.resume_recording(session);
+        }
+        *self.client.borrow_mut() = Rc::new(client);
+        self.set_state(ConnectionState::Connected);
+    }
+}
 
-#[derive(Debug)]
-pub enum Error<E: std::error::Error> {
-    Comms(HostErr<WireError>),
-    Endpoint(E),
+/// Identity of a bot.
+#[wasm_bindgen(getter_with_clone)]
+pub struct DeviceInfo {
+    /// USB serial number.
+    pub serial: String,
+    /// Empty until set by the user.
+    pub name: String,
 }
 
-impl<E: std::error::Error> From<Error<E>> for JsValue {
-    fn from(e: Error<E>) -> Self {
-        match e {
-            Error::Comms(e) => format!("comms error: {e:?}").into(),
-            Error::Endpoint(e) => format!("endpoint error: {e}").into(),
+impl From<blinkybot_rpc::DeviceInfo> for DeviceInfo {
+    fn from(info: blinkybot_rpc::DeviceInfo) -> Self {
+        Self {
+            serial: String::from_utf8_lossy(&serial_number(info.serial)).into_owned(),
+            name: info.name.as_str().to_string(),
         }
     }
 }
 
-impl<E: std::error::Error> From<HostErr<WireError>> for Error<E> {
-    fn from(value: HostErr<WireError>) -> Self {
-        Self::Comms(value)
+/// Another bot seen over the link line.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Peer {
+    /// USB serial number.
+    pub serial: String,
+    pub name: String,
+    pub mood: Mood,
+}
+
+impl From<blinkybot_rpc::PeerInfo> for Peer {
+    fn from(info: blinkybot_rpc::PeerInfo) -> Self {
+        Self {
+            serial: String::from_utf8_lossy(&serial_number(info.serial)).into_owned(),
+            name: info.name.as_str().to_string(),
+            mood: info.mood,
+        }
+    }
+}
+
+impl DeviceInfo {
+    fn matches(&self, selector: &str) -> bool {
+        self.serial.eq_ignore_ascii_case(selector) || self.name == selector
     }
 }
 
 #[wasm_bindgen]
-pub struct BlinkyBotClient {
-    client: HostClient<WireError>,
+extern "C" {
+    /// Pixels of an expression, row by row.
+    #[wasm_bindgen(typescript_type = "boolean[][]")]
+    pub type Rows;
+}
+
+/// Checks that an expression of `width` by `height` pixels fits.
+fn check_size(width: u32, height: u32) -> Result<(), BlinkyBotError> {
+    if width > MAX_WIDTH || height > MAX_HEIGHT {
+        return Err(BlinkyBotError::invalid(format!(
+            "expression is {width}x{height}, the maximum is {MAX_WIDTH}x{MAX_HEIGHT}"
+        )));
+    }
+    Ok(())
 }
 
+/// Pixels of an expression.  Besides pixel by pixel, it converts to and from
+/// `boolean[][]`, `Uint8Array` with a byte per pixel and `ImageData` for
+/// canvases, all row by row.  Exports take the size to give, usually that
+/// of the display from `get_display_info()`.
 #[wasm_bindgen]
 pub struct Expression {
     inner: blinkybot_rpc::Expression,
@@ -48,7 +198,7 @@ impl Expression {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
         Self {
-            inner: blinkybot_rpc::Expression { pixels: [0u16; 7] },
+            inner: blinkybot_rpc::Expression::default(),
         }
     }
 
@@ -59,42 +209,809 @@ impl Expression {
     pub fn get_pixel(&self, x: u32, y: u32) -> bool {
         self.inner.get_pixel(x, y)
     }
+
+    /// Reads `rows` of pixels, any truthy value being lit.
+    pub fn from_rows(rows: Rows) -> Result<Expression, BlinkyBotError> {
+        let rows: js_sys::Array = rows
+            .dyn_into()
+            .map_err(|_| BlinkyBotError::invalid("rows must be an array"))?;
+        let mut expression = Self::new();
+        for (y, row) in rows.iter().enumerate() {
+            let row: js_sys::Array = row
+                .dyn_into()
+                .map_err(|_| BlinkyBotError::invalid(format!("row {y} must be an array")))?;
+            check_size(row.length(), rows.length())?;
+            for (x, pixel) in row.iter().enumerate() {
+                expression.set_pixel(x as u32, y as u32, pixel.is_truthy());
+            }
+        }
+        Ok(expression)
+    }
+
+    /// Returns the top left `width` by `height` pixels.
+    pub fn to_rows(&self, width: u32, height: u32) -> Rows {
+        let rows: js_sys::Array = (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| JsValue::from_bool(self.get_pixel(x, y)))
+                    .collect::<js_sys::Array>()
+            })
+            .collect();
+        rows.unchecked_into()
+    }
+
+    /// Reads `width` pixels per row from `bytes`, any non-zero byte being
+    /// lit.
+    pub fn from_bytes(bytes: &[u8], width: u32) -> Result<Expression, BlinkyBotError> {
+        if width == 0 || !bytes.len().is_multiple_of(width as usize) {
+            return Err(BlinkyBotError::invalid(format!(
+                "{} bytes is not a whole number of {width} pixel rows",
+                bytes.len()
+            )));
+        }
+        check_size(width, bytes.len() as u32 / width)?;
+        let mut expression = Self::new();
+        for (i, &byte) in bytes.iter().enumerate() {
+            let i = i as u32;
+            expression.set_pixel(i % width, i / width, byte != 0);
+        }
+        Ok(expression)
+    }
+
+    /// Returns the top left `width` by `height` pixels, 1 when lit and 0
+    /// otherwise.
+    pub fn to_bytes(&self, width: u32, height: u32) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| self.get_pixel(x, y) as u8)
+            .collect()
+    }
+
+    /// Reads the pixels of `image`, those brighter than half being lit.
+    /// Transparent pixels are dark.
+    pub fn from_image_data(image: &ImageData) -> Result<Expression, BlinkyBotError> {
+        check_size(image.width(), image.height())?;
+        let mut expression = Self::new();
+        for (i, rgba) in image.data().chunks_exact(4).enumerate() {
+            let i = i as u32;
+            let [r, g, b, a] = [rgba[0], rgba[1], rgba[2], rgba[3]].map(u32::from);
+            let lit = (r + g + b) * a > 3 * 255 * 255 / 2;
+            expression.set_pixel(i % image.width(), i / image.width(), lit);
+        }
+        Ok(expression)
+    }
+
+    /// Returns the top left `width` by `height` pixels as white on black.
+    pub fn to_image_data(&self, width: u32, height: u32) -> Result<ImageData, BlinkyBotError> {
+        let data: Vec<u8> = self
+            .to_bytes(width, height)
+            .into_iter()
+            .flat_map(|lit| {
+                let level = if lit != 0 { 0xff } else { 0 };
+                [level, level, level, 0xff]
+            })
+            .collect();
+        ImageData::new_with_u8_clamped_array_and_sh(Clamped(data.as_slice()), width, height)
+            .map_err(|e| BlinkyBotError::invalid(format!("{e:?}")))
+    }
 }
 
+/// A stored expression, for listing them.
+#[wasm_bindgen(getter_with_clone)]
+pub struct ExpressionSlot {
+    pub index: ExpressionIndex,
+    /// Name for URLs and files, e.g. `friend_blink`.
+    pub key: String,
+    /// Name to show, e.g. `Friend blink`.
+    pub name: String,
+}
+
+/// Returns every stored expression, in `ExpressionIndex` order.
 #[wasm_bindgen]
-impl BlinkyBotClient {
+pub fn expression_slots() -> Vec<ExpressionSlot> {
+    ExpressionIndex::ALL
+        .iter()
+        .map(|&index| {
+            let name = match index {
+                ExpressionIndex::Default => "Default",
+                ExpressionIndex::Blink => "Blink",
+                ExpressionIndex::Friend => "Friend",
+                ExpressionIndex::FriendBlink => "Friend blink",
+                ExpressionIndex::Sleep => "Sleep",
+            };
+            ExpressionSlot {
+                index,
+                key: name.to_lowercase().replace(' ', "_"),
+                name: name.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// A built-in expression, from `list_presets()`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct PresetEntry {
+    pub preset: Preset,
+    /// Name used by the CLI, e.g. `heart-eyes`.
+    pub key: String,
+    /// Name to show, e.g. `Heart eyes`.
+    pub name: String,
+}
+
+impl From<Preset> for PresetEntry {
+    fn from(preset: Preset) -> Self {
+        let key = preset.name();
+        let mut name = key.replace('-', " ");
+        name[..1].make_ascii_uppercase();
+        Self {
+            preset,
+            key: key.to_string(),
+            name,
+        }
+    }
+}
+
+/// Returns what `preset` looks like centred on a `width` by `height`
+/// display, as the bot applies it, to show it without asking the bot.
+#[wasm_bindgen]
+pub fn preset_expression(preset: Preset, width: u8, height: u8) -> Expression {
+    Expression {
+        inner: preset.expression_for(DisplayInfo { width, height }),
+    }
+}
+
+/// How a friend is recognised.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct FriendId {
+    inner: blinkybot_rpc::FriendId,
+}
+
+#[wasm_bindgen]
+impl FriendId {
+    /// The bot with USB serial number `serial`.
+    pub fn from_serial(serial: &str) -> Result<FriendId, BlinkyBotError> {
+        let serial = u64::from_str_radix(serial, 16)
+            .map_err(|_| BlinkyBotError::invalid(format!("invalid serial number {serial}")))?;
+        Ok(Self {
+            inner: blinkybot_rpc::FriendId::Serial(serial),
+        })
+    }
+
+    /// Anything reading from `min` to `max` on the sense input.
+    pub fn from_adc_band(min: u16, max: u16) -> FriendId {
+        Self {
+            inner: blinkybot_rpc::FriendId::AdcBand { min, max },
+        }
+    }
+
+    /// USB serial number, `undefined` for ADC bands.
+    #[wasm_bindgen(getter)]
+    pub fn serial(&self) -> Option<String> {
+        match self.inner {
+            blinkybot_rpc::FriendId::Serial(serial) => {
+                Some(String::from_utf8_lossy(&serial_number(serial)).into_owned())
+            }
+            blinkybot_rpc::FriendId::AdcBand { .. } => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn adc_min(&self) -> Option<u16> {
+        match self.inner {
+            blinkybot_rpc::FriendId::AdcBand { min, .. } => Some(min),
+            blinkybot_rpc::FriendId::Serial(_) => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn adc_max(&self) -> Option<u16> {
+        match self.inner {
+            blinkybot_rpc::FriendId::AdcBand { max, .. } => Some(max),
+            blinkybot_rpc::FriendId::Serial(_) => None,
+        }
+    }
+}
+
+/// Entry in the friend table.
+#[wasm_bindgen]
+pub struct Friend {
+    inner: blinkybot_rpc::Friend,
+}
+
+#[wasm_bindgen]
+impl Friend {
+    #[wasm_bindgen(constructor)]
+    pub fn new(id: &FriendId, expression: &Expression, blink_expression: &Expression) -> Self {
+        Self {
+            inner: blinkybot_rpc::Friend {
+                id: id.inner,
+                expression: expression.inner.clone(),
+                blink_expression: blink_expression.inner.clone(),
+            },
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> FriendId {
+        FriendId {
+            inner: self.inner.id,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn expression(&self) -> Expression {
+        Expression {
+            inner: self.inner.expression.clone(),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn blink_expression(&self) -> Expression {
+        Expression {
+            inner: self.inner.blink_expression.clone(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct Schedule {
+    inner: blinkybot_rpc::Schedule,
+}
+
+#[wasm_bindgen]
+impl Schedule {
     #[wasm_bindgen(constructor)]
-    pub async fn new() -> Result<Self, String> {
-        let client = HostClient::try_new_webusb(
-            /* vendor */ 0xf569, /* interface */ 1, /* max_transfer_len */ 64,
-            /* ep_in */ 1, /* ep_out */ 1, /* err_uri_path */ ERROR_PATH,
-            /*outgoing_depth */ 64,
-        )
+    pub fn new() -> Self {
+        Self {
+            inner: blinkybot_rpc::Schedule::default(),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<ScheduleEntry> {
+        self.inner.entries.iter().flatten().copied().collect()
+    }
+
+    pub fn set_entries(&mut self, entries: Vec<ScheduleEntry>) -> Result<(), BlinkyBotError> {
+        if entries.len() > MAX_SCHEDULE_ENTRIES {
+            return Err(BlinkyBotError::invalid(format!(
+                "schedule has {} entries, the maximum is {MAX_SCHEDULE_ENTRIES}",
+                entries.len()
+            )));
+        }
+        self.inner = blinkybot_rpc::Schedule::default();
+        for (slot, entry) in self.inner.entries.iter_mut().zip(entries) {
+            *slot = Some(entry);
+        }
+        Ok(())
+    }
+}
+
+/// Button gestures as they happen.
+#[wasm_bindgen]
+pub struct ButtonEvents {
+    inner: RecordingSubscription<blinkybot_rpc::ButtonEvent>,
+}
+
+#[wasm_bindgen]
+impl ButtonEvents {
+    /// Waits for the next event.  Returns `undefined` once the bot is
+    /// disconnected.
+    pub async fn next(&mut self) -> Option<ButtonEvent> {
+        self.inner.recv().await
+    }
+}
+
+/// Kinds of `BehaviorEvent`.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum BehaviorEventKind {
+    /// The bot blinked by itself.
+    Blink,
+    /// A stored expression started showing, see `expression`.
+    Expression,
+    /// See `face_mode`.
+    FaceMode,
+    /// See `mood`.
+    Mood,
+    /// Started or stopped seeing a friend, see `active`.
+    Friend,
+    /// The display's brightness changed, see `brightness`.
+    Brightness,
+    /// Fell asleep or woke up, see `active`.
+    Sleep,
+}
+
+/// Something the bot did.  Only the getters for its `kind` are defined.
+#[wasm_bindgen]
+pub struct BehaviorEvent {
+    inner: blinkybot_rpc::BehaviorEvent,
+}
+
+#[wasm_bindgen]
+impl BehaviorEvent {
+    /// Milliseconds since the bot started.
+    #[wasm_bindgen(getter)]
+    pub fn uptime_ms(&self) -> f64 {
+        self.inner.uptime_ms as f64
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> BehaviorEventKind {
+        match self.inner.kind {
+            blinkybot_rpc::BehaviorEventKind::Blink => BehaviorEventKind::Blink,
+            blinkybot_rpc::BehaviorEventKind::Expression(_) => BehaviorEventKind::Expression,
+            blinkybot_rpc::BehaviorEventKind::FaceMode(_) => BehaviorEventKind::FaceMode,
+            blinkybot_rpc::BehaviorEventKind::Mood(_) => BehaviorEventKind::Mood,
+            blinkybot_rpc::BehaviorEventKind::Friend(_) => BehaviorEventKind::Friend,
+            blinkybot_rpc::BehaviorEventKind::Brightness(_) => BehaviorEventKind::Brightness,
+            blinkybot_rpc::BehaviorEventKind::Sleep(_) => BehaviorEventKind::Sleep,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn expression(&self) -> Option<ExpressionIndex> {
+        match self.inner.kind {
+            blinkybot_rpc::BehaviorEventKind::Expression(index) => Some(index),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn face_mode(&self) -> Option<FaceMode> {
+        match self.inner.kind {
+            blinkybot_rpc::BehaviorEventKind::FaceMode(mode) => Some(mode),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn mood(&self) -> Option<Mood> {
+        match self.inner.kind {
+            blinkybot_rpc::BehaviorEventKind::Mood(mood) => Some(mood),
+            _ => None,
+        }
+    }
+
+    /// Whether a friend is seen or the bot is asleep.
+    #[wasm_bindgen(getter)]
+    pub fn active(&self) -> Option<bool> {
+        match self.inner.kind {
+            blinkybot_rpc::BehaviorEventKind::Friend(active)
+            | blinkybot_rpc::BehaviorEventKind::Sleep(active) => Some(active),
+            _ => None,
+        }
+    }
+
+    /// Perceptual brightness from 0 to 100, 0 when the display is off.
+    #[wasm_bindgen(getter)]
+    pub fn brightness(&self) -> Option<u8> {
+        match self.inner.kind {
+            blinkybot_rpc::BehaviorEventKind::Brightness(level) => Some(level),
+            _ => None,
+        }
+    }
+}
+
+/// Behavior events as they happen.
+#[wasm_bindgen]
+pub struct BehaviorEvents {
+    inner: RecordingSubscription<blinkybot_rpc::BehaviorEvent>,
+}
+
+#[wasm_bindgen]
+impl BehaviorEvents {
+    /// Waits for the next event.  Returns `undefined` once the bot is
+    /// disconnected.
+    pub async fn next(&mut self) -> Option<BehaviorEvent> {
+        let inner = self.inner.recv().await?;
+        Some(BehaviorEvent { inner })
+    }
+}
+
+/// Settings named in a `ConfigChange`.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum ConfigSetting {
+    DeviceName,
+    Expression,
+    Brightness,
+    BrightnessCurve,
+    BrightnessMode,
+    AutoBrightness,
+    Schedule,
+    FaceMode,
+    Gaze,
+    ForcedMood,
+    MoodStyle,
+    Transition,
+    ButtonBindings,
+    FriendAdded,
+    FriendRemoved,
+    BlinkSync,
+    Script,
+    ClockConfig,
+    DisplayOff,
+    ClockToggled,
+    FaceModeCycled,
+}
+
+/// A setting changed by some client.  Getters that don't apply to the
+/// `setting` return `undefined`; re-read other values from the bot.
+#[wasm_bindgen]
+pub struct ConfigChange {
+    inner: blinkybot_rpc::ConfigChange,
+}
+
+#[wasm_bindgen]
+impl ConfigChange {
+    #[wasm_bindgen(getter)]
+    pub fn setting(&self) -> ConfigSetting {
+        use blinkybot_rpc::ConfigChange as Change;
+        match self.inner {
+            Change::DeviceName(_) => ConfigSetting::DeviceName,
+            Change::Expression(_) => ConfigSetting::Expression,
+            Change::Brightness(_) => ConfigSetting::Brightness,
+            Change::BrightnessCurve(_) => ConfigSetting::BrightnessCurve,
+            Change::BrightnessMode(_) => ConfigSetting::BrightnessMode,
+            Change::AutoBrightness(_) => ConfigSetting::AutoBrightness,
+            Change::Schedule(_) => ConfigSetting::Schedule,
+            Change::FaceMode(_) => ConfigSetting::FaceMode,
+            Change::Gaze(_) => ConfigSetting::Gaze,
+            Change::ForcedMood(_) => ConfigSetting::ForcedMood,
+            Change::MoodStyle(_) => ConfigSetting::MoodStyle,
+            Change::Transition(_) => ConfigSetting::Transition,
+            Change::ButtonBindings(_) => ConfigSetting::ButtonBindings,
+            Change::FriendAdded(_) => ConfigSetting::FriendAdded,
+            Change::FriendRemoved(_) => ConfigSetting::FriendRemoved,
+            Change::BlinkSync(_) => ConfigSetting::BlinkSync,
+            Change::Script(_) => ConfigSetting::Script,
+            Change::ClockConfig(_) => ConfigSetting::ClockConfig,
+            Change::DisplayOff(_) => ConfigSetting::DisplayOff,
+            Change::ClockToggled(_) => ConfigSetting::ClockToggled,
+            Change::FaceModeCycled(_) => ConfigSetting::FaceModeCycled,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn device_name(&self) -> Option<String> {
+        match &self.inner {
+            blinkybot_rpc::ConfigChange::DeviceName(name) => Some(name.as_str().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Which expression changed.
+    #[wasm_bindgen(getter)]
+    pub fn expression_index(&self) -> Option<ExpressionIndex> {
+        match &self.inner {
+            blinkybot_rpc::ConfigChange::Expression(set) => Some(set.index),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn brightness(&self) -> Option<u8> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::Brightness(brightness) => Some(brightness),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn brightness_curve(&self) -> Option<BrightnessCurve> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::BrightnessCurve(curve) => Some(curve),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn brightness_mode(&self) -> Option<BrightnessMode> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::BrightnessMode(mode) => Some(mode),
+            _ => None,
+        }
+    }
+
+    /// The stored face mode, or the one switched to with a button.
+    #[wasm_bindgen(getter)]
+    pub fn face_mode(&self) -> Option<FaceMode> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::FaceMode(mode)
+            | blinkybot_rpc::ConfigChange::FaceModeCycled(mode) => Some(mode),
+            _ => None,
+        }
+    }
+
+    /// The mood whose style changed, or the forced mood, `undefined` when
+    /// the bot was left to pick its own.
+    #[wasm_bindgen(getter)]
+    pub fn mood(&self) -> Option<Mood> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::MoodStyle(set) => Some(set.mood),
+            blinkybot_rpc::ConfigChange::ForcedMood(mood) => mood,
+            _ => None,
+        }
+    }
+
+    /// Which transition changed.
+    #[wasm_bindgen(getter)]
+    pub fn transition_kind(&self) -> Option<TransitionKind> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::Transition(set) => Some(set.kind),
+            _ => None,
+        }
+    }
+
+    /// Which button's bindings changed.
+    #[wasm_bindgen(getter)]
+    pub fn button(&self) -> Option<u8> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::ButtonBindings(set) => Some(set.button),
+            _ => None,
+        }
+    }
+
+    /// Whether the display was turned off, or the clock toggled on, with a
+    /// button.
+    #[wasm_bindgen(getter)]
+    pub fn toggled(&self) -> Option<bool> {
+        match self.inner {
+            blinkybot_rpc::ConfigChange::DisplayOff(off) => Some(off),
+            blinkybot_rpc::ConfigChange::ClockToggled(toggled) => Some(toggled),
+            _ => None,
+        }
+    }
+}
+
+/// Settings changes as they happen.
+#[wasm_bindgen]
+pub struct ConfigChanges {
+    inner: RecordingSubscription<blinkybot_rpc::ConfigChange>,
+}
+
+#[wasm_bindgen]
+impl ConfigChanges {
+    /// Waits for the next change.  Returns `undefined` once the bot is
+    /// disconnected.
+    pub async fn next(&mut self) -> Option<ConfigChange> {
+        let inner = self.inner.recv().await?;
+        Some(ConfigChange { inner })
+    }
+}
+
+/// Returns the current time of the host.
+fn host_time() -> WallTime {
+    let date = js_sys::Date::new_0();
+    // `getTimezoneOffset()` is UTC minus local time.
+    WallTime::new(date.get_time() as u64, -date.get_timezone_offset() as i16)
+}
+
+/// Opens `device` and gets it ready for requests.
+async fn open(device: UsbDevice) -> Result<HostClient<WireError>, BlinkyBotError> {
+    let wire = WebUsbWire::open(device)
         .await
-        .map_err(|e| format!("{e}"))?;
+        .map_err(|e| BlinkyBotError::new(ErrorKind::Connect, e.to_string()))?;
+    let client = HostClient::new_with_wire(
+        wire.clone(),
+        wire,
+        WebUsbSpawn,
+        ERROR_PATH,
+        /* outgoing_depth */ 64,
+    );
+
+    // The bot has no clock of its own, so share ours on every connect.
+    client.send_resp::<SetTimeEndpoint>(&host_time()).await?;
+    Ok(client)
+}
+
+/// Returns the USB serial number `selector` is, in the bot's upper case, or
+/// `None` if it is a name.
+fn selector_serial(selector: &str) -> Option<String> {
+    if selector.len() != SERIAL_NUMBER_LEN {
+        return None;
+    }
+    let serial = u64::from_str_radix(selector, 16).ok()?;
+    Some(String::from_utf8_lossy(&serial_number(serial)).into_owned())
+}
+
+impl BlinkyBotClient {
+    async fn connect(device: UsbDevice, selector: Option<&str>) -> Result<Self, BlinkyBotError> {
+        let serial = device.serial_number();
+        let client = open(device).await?;
+
+        if let Some(selector) = selector {
+            let info: DeviceInfo = client.send_resp::<GetDeviceInfoEndpoint>(&()).await?.into();
+            if !info.matches(selector) {
+                client.close();
+                return Err(BlinkyBotError::new(
+                    ErrorKind::Connect,
+                    format!(
+                        "picked bot {} ({}) is not {selector}",
+                        info.serial, info.name
+                    ),
+                ));
+            }
+        }
+
+        let connection = Rc::new(Connection {
+            client: RefCell::new(Rc::new(RecordingClient::new(client))),
+            serial,
+            state: Cell::new(ConnectionState::Connected),
+            listener: RefCell::new(None),
+        });
+        // The listeners hold weak references so that they don't keep the
+        // connection alive after the client is freed.
+        let on_connect = {
+            let connection = Rc::downgrade(&connection);
+            Closure::new(move |event: UsbConnectionEvent| {
+                if let Some(connection) = connection.upgrade() {
+                    if connection.is_bot(&event.device()) {
+                        spawn_local(connection.reconnect(event.device()));
+                    }
+                }
+            })
+        };
+        let on_disconnect = {
+            let connection = Rc::downgrade(&connection);
+            Closure::new(move |event: UsbConnectionEvent| {
+                if let Some(connection) = connection.upgrade() {
+                    if connection.is_bot(&event.device()) {
+                        connection.disconnected();
+                    }
+                }
+            })
+        };
+        let client = Self {
+            connection,
+            on_connect,
+            on_disconnect,
+        };
+        let usb =
+            webusb::usb().map_err(|e| BlinkyBotError::new(ErrorKind::Connect, e.to_string()))?;
+        usb.add_event_listener_with_callback("connect", client.on_connect.as_ref().unchecked_ref())
+            .and_then(|()| {
+                usb.add_event_listener_with_callback(
+                    "disconnect",
+                    client.on_disconnect.as_ref().unchecked_ref(),
+                )
+            })
+            .map_err(|e| BlinkyBotError::new(ErrorKind::Connect, format!("{e:?}")))?;
+        Ok(client)
+    }
+
+    fn client(&self) -> Rc<RecordingClient> {
+        self.connection.client()
+    }
+
+    fn remove_listeners(&self) {
+        if let Ok(usb) = webusb::usb() {
+            let _ = usb.remove_event_listener_with_callback(
+                "connect",
+                self.on_connect.as_ref().unchecked_ref(),
+            );
+            let _ = usb.remove_event_listener_with_callback(
+                "disconnect",
+                self.on_disconnect.as_ref().unchecked_ref(),
+            );
+        }
+    }
+}
+
+impl Drop for BlinkyBotClient {
+    fn drop(&mut self) {
+        self.remove_listeners();
+    }
+}
+
+#[wasm_bindgen]
+impl BlinkyBotClient {
+    /// Connects to the bot picked by the user.  If `selector` is given, the
+    /// connection fails unless it is the serial number or name of that bot,
+    /// and a serial number limits the choice to that bot.
+    #[wasm_bindgen(constructor)]
+    pub async fn new(selector: Option<String>) -> Result<BlinkyBotClient, BlinkyBotError> {
+        let serial = selector.as_deref().and_then(selector_serial);
+        let device = webusb::request_device(serial.as_deref())
+            .await
+            .map_err(|e| BlinkyBotError::new(ErrorKind::Connect, e.to_string()))?;
+        Self::connect(device, selector.as_deref()).await
+    }
+
+    /// Connects to a bot the user picked before without asking again, or
+    /// returns `undefined` if none is plugged in.  If `selector` is given,
+    /// only that bot is considered.
+    pub async fn authorized(
+        selector: Option<String>,
+    ) -> Result<Option<BlinkyBotClient>, BlinkyBotError> {
+        let devices = webusb::authorized_devices()
+            .await
+            .map_err(|e| BlinkyBotError::new(ErrorKind::Connect, e.to_string()))?;
+        for device in devices {
+            match Self::connect(device, selector.as_deref()).await {
+                Ok(client) => return Ok(Some(client)),
+                Err(e) if e.kind == ErrorKind::Connect => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
 
-        Ok(Self { client })
+    /// Calls `listener` with the new `ConnectionState` whenever it changes.
+    /// Subscriptions end when the bot is disconnected, so subscribe again
+    /// once it is `Connected`.
+    pub fn set_connection_listener(&self, listener: Option<js_sys::Function>) {
+        *self.connection.listener.borrow_mut() = listener;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn state(&self) -> ConnectionState {
+        self.connection.state.get()
     }
 
     pub fn close(&self) {
-        self.client.close();
+        self.remove_listeners();
+        self.client().close();
+        self.connection.set_state(ConnectionState::Closed);
+    }
+
+    /// Starts recording requests and responses, including failed ones, and
+    /// the events received on subscriptions, for replaying with the CLI's
+    /// `replay` command.
+    pub fn start_recording(&self) {
+        self.client().start_recording();
+    }
+
+    /// Stops recording and returns the session as text, or `undefined` if
+    /// not recording.
+    pub fn stop_recording(&self) -> Option<String> {
+        self.client()
+            .stop_recording()
+            .map(|session| session.to_text())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.client().is_recording()
     }
 
     pub async fn wait_closed(&self) {
-        self.client.wait_closed().await;
+        self.client().wait_closed().await;
     }
 
-    pub async fn ping(&self, id: u32) -> Result<u32, Error<Infallible>> {
-        let val = self.client.send_resp::<PingEndpoint>(&id).await?;
+    pub async fn ping(&self, id: u32) -> Result<u32, BlinkyBotError> {
+        let val = self.client().send_resp::<PingEndpoint>(&id).await?;
         Ok(val)
     }
 
+    pub async fn get_device_info(&self) -> Result<DeviceInfo, BlinkyBotError> {
+        let info = self
+            .client()
+            .send_resp::<GetDeviceInfoEndpoint>(&())
+            .await?;
+        Ok(info.into())
+    }
+
+    pub async fn set_device_name(&self, name: String) -> Result<(), BlinkyBotError> {
+        let name = DeviceName::new(&name).ok_or_else(|| {
+            BlinkyBotError::invalid(format!(
+                "name is {} bytes, the maximum is {}",
+                name.len(),
+                blinkybot_rpc::MAX_NAME_LEN
+            ))
+        })?;
+        self.client()
+            .send_resp::<SetDeviceNameEndpoint>(&name)
+            .await?;
+        Ok(())
+    }
+
     pub async fn set_expression(
         &self,
         index: ExpressionIndex,
         expression: Expression,
-    ) -> Result<(), Error<Infallible>> {
-        self.client
+    ) -> Result<(), BlinkyBotError> {
+        self.client()
             .send_resp::<SetExpressionEndpoint>(&SetExpression {
                 index,
                 expression: expression.inner,
@@ -106,31 +1023,372 @@ impl BlinkyBotClient {
     pub async fn get_expression(
         &self,
         index: ExpressionIndex,
-    ) -> Result<Expression, Error<Infallible>> {
+    ) -> Result<Expression, BlinkyBotError> {
         let expression = self
-            .client
+            .client()
             .send_resp::<GetExpressionEndpoint>(&index)
             .await?;
         Ok(Expression { inner: expression })
     }
 
-    pub async fn get_adc(&self) -> Result<u16, Error<Infallible>> {
-        let val = self.client.send_resp::<GetAdcEndpoint>(&()).await?;
+    pub async fn get_adc(&self) -> Result<u16, BlinkyBotError> {
+        let val = self.client().send_resp::<GetAdcEndpoint>(&()).await?;
         Ok(val)
     }
 
-    pub async fn get_brightness(&self) -> Result<u8, Error<Infallible>> {
-        let val = self.client.send_resp::<GetBrightnessEndpoint>(&()).await?;
+    pub async fn get_brightness(&self) -> Result<u8, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetBrightnessEndpoint>(&())
+            .await?;
         Ok(val)
     }
 
-    pub async fn set_brightness(&self, value: u8) -> Result<(), Error<Infallible>> {
-        self.client
+    pub async fn set_brightness(&self, value: u8) -> Result<(), BlinkyBotError> {
+        self.client()
             .send_resp::<SetBrightnessEndpoint>(&value)
             .await?;
         Ok(())
     }
+
+    pub async fn get_brightness_curve(&self) -> Result<BrightnessCurve, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetBrightnessCurveEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn set_brightness_curve(&self, curve: BrightnessCurve) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetBrightnessCurveEndpoint>(&curve)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_brightness_mode(&self) -> Result<BrightnessMode, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetBrightnessModeEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn set_brightness_mode(&self, mode: BrightnessMode) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetBrightnessModeEndpoint>(&mode)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_auto_brightness(&self) -> Result<AutoBrightness, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetAutoBrightnessEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn set_auto_brightness(&self, config: AutoBrightness) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetAutoBrightnessEndpoint>(&config)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_ambient_light(&self) -> Result<u16, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetAmbientLightEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn get_display_info(&self) -> Result<DisplayInfo, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetDisplayInfoEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn get_display_stats(&self) -> Result<DisplayStats, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetDisplayStatsEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn get_display_health(&self) -> Result<DisplayHealth, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetDisplayHealthEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    /// Sets the bot's clock to the host's time, as done on every connect.
+    pub async fn set_time(&self) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetTimeEndpoint>(&host_time())
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the bot's time, or `undefined` if it has not been set.
+    pub async fn get_time(&self) -> Result<Option<WallTime>, BlinkyBotError> {
+        let val = self.client().send_resp::<GetTimeEndpoint>(&()).await?;
+        Ok(val)
+    }
+
+    pub async fn get_schedule(&self) -> Result<Schedule, BlinkyBotError> {
+        let schedule = self.client().send_resp::<GetScheduleEndpoint>(&()).await?;
+        Ok(Schedule { inner: schedule })
+    }
+
+    pub async fn set_schedule(&self, schedule: Schedule) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetScheduleEndpoint>(&schedule.inner)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_face_mode(&self) -> Result<FaceMode, BlinkyBotError> {
+        let val = self.client().send_resp::<GetFaceModeEndpoint>(&()).await?;
+        Ok(val)
+    }
+
+    pub async fn set_face_mode(&self, mode: FaceMode) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetFaceModeEndpoint>(&mode)
+            .await?;
+        Ok(())
+    }
+
+    /// Points the procedural face's eyes at `gaze`, or lets the bot look
+    /// around by itself if `undefined`.
+    pub async fn set_gaze(&self, gaze: Option<Gaze>) -> Result<(), BlinkyBotError> {
+        self.client().send_resp::<SetGazeEndpoint>(&gaze).await?;
+        Ok(())
+    }
+
+    pub async fn get_mood(&self) -> Result<Mood, BlinkyBotError> {
+        let val = self.client().send_resp::<GetMoodEndpoint>(&()).await?;
+        Ok(val)
+    }
+
+    /// Holds the bot in `mood`, or lets it decide for itself again if
+    /// `undefined`.
+    pub async fn set_mood(&self, mood: Option<Mood>) -> Result<(), BlinkyBotError> {
+        self.client().send_resp::<SetMoodEndpoint>(&mood).await?;
+        Ok(())
+    }
+
+    pub async fn get_mood_style(&self, mood: Mood) -> Result<MoodStyle, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetMoodStyleEndpoint>(&mood)
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn set_mood_style(&self, mood: Mood, style: MoodStyle) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetMoodStyleEndpoint>(&SetMoodStyle { mood, style })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_transition(
+        &self,
+        kind: TransitionKind,
+    ) -> Result<TransitionStyle, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetTransitionEndpoint>(&kind)
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn set_transition(
+        &self,
+        kind: TransitionKind,
+        style: TransitionStyle,
+    ) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetTransitionEndpoint>(&SetTransition { kind, style })
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the bytecode of the script run in `FaceMode::Script`.
+    pub async fn get_script(&self) -> Result<Vec<u8>, BlinkyBotError> {
+        let script = self.client().send_resp::<GetScriptEndpoint>(&()).await?;
+        Ok(script.code().to_vec())
+    }
+
+    /// Uploads script bytecode, failing if the bot rejects it.
+    pub async fn set_script(&self, code: Vec<u8>) -> Result<(), BlinkyBotError> {
+        let script = Script::new(&code).ok_or_else(|| {
+            BlinkyBotError::invalid(format!(
+                "script is {} bytes, the maximum is {}",
+                code.len(),
+                blinkybot_rpc::MAX_SCRIPT_LEN
+            ))
+        })?;
+        self.client()
+            .send_resp::<SetScriptEndpoint>(&script)
+            .await?
+            .map_err(|e| {
+                BlinkyBotError::new(
+                    ErrorKind::Rejected,
+                    format!("invalid script: {:?} at offset {}", e.kind, e.offset),
+                )
+            })
+    }
+
+    /// Returns the actions of `button`, or `undefined` if the bot doesn't
+    /// have that button.
+    pub async fn get_button_bindings(
+        &self,
+        button: u8,
+    ) -> Result<Option<ButtonBindings>, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetButtonBindingsEndpoint>(&button)
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn set_button_bindings(
+        &self,
+        button: u8,
+        bindings: ButtonBindings,
+    ) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetButtonBindingsEndpoint>(&SetButtonBindings { button, bindings })
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the friend the bot is facing, or `undefined` if there is
+    /// none or it hasn't said hello yet.
+    pub async fn get_peer(&self) -> Result<Option<Peer>, BlinkyBotError> {
+        let val = self.client().send_resp::<GetPeerEndpoint>(&()).await?;
+        Ok(val.map(Peer::from))
+    }
+
+    pub async fn get_blink_sync(&self) -> Result<BlinkSync, BlinkyBotError> {
+        let val = self.client().send_resp::<GetBlinkSyncEndpoint>(&()).await?;
+        Ok(val)
+    }
+
+    pub async fn set_blink_sync(&self, sync: BlinkSync) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetBlinkSyncEndpoint>(&sync)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_clock_config(&self) -> Result<ClockConfig, BlinkyBotError> {
+        let val = self
+            .client()
+            .send_resp::<GetClockConfigEndpoint>(&())
+            .await?;
+        Ok(val)
+    }
+
+    pub async fn set_clock_config(&self, config: ClockConfig) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<SetClockConfigEndpoint>(&config)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the presets the bot has, for `apply_preset()`.
+    pub async fn list_presets(&self) -> Result<Vec<PresetEntry>, BlinkyBotError> {
+        let presets = self.client().send_resp::<ListPresetsEndpoint>(&()).await?;
+        Ok(presets.iter().map(|&preset| preset.into()).collect())
+    }
+
+    /// Sets the expression at `index` to `preset`.
+    pub async fn apply_preset(
+        &self,
+        index: ExpressionIndex,
+        preset: Preset,
+    ) -> Result<(), BlinkyBotError> {
+        self.client()
+            .send_resp::<ApplyPresetEndpoint>(&ApplyPreset { preset, index })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_friends(&self) -> Result<Vec<FriendId>, BlinkyBotError> {
+        let ids = self.client().send_resp::<ListFriendsEndpoint>(&()).await?;
+        Ok(ids
+            .into_iter()
+            .flatten()
+            .map(|inner| FriendId { inner })
+            .collect())
+    }
+
+    pub async fn get_friend(&self, id: &FriendId) -> Result<Option<Friend>, BlinkyBotError> {
+        let friend = self
+            .client()
+            .send_resp::<GetFriendEndpoint>(&id.inner)
+            .await?;
+        Ok(friend.map(|inner| Friend { inner }))
+    }
+
+    /// Adds `friend`, replacing any entry with the same ID.  Returns false if
+    /// the friend table is full.
+    pub async fn add_friend(&self, friend: &Friend) -> Result<bool, BlinkyBotError> {
+        let added = self
+            .client()
+            .send_resp::<AddFriendEndpoint>(&friend.inner)
+            .await?;
+        Ok(added)
+    }
+
+    /// Returns false if there was no such friend.
+    pub async fn remove_friend(&self, id: &FriendId) -> Result<bool, BlinkyBotError> {
+        let removed = self
+            .client()
+            .send_resp::<RemoveFriendEndpoint>(&id.inner)
+            .await?;
+        Ok(removed)
+    }
+
+    pub async fn subscribe_buttons(&self) -> Result<ButtonEvents, BlinkyBotError> {
+        let inner = self
+            .client()
+            .subscribe::<ButtonTopic>(8)
+            .await
+            .map_err(|_| BlinkyBotError::new(ErrorKind::Disconnected, "bot disconnected"))?;
+        Ok(ButtonEvents { inner })
+    }
+
+    pub async fn subscribe_behavior(&self) -> Result<BehaviorEvents, BlinkyBotError> {
+        let inner = self
+            .client()
+            .subscribe::<BehaviorTopic>(16)
+            .await
+            .map_err(|_| BlinkyBotError::new(ErrorKind::Disconnected, "bot disconnected"))?;
+        Ok(BehaviorEvents { inner })
+    }
+
+    /// Follows settings changed by any client, including this one.
+    pub async fn subscribe_config(&self) -> Result<ConfigChanges, BlinkyBotError> {
+        let inner = self
+            .client()
+            .subscribe::<ConfigChangedTopic>(8)
+            .await
+            .map_err(|_| BlinkyBotError::new(ErrorKind::Disconnected, "bot disconnected"))?;
+        Ok(ConfigChanges { inner })
+    }
 }
+
 #[wasm_bindgen]
 extern "C" {
     fn alert(s: &str);
@@ -0,0 +1,130 @@
+//! Expression packs, exported from and imported into the bot as bytes for
+//! files, or as text for share links.
+
+use blinkybot_core::pack::{
+    self, Manifest, Pack, PackError, Requests, TransferError, MAX_PACK_LEN, MAX_TEXT_LEN,
+};
+use blinkybot_rpc::{DeviceName, MAX_NAME_LEN};
+use postcard::experimental::schema::Schema;
+use postcard_rpc::{host_client::HostErr, standard_icd::WireError, Endpoint};
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::{BlinkyBotClient, BlinkyBotError, ErrorKind};
+
+fn pack_error(e: PackError) -> BlinkyBotError {
+    BlinkyBotError::invalid(format!("bad pack: {:?}", e))
+}
+
+fn decode(bytes: &[u8]) -> Result<Pack, BlinkyBotError> {
+    pack::decode(bytes).map_err(pack_error)
+}
+
+/// What a pack contains, from `pack_info()`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct PackInfo {
+    pub name: String,
+    pub author: String,
+    /// Number of expressions in the pack.
+    pub expressions: u32,
+    pub has_script: bool,
+    pub has_behavior: bool,
+    pub has_brightness: bool,
+}
+
+/// Describes the pack `bytes`, failing if they aren't one.
+#[wasm_bindgen]
+pub fn pack_info(bytes: &[u8]) -> Result<PackInfo, BlinkyBotError> {
+    let pack = decode(bytes)?;
+    Ok(PackInfo {
+        name: pack.manifest.name.as_str().to_string(),
+        author: pack.manifest.author.as_str().to_string(),
+        expressions: pack.expressions.iter().flatten().count() as u32,
+        has_script: pack.script.is_some(),
+        has_behavior: pack.behavior.is_some(),
+        has_brightness: pack.brightness.is_some(),
+    })
+}
+
+/// Returns the pack `bytes` as text for a share link.
+#[wasm_bindgen]
+pub fn pack_to_text(bytes: &[u8]) -> Result<String, BlinkyBotError> {
+    let mut buffer = [0; MAX_TEXT_LEN];
+    let text = pack::encode_text(bytes, &mut buffer).map_err(pack_error)?;
+    Ok(text.to_string())
+}
+
+/// Returns the pack in share text, or in a whole share link as anything up
+/// to the last `pack=` is skipped.
+#[wasm_bindgen]
+pub fn pack_from_text(text: &str) -> Result<Vec<u8>, BlinkyBotError> {
+    let text = text.rsplit("pack=").next().unwrap_or(text);
+    let mut buffer = [0; MAX_PACK_LEN];
+    let bytes = pack::decode_text(text, &mut buffer).map_err(pack_error)?;
+    decode(bytes)?;
+    Ok(bytes.to_vec())
+}
+
+impl Requests for BlinkyBotClient {
+    type Error = HostErr<WireError>;
+
+    async fn send<E: Endpoint>(&self, request: &E::Request) -> Result<E::Response, Self::Error>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: Serialize + DeserializeOwned + Schema,
+    {
+        self.client().send_resp::<E>(request).await
+    }
+}
+
+fn transfer_error(e: TransferError<HostErr<WireError>>) -> BlinkyBotError {
+    match e {
+        TransferError::Request { error, .. } => error.into(),
+        TransferError::ScriptRejected(e) => BlinkyBotError::new(
+            ErrorKind::Rejected,
+            format!("invalid script: {:?} at offset {}", e.kind, e.offset),
+        ),
+    }
+}
+
+fn manifest(name: &str, author: &str) -> Result<Manifest, BlinkyBotError> {
+    let text = |text: &str| {
+        DeviceName::new(text).ok_or_else(|| {
+            BlinkyBotError::invalid(format!(
+                "{} is {} bytes, the maximum is {}",
+                text,
+                text.len(),
+                MAX_NAME_LEN
+            ))
+        })
+    };
+    Ok(Manifest {
+        name: text(name)?,
+        author: text(author)?,
+    })
+}
+
+#[wasm_bindgen]
+impl BlinkyBotClient {
+    /// Returns a pack called `name` of the bot's expressions, script,
+    /// behavior and brightness.
+    pub async fn export_pack(
+        &self,
+        name: String,
+        author: String,
+    ) -> Result<Vec<u8>, BlinkyBotError> {
+        let manifest = manifest(&name, &author)?;
+        let pack = pack::read(self, manifest).await.map_err(transfer_error)?;
+
+        let mut buffer = [0; MAX_PACK_LEN];
+        let bytes = pack::encode(&pack, &mut buffer).map_err(pack_error)?;
+        Ok(bytes.to_vec())
+    }
+
+    /// Sets everything in the pack `bytes` on the bot.  Parts the pack
+    /// leaves out are kept as they are.
+    pub async fn import_pack(&self, bytes: Vec<u8>) -> Result<(), BlinkyBotError> {
+        let pack = decode(&bytes)?;
+        pack::apply(self, &pack).await.map_err(transfer_error)
+    }
+}
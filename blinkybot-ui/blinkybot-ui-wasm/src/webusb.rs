@@ -0,0 +1,144 @@
+//! WebUSB wire for postcard-rpc.  Unlike `HostClient::try_new_webusb()`,
+//! which always asks the user to pick a bot, this opens any `UsbDevice`, so
+//! a bot the user already picked can be opened again when it comes back.
+
+use std::fmt;
+use std::future::Future;
+
+use blinkybot_rpc::{USB_PRODUCT_ID, USB_VENDOR_ID};
+use js_sys::{Array, Promise, Uint8Array};
+use postcard_rpc::host_client::{WireRx, WireSpawn, WireTx};
+use send_wrapper::SendWrapper;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+    Usb, UsbDevice, UsbDeviceFilter, UsbDeviceRequestOptions, UsbInTransferResult,
+    UsbTransferStatus,
+};
+
+const INTERFACE: u8 = 1;
+const ENDPOINT: u8 = 1;
+const MAX_TRANSFER_LEN: u32 = 64;
+
+/// A failed WebUSB call, with the browser's error.
+#[derive(Debug)]
+pub struct UsbError(String);
+
+impl fmt::Display for UsbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsbError {}
+
+impl From<JsValue> for UsbError {
+    fn from(e: JsValue) -> Self {
+        Self(format!("{e:?}"))
+    }
+}
+
+pub fn usb() -> Result<Usb, UsbError> {
+    let window = web_sys::window().ok_or_else(|| UsbError("no window".to_string()))?;
+    Ok(window.navigator().usb())
+}
+
+/// Waits for `promise`.  postcard-rpc wants its wire futures to be `Send`,
+/// which JS futures are not, but the browser only runs them on one thread.
+fn wait(promise: Promise) -> impl Future<Output = Result<JsValue, UsbError>> + Send {
+    let future = SendWrapper::new(JsFuture::from(promise));
+    async move { future.await.map_err(UsbError::from) }
+}
+
+/// Asks the user to pick a bot, only offering the one with USB serial number
+/// `serial` if given.
+pub async fn request_device(serial: Option<&str>) -> Result<UsbDevice, UsbError> {
+    let filter = UsbDeviceFilter::new();
+    filter.set_vendor_id(USB_VENDOR_ID);
+    filter.set_product_id(USB_PRODUCT_ID);
+    if let Some(serial) = serial {
+        filter.set_serial_number(serial);
+    }
+    let options = UsbDeviceRequestOptions::new(&Array::of1(&filter));
+    Ok(wait(usb()?.request_device(&options))
+        .await?
+        .unchecked_into())
+}
+
+/// Returns the bots the user picked before, on this page or an earlier
+/// visit, that are plugged in.
+pub async fn authorized_devices() -> Result<Vec<UsbDevice>, UsbError> {
+    let devices: Array = wait(usb()?.get_devices()).await?.unchecked_into();
+    Ok(devices
+        .iter()
+        .map(JsCast::unchecked_into::<UsbDevice>)
+        .filter(|device| device.vendor_id() == USB_VENDOR_ID)
+        .collect())
+}
+
+/// An opened bot.
+#[derive(Clone)]
+pub struct WebUsbWire {
+    device: UsbDevice,
+}
+
+// The browser runs everything on one thread.
+unsafe impl Send for WebUsbWire {}
+
+impl WebUsbWire {
+    pub async fn open(device: UsbDevice) -> Result<Self, UsbError> {
+        if !device.opened() {
+            wait(device.open()).await?;
+        }
+        if device.configuration().is_none() {
+            wait(device.select_configuration(1)).await?;
+        }
+        wait(device.claim_interface(INTERFACE)).await?;
+        Ok(Self { device })
+    }
+}
+
+impl WireTx for WebUsbWire {
+    type Error = UsbError;
+
+    async fn send(&mut self, data: Vec<u8>) -> Result<(), UsbError> {
+        // JS values can't be held across the await, see `wait()`.
+        let transfer = wait(
+            self.device
+                .transfer_out_with_buffer_source(ENDPOINT, &Uint8Array::from(data.as_slice())),
+        );
+        transfer.await?;
+        Ok(())
+    }
+}
+
+impl WireRx for WebUsbWire {
+    type Error = UsbError;
+
+    /// Fails once the bot is unplugged, which stops the client.
+    async fn receive(&mut self) -> Result<Vec<u8>, UsbError> {
+        let result: UsbInTransferResult = wait(self.device.transfer_in(ENDPOINT, MAX_TRANSFER_LEN))
+            .await?
+            .unchecked_into();
+        if result.status() != UsbTransferStatus::Ok {
+            return Err(UsbError(format!("transfer failed: {:?}", result.status())));
+        }
+        let data = result
+            .data()
+            .ok_or_else(|| UsbError("transfer returned no data".to_string()))?;
+        Ok(Uint8Array::new_with_byte_offset_and_length(
+            &data.buffer(),
+            data.byte_offset() as u32,
+            data.byte_length() as u32,
+        )
+        .to_vec())
+    }
+}
+
+pub struct WebUsbSpawn;
+
+impl WireSpawn for WebUsbSpawn {
+    fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+        spawn_local(future);
+    }
+}
@@ -1,6 +1,7 @@
 import { createRouter, createWebHistory } from 'vue-router'
 import ExpressionView from '../views/ExpressionView.vue'
 import HomeView from '../views/HomeView.vue'
+import ScheduleView from '../views/ScheduleView.vue'
 import SettingsView from '../views/SettingsView.vue'
 
 const router = createRouter({
@@ -24,6 +25,11 @@ const router = createRouter({
       name: 'settings',
       component: SettingsView
     },
+    {
+      path: '/schedule',
+      name: 'schedule',
+      component: ScheduleView
+    },
     {
       path: '/expression/:id',
       name: 'expression',
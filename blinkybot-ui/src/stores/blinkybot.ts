@@ -1,12 +1,93 @@
 import { ref, computed } from 'vue'
 import { defineStore } from 'pinia'
-import init, { greet, BlinkyBotClient, ExpressionIndex, Expression } from 'blinkybot-ui-wasm';
+import init, {
+	greet,
+	AutoBrightness,
+	BehaviorEvents,
+	BlinkSync,
+	BlinkyBotClient,
+	BrightnessCurve,
+	BrightnessMode,
+	ButtonBindings,
+	ButtonEvents,
+	ClockConfig,
+	ConfigChange,
+	ConfigSetting,
+	ConnectionState,
+	DeviceInfo,
+	DisplayHealth,
+	DisplayInfo,
+	DisplayStats,
+	ExpressionIndex,
+	Expression,
+	FaceMode,
+	Friend,
+	FriendId,
+	Gaze,
+	Mood,
+	MoodStyle,
+	Peer,
+	Preset,
+	PresetEntry,
+	Schedule,
+	TransitionKind,
+	TransitionStyle,
+	WallTime,
+	pack_from_text
+} from 'blinkybot-ui-wasm';
 
-export { Expression, ExpressionIndex } from 'blinkybot-ui-wasm';
+export {
+	expression_slots,
+	pack_info,
+	pack_to_text,
+	preset_expression,
+	AutoBrightness,
+	BehaviorEvent,
+	BehaviorEventKind,
+	BlinkSync,
+	BrightnessCurve,
+	BrightnessMode,
+	ButtonAction,
+	ButtonBindings,
+	ButtonEvent,
+	ClockConfig,
+	ConfigChange,
+	ConfigSetting,
+	ConnectionState,
+	Gesture,
+	DisplayState,
+	ErrorKind,
+	Expression,
+	ExpressionIndex,
+	ExpressionSlot,
+	FaceMode,
+	Friend,
+	FriendId,
+	Gaze,
+	Mood,
+	MoodStyle,
+	PackInfo,
+	Preset,
+	PresetEntry,
+	Schedule,
+	ScheduleEntry,
+	ScheduledDisplay,
+	TransitionEffect,
+	TransitionKind,
+	TransitionStyle
+} from 'blinkybot-ui-wasm';
+
+export type { BlinkyBotError } from 'blinkybot-ui-wasm';
 
 export const useBlinkyBotStore = defineStore('blinkybot', {
 	state: (): BlinkyBot => {
-		return { wasmInitialized: false, client: null }
+		return {
+			wasmInitialized: false,
+			client: null,
+			connectionState: null,
+			deviceInfo: null,
+			configChange: null
+		}
 	},
 	getters: {
 		isConnected(): boolean {
@@ -14,18 +95,68 @@ export const useBlinkyBotStore = defineStore('blinkybot', {
 		}
 	},
 	actions: {
-		async connect() {
-			if (!this.wasmInitialized) {
-				await init();
-				this.wasmInitialized = true;
+		// Connects to the bot the user picks.  If `selector` is given, it must
+		// be the serial number or name of that bot.
+		async connect(selector?: string) {
+			await this.initWasm();
+			if (this.client !== null) {
+				return;
 			}
 
+			await this.attach(await new BlinkyBotClient(selector || undefined));
+		},
+
+		// Connects to a bot the user picked before without asking, if one
+		// is plugged in.
+		async connectAuthorized(selector?: string) {
+			await this.initWasm();
 			if (this.client !== null) {
 				return;
 			}
 
-			const client = await new BlinkyBotClient();
+			const client = await BlinkyBotClient.authorized(selector || undefined);
+			if (client !== undefined) {
+				await this.attach(client);
+			}
+		},
+
+		async initWasm() {
+			if (!this.wasmInitialized) {
+				await init();
+				this.wasmInitialized = true;
+			}
+		},
+
+		async attach(client: BlinkyBotClient) {
 			this.client = client;
+			this.connectionState = client.state;
+			client.set_connection_listener((state: ConnectionState) => {
+				this.connectionChanged(client, state);
+			});
+			this.deviceInfo = await client.get_device_info();
+			this.followConfig(client);
+		},
+
+		// The client reconnects by itself after the bot is unplugged or
+		// reset, but subscriptions have to be made again.
+		async connectionChanged(client: BlinkyBotClient, state: ConnectionState) {
+			this.connectionState = state;
+			if (state === ConnectionState.Connected) {
+				this.deviceInfo = await client.get_device_info();
+				this.followConfig(client);
+			}
+		},
+
+		// Keeps `configChange` up to date with settings changed by any
+		// client, so that views can refresh.
+		async followConfig(client: BlinkyBotClient) {
+			const changes = await client.subscribe_config();
+			for (let change = await changes.next(); change !== undefined; change = await changes.next()) {
+				if (change.setting === ConfigSetting.DeviceName) {
+					this.deviceInfo = await client.get_device_info();
+				}
+				this.configChange = change;
+			}
 		},
 
 		async disconnect() {
@@ -36,6 +167,8 @@ export const useBlinkyBotStore = defineStore('blinkybot', {
 			this.client.close();
 			await this.client.wait_closed();
 			this.client = null;
+			this.connectionState = null;
+			this.deviceInfo = null;
 		},
 
 		async ping(id: number): Promise<number> {
@@ -46,6 +179,14 @@ export const useBlinkyBotStore = defineStore('blinkybot', {
 			return await this.client.ping(1);
 		},
 
+		async set_device_name(name: string) {
+			if (this.client === null) {
+				return;
+			}
+			await this.client.set_device_name(name);
+			this.deviceInfo = await this.client.get_device_info();
+		},
+
 		async set_expression(index: ExpressionIndex, expression: Expression) {
 			if (this.client === null) {
 				return;
@@ -60,6 +201,21 @@ export const useBlinkyBotStore = defineStore('blinkybot', {
 			return await this.client.get_expression(index);
 		},
 
+		// Returns the bot's built-in expressions.
+		async list_presets(): Promise<PresetEntry[]> {
+			if (this.client === null) {
+				return [];
+			}
+			return await this.client.list_presets();
+		},
+
+		async apply_preset(index: ExpressionIndex, preset: Preset) {
+			if (this.client === null) {
+				return;
+			}
+			await this.client.apply_preset(index, preset);
+		},
+
 		async get_adc(): Promise<number> {
 			if (this.client === null) {
 				return 0x0;
@@ -79,11 +235,327 @@ export const useBlinkyBotStore = defineStore('blinkybot', {
 				return;
 			}
 			return await this.client.set_brightness(value);
+		},
+
+		async get_brightness_curve(): Promise<BrightnessCurve> {
+			if (this.client === null) {
+				return BrightnessCurve.Cie1931;
+			}
+			return await this.client.get_brightness_curve();
+		},
+
+		async set_brightness_curve(curve: BrightnessCurve) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_brightness_curve(curve);
+		},
+
+		async get_brightness_mode(): Promise<BrightnessMode> {
+			if (this.client === null) {
+				return BrightnessMode.Manual;
+			}
+			return await this.client.get_brightness_mode();
+		},
+
+		async set_brightness_mode(mode: BrightnessMode) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_brightness_mode(mode);
+		},
+
+		async get_auto_brightness(): Promise<AutoBrightness | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_auto_brightness();
+		},
+
+		async set_auto_brightness(config: AutoBrightness) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_auto_brightness(config);
+		},
+
+		async get_ambient_light(): Promise<number> {
+			if (this.client === null) {
+				return 0x0;
+			}
+			return await this.client.get_ambient_light();
+		},
+
+		async get_display_info(): Promise<DisplayInfo | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_display_info();
+		},
+
+		async get_display_stats(): Promise<DisplayStats | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_display_stats();
+		},
+
+		async get_display_health(): Promise<DisplayHealth | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_display_health();
+		},
+
+		async get_time(): Promise<WallTime | undefined> {
+			if (this.client === null) {
+				return undefined;
+			}
+			return await this.client.get_time();
+		},
+
+		async get_schedule(): Promise<Schedule> {
+			if (this.client === null) {
+				return new Schedule();
+			}
+			return await this.client.get_schedule();
+		},
+
+		async set_schedule(schedule: Schedule) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_schedule(schedule);
+		},
+
+		async get_face_mode(): Promise<FaceMode> {
+			if (this.client === null) {
+				return FaceMode.Expressions;
+			}
+			return await this.client.get_face_mode();
+		},
+
+		async set_face_mode(mode: FaceMode) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_face_mode(mode);
+		},
+
+		// Returns the bytecode of the script run in `FaceMode.Script`.
+		async get_script(): Promise<Uint8Array> {
+			if (this.client === null) {
+				return new Uint8Array();
+			}
+			return await this.client.get_script();
+		},
+
+		// Uploads script bytecode, as assembled by `blinkybot script`.
+		// Throws if the bot rejects it.
+		async set_script(code: Uint8Array) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_script(code);
+		},
+
+		// Starts recording requests to the bot, its responses and its events.
+		start_recording() {
+			if (this.client === null) {
+				return;
+			}
+			this.client.start_recording();
+		},
+
+		// Stops recording and returns the session as text, for replaying
+		// with `blinkybot replay`.
+		stop_recording(): string | undefined {
+			if (this.client === null) {
+				return undefined;
+			}
+			return this.client.stop_recording();
+		},
+
+		// Returns a pack named `name` of the bot's expressions, script,
+		// behavior and brightness, for saving to a file or `pack_to_text`.
+		async export_pack(name: string, author: string): Promise<Uint8Array | undefined> {
+			if (this.client === null) {
+				return undefined;
+			}
+			return await this.client.export_pack(name, author);
+		},
+
+		// Sets everything in a pack on the bot.  Throws if it isn't a pack
+		// or the bot rejects its script.
+		async import_pack(bytes: Uint8Array) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.import_pack(bytes);
+		},
+
+		// Returns the pack in a share link or its text.  Works before
+		// connecting, so links can be opened first.
+		async read_pack_link(text: string): Promise<Uint8Array> {
+			await this.initWasm();
+			return pack_from_text(text);
+		},
+
+		// Points the procedural face's eyes, or lets it look around by
+		// itself if `gaze` is undefined.
+		async set_gaze(gaze?: Gaze) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_gaze(gaze);
+		},
+
+		async get_mood(): Promise<Mood> {
+			if (this.client === null) {
+				return Mood.Idle;
+			}
+			return await this.client.get_mood();
+		},
+
+		// Holds the bot in `mood`, or lets it decide for itself again if
+		// `mood` is undefined.
+		async set_mood(mood?: Mood) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_mood(mood);
+		},
+
+		async get_mood_style(mood: Mood): Promise<MoodStyle | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_mood_style(mood);
+		},
+
+		async set_mood_style(mood: Mood, style: MoodStyle) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_mood_style(mood, style);
+		},
+
+		async get_transition(kind: TransitionKind): Promise<TransitionStyle | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_transition(kind);
+		},
+
+		async set_transition(kind: TransitionKind, style: TransitionStyle) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_transition(kind, style);
+		},
+
+		async get_button_bindings(button: number): Promise<ButtonBindings | undefined> {
+			if (this.client === null) {
+				return undefined;
+			}
+			return await this.client.get_button_bindings(button);
+		},
+
+		async set_button_bindings(button: number, bindings: ButtonBindings) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_button_bindings(button, bindings);
+		},
+
+		// Returns the friend the bot is facing, if it has said hello.
+		async get_peer(): Promise<Peer | undefined> {
+			if (this.client === null) {
+				return undefined;
+			}
+			return await this.client.get_peer();
+		},
+
+		async get_blink_sync(): Promise<BlinkSync | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_blink_sync();
+		},
+
+		async set_blink_sync(sync: BlinkSync) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_blink_sync(sync);
+		},
+
+		async get_clock_config(): Promise<ClockConfig | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.get_clock_config();
+		},
+
+		async set_clock_config(config: ClockConfig) {
+			if (this.client === null) {
+				return;
+			}
+			return await this.client.set_clock_config(config);
+		},
+
+		async list_friends(): Promise<FriendId[]> {
+			if (this.client === null) {
+				return [];
+			}
+			return await this.client.list_friends();
+		},
+
+		async get_friend(id: FriendId): Promise<Friend | undefined> {
+			if (this.client === null) {
+				return undefined;
+			}
+			return await this.client.get_friend(id);
+		},
+
+		// Adds `friend`, replacing any entry with the same ID.  Returns false
+		// if the friend table is full.
+		async add_friend(friend: Friend): Promise<boolean> {
+			if (this.client === null) {
+				return false;
+			}
+			return await this.client.add_friend(friend);
+		},
+
+		async remove_friend(id: FriendId): Promise<boolean> {
+			if (this.client === null) {
+				return false;
+			}
+			return await this.client.remove_friend(id);
+		},
+
+		async subscribe_buttons(): Promise<ButtonEvents | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.subscribe_buttons();
+		},
+
+		// Events for what the bot does, as it does it.
+		async subscribe_behavior(): Promise<BehaviorEvents | null> {
+			if (this.client === null) {
+				return null;
+			}
+			return await this.client.subscribe_behavior();
 		}
 	},
 })
 
 interface BlinkyBot {
 	client: BlinkyBotClient | null;
+	connectionState: ConnectionState | null;
+	deviceInfo: DeviceInfo | null;
 	wasmInitialized: boolean;
+	// Last setting changed by any client.
+	configChange: ConfigChange | null;
 }